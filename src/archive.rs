@@ -0,0 +1,400 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use zip::result::ZipError;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::config::ECompression;
+use crate::error::BackupError;
+
+/// A backup written into one file instead of a folder tree. It starts with the
+/// `backup_config.toml` of the backup and ends with its manifest.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EArchiveFormat {
+    Tar,
+    TarZst,
+    Zip,
+}
+
+impl EArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            EArchiveFormat::Tar => ".tar",
+            EArchiveFormat::TarZst => ".tar.zst",
+            EArchiveFormat::Zip => ".zip",
+        }
+    }
+
+    /// The format of an archive file by its name, `None` for folders and other files
+    pub fn of_file(path: &Path) -> Option<EArchiveFormat> {
+        if !path.is_file() {
+            return None;
+        }
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        [
+            EArchiveFormat::TarZst,
+            EArchiveFormat::Tar,
+            EArchiveFormat::Zip,
+        ]
+        .into_iter()
+        .find(|format| name.ends_with(format.extension()))
+    }
+}
+
+/// One file or folder of an archive, `name` is `/` separated and relative to the archive root
+pub struct SArchiveEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Where a symlink points, its entry has no contents
+    pub symlink: Option<String>,
+}
+
+pub enum EArchiveWriter {
+    Tar(tar::Builder<BufWriter<File>>),
+    TarZst(tar::Builder<zstd::Encoder<'static, BufWriter<File>>>),
+    Zip(Box<ZipWriter<BufWriter<File>>>),
+}
+
+impl EArchiveWriter {
+    /// `compression` sets the zstd level of a tar.zst, zip entries are deflated one by one
+    pub fn create(
+        path: &Path,
+        format: EArchiveFormat,
+        compression: ECompression,
+    ) -> io::Result<EArchiveWriter> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match format {
+            EArchiveFormat::Tar => EArchiveWriter::Tar(tar::Builder::new(file)),
+            EArchiveFormat::TarZst => {
+                let level = match compression {
+                    ECompression::Zstd(level) => level,
+                    _ => ECompression::DEFAULT_ZSTD_LEVEL,
+                };
+                EArchiveWriter::TarZst(tar::Builder::new(zstd::Encoder::new(file, level)?))
+            }
+            EArchiveFormat::Zip => EArchiveWriter::Zip(Box::new(ZipWriter::new(file))),
+        })
+    }
+
+    pub fn add_dir(&mut self, name: &str, modified: Option<SystemTime>) -> io::Result<()> {
+        let name = format!("{}/", name.trim_end_matches('/'));
+        match self {
+            EArchiveWriter::Tar(builder) => append_tar_dir(builder, &name, modified),
+            EArchiveWriter::TarZst(builder) => append_tar_dir(builder, &name, modified),
+            EArchiveWriter::Zip(writer) => writer
+                .add_directory(name, zip_options(modified, false, 0))
+                .map_err(io::Error::other),
+        }
+    }
+
+    pub fn add_symlink(
+        &mut self,
+        name: &str,
+        target: &str,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        match self {
+            EArchiveWriter::Tar(builder) => append_tar_symlink(builder, name, target, modified),
+            EArchiveWriter::TarZst(builder) => append_tar_symlink(builder, name, target, modified),
+            EArchiveWriter::Zip(writer) => writer
+                .add_symlink(name, target, zip_options(modified, false, 0))
+                .map_err(io::Error::other),
+        }
+    }
+
+    /// Fails when `contents` does not hold exactly `size` bytes, a tar header is written first
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        size: u64,
+        modified: Option<SystemTime>,
+        deflate: bool,
+        contents: &mut dyn Read,
+    ) -> io::Result<()> {
+        let mut contents = SExactReader {
+            inner: contents,
+            left: size,
+        };
+        match self {
+            EArchiveWriter::Tar(builder) => {
+                append_tar_file(builder, name, size, modified, &mut contents)?
+            }
+            EArchiveWriter::TarZst(builder) => {
+                append_tar_file(builder, name, size, modified, &mut contents)?
+            }
+            EArchiveWriter::Zip(writer) => {
+                writer
+                    .start_file(name, zip_options(modified, deflate, size))
+                    .map_err(io::Error::other)?;
+                io::copy(&mut contents, writer)?;
+            }
+        }
+        let mut rest = [0u8; 1];
+        if contents.left > 0 || contents.inner.read(&mut rest)? > 0 {
+            return Err(io::Error::other("the file changed while it was archived"));
+        }
+        Ok(())
+    }
+
+    pub fn add_bytes(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        self.add_file(
+            name,
+            contents.len() as u64,
+            Some(SystemTime::now()),
+            true,
+            &mut &contents[..],
+        )
+    }
+
+    /// Writes the end of the archive and flushes it to disk
+    pub fn finish(self) -> io::Result<()> {
+        let file = match self {
+            EArchiveWriter::Tar(builder) => builder.into_inner()?,
+            EArchiveWriter::TarZst(builder) => builder.into_inner()?.finish()?,
+            EArchiveWriter::Zip(writer) => writer.finish().map_err(io::Error::other)?,
+        };
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()
+    }
+}
+
+/// Entries of an archive in the order they are stored, without their contents
+pub fn list(path: &Path, format: EArchiveFormat) -> io::Result<Vec<SArchiveEntry>> {
+    let mut entries = Vec::new();
+    match format {
+        EArchiveFormat::Tar => {
+            let mut archive = tar::Archive::new(File::open(path)?);
+            for entry in archive.entries_with_seek()? {
+                entries.push(tar_entry(&entry?)?);
+            }
+        }
+        EArchiveFormat::TarZst => {
+            let decoder = zstd::Decoder::new(File::open(path)?)?;
+            for entry in tar::Archive::new(decoder).entries()? {
+                entries.push(tar_entry(&entry?)?);
+            }
+        }
+        EArchiveFormat::Zip => {
+            let mut archive =
+                ZipArchive::new(BufReader::new(File::open(path)?)).map_err(io::Error::other)?;
+            for i in 0..archive.len() {
+                // Symlinks are always stored, so the raw contents are the target
+                let mut file = archive.by_index_raw(i).map_err(io::Error::other)?;
+                entries.push(zip_entry(&mut file)?);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Calls `visit` with every entry in the order they are stored and a reader of its contents
+pub fn read(
+    path: &Path,
+    format: EArchiveFormat,
+    mut visit: impl FnMut(&SArchiveEntry, &mut dyn Read) -> Result<(), BackupError>,
+) -> Result<(), BackupError> {
+    let read_error = || BackupError::io("read archive", path);
+    let file = File::open(path).map_err(read_error())?;
+    match format {
+        EArchiveFormat::Tar => {
+            let mut archive = tar::Archive::new(BufReader::new(file));
+            for entry in archive.entries().map_err(read_error())? {
+                let mut entry = entry.map_err(read_error())?;
+                visit(&tar_entry(&entry).map_err(read_error())?, &mut entry)?;
+            }
+        }
+        EArchiveFormat::TarZst => {
+            let decoder = zstd::Decoder::new(file).map_err(read_error())?;
+            for entry in tar::Archive::new(decoder).entries().map_err(read_error())? {
+                let mut entry = entry.map_err(read_error())?;
+                visit(&tar_entry(&entry).map_err(read_error())?, &mut entry)?;
+            }
+        }
+        EArchiveFormat::Zip => {
+            let mut archive = ZipArchive::new(BufReader::new(file))
+                .map_err(|e| read_error()(io::Error::other(e)))?;
+            for i in 0..archive.len() {
+                let mut file = archive
+                    .by_index(i)
+                    .map_err(|e| read_error()(io::Error::other(e)))?;
+                let entry = zip_entry(&mut file).map_err(read_error())?;
+                visit(&entry, &mut file)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The contents of the entry called `name`, `None` when the archive has none.
+/// A tar is only read up to the entry, which makes the config at its start quick to get.
+pub fn read_file(
+    path: &Path,
+    format: EArchiveFormat,
+    name: &str,
+) -> Result<Option<Vec<u8>>, BackupError> {
+    let read_error = || BackupError::io("read archive", path);
+    let file = File::open(path).map_err(read_error())?;
+    let mut contents = Vec::new();
+    let reader: Box<dyn Read> = match format {
+        EArchiveFormat::Tar => Box::new(BufReader::new(file)),
+        EArchiveFormat::TarZst => Box::new(zstd::Decoder::new(file).map_err(read_error())?),
+        EArchiveFormat::Zip => {
+            let mut archive = ZipArchive::new(BufReader::new(file))
+                .map_err(|e| read_error()(io::Error::other(e)))?;
+            let mut file = match archive.by_name(name) {
+                Ok(file) => file,
+                Err(ZipError::FileNotFound) => return Ok(None),
+                Err(e) => return Err(read_error()(io::Error::other(e))),
+            };
+            file.read_to_end(&mut contents).map_err(read_error())?;
+            return Ok(Some(contents));
+        }
+    };
+    for entry in tar::Archive::new(reader).entries().map_err(read_error())? {
+        let mut entry = entry.map_err(read_error())?;
+        if tar_entry(&entry).map_err(read_error())?.name == name {
+            entry.read_to_end(&mut contents).map_err(read_error())?;
+            return Ok(Some(contents));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads at most `left` bytes, so a file that grows while it is archived does not
+/// overrun its tar header
+struct SExactReader<'a> {
+    inner: &'a mut dyn Read,
+    left: u64,
+}
+
+impl Read for SExactReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf.len().min(self.left.min(usize::MAX as u64) as usize);
+        let read = self.inner.read(&mut buf[..max])?;
+        self.left -= read as u64;
+        Ok(read)
+    }
+}
+
+fn append_tar_dir<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    modified: Option<SystemTime>,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_mtime(unix_seconds(modified));
+    header.set_size(0);
+    builder.append_data(&mut header, name, io::empty())
+}
+
+fn append_tar_file<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    size: u64,
+    modified: Option<SystemTime>,
+    contents: &mut dyn Read,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(0o644);
+    header.set_mtime(unix_seconds(modified));
+    header.set_size(size);
+    builder.append_data(&mut header, name, contents)
+}
+
+fn append_tar_symlink<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    target: &str,
+    modified: Option<SystemTime>,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_mode(0o777);
+    header.set_mtime(unix_seconds(modified));
+    header.set_size(0);
+    // `append_link` normalizes the target, which turns `/` into `//`. Only targets too long
+    // for the header go through it, in an extension entry.
+    if target.len() < 100 {
+        header.set_link_name_literal(target)?;
+        builder.append_data(&mut header, name, io::empty())
+    } else {
+        builder.append_link(&mut header, name, target)
+    }
+}
+
+fn tar_entry<R: Read>(entry: &tar::Entry<'_, R>) -> io::Result<SArchiveEntry> {
+    let header = entry.header();
+    Ok(SArchiveEntry {
+        name: entry
+            .path()?
+            .to_string_lossy()
+            .trim_end_matches('/')
+            .to_string(),
+        is_dir: header.entry_type().is_dir(),
+        size: entry.size(),
+        modified: header
+            .mtime()
+            .ok()
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds)),
+        symlink: match header.entry_type() {
+            tar::EntryType::Symlink => entry
+                .link_name()?
+                .map(|target| target.to_string_lossy().to_string()),
+            _ => None,
+        },
+    })
+}
+
+/// Reads the target of a symlink, which leaves nothing else to read of `file`
+fn zip_entry<R: Read>(file: &mut zip::read::ZipFile<'_, R>) -> io::Result<SArchiveEntry> {
+    let symlink = if file.is_symlink() {
+        let mut target = String::new();
+        file.read_to_string(&mut target)?;
+        Some(target)
+    } else {
+        None
+    };
+    Ok(SArchiveEntry {
+        name: file.name().trim_end_matches('/').to_string(),
+        is_dir: file.is_dir(),
+        size: file.size(),
+        modified: file
+            .last_modified()
+            .and_then(|time| NaiveDateTime::try_from(time).ok())
+            .and_then(|time| Local.from_local_datetime(&time).earliest())
+            .map(SystemTime::from),
+        symlink,
+    })
+}
+
+fn zip_options(modified: Option<SystemTime>, deflate: bool, size: u64) -> SimpleFileOptions {
+    let method = if deflate {
+        CompressionMethod::Deflated
+    } else {
+        CompressionMethod::Stored
+    };
+    let mut options = SimpleFileOptions::default()
+        .compression_method(method)
+        .large_file(size >= u32::MAX as u64);
+    // Zip times are local and have no time zone
+    let modified = modified
+        .map(|time| DateTime::<Local>::from(time).naive_local())
+        .and_then(|time| zip::DateTime::try_from(time).ok());
+    if let Some(modified) = modified {
+        options = options.last_modified_time(modified);
+    }
+    options
+}
+
+fn unix_seconds(time: Option<SystemTime>) -> u64 {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
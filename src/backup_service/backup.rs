@@ -0,0 +1,1463 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+
+use crate::archive::{EArchiveFormat, EArchiveWriter};
+use crate::catalog::{
+    expand_name, ESnapshotStatus, SCatalog, SCommitMarker, SSnapshotInfo, COMMIT_MARKER,
+};
+use crate::config::{ECompression, EElementType, EIncrementalMode, SBackupConfig};
+use crate::encryption::{self, EKeySource, SEncryptionHeader, SKey, ENCRYPTION_FILE};
+use crate::error::BackupError;
+use crate::filter::SPathFilter;
+use crate::journal::{SJournal, SJournalEntry, SJournalHeader, JOURNAL_FILE};
+use crate::manifest::{self, SManifest, SManifestEntry, SManifestFolder, MANIFEST_FILE};
+use crate::parallel;
+use crate::plan::{EPlanAction, SPlan};
+use crate::progress::{EProgressEvent, ProgressObserver, SProgress};
+use crate::repository::{
+    EDestinationFormat, ETreeEntryKind, SRepository, SSnapshotObject, STreeBuilder, STreeEntry,
+};
+use crate::storage::{self, SLocalStorage, StorageBackend};
+use crate::throttle::{self, SThrottledObserver};
+use crate::ui::backup::SBackupUI;
+
+use super::copy::copy_items;
+use super::scan::{read_found, scan_dir, scan_file, set_compression, SScan};
+use super::{
+    hardlink_sources, hash_file, item_note, link_hash, new_progress, SCopyItem, SHashingReader,
+    BACKUP_CONFIG_FILE, INCOMPLETE_MARKER,
+};
+
+/// A backup is written under its name with a leading dot and this suffix until it is complete
+const STAGING_SUFFIX: &str = ".staging";
+
+/// Where unchanged files are looked up during an incremental backup
+pub struct SIncremental {
+    /// Earlier backups, newest first. Skip mode leaves files out,
+    /// so the newest copy of a file can be in any of them.
+    pub previous: Vec<PathBuf>,
+    /// Manifest entries of each of `previous`, empty for backups without a manifest
+    manifests: Vec<HashMap<String, SManifestEntry>>,
+    current: PathBuf,
+    pub mode: EIncrementalMode,
+    compare_hash: bool,
+}
+
+/// An unchanged file found in an earlier backup
+pub struct SPreviousCopy {
+    pub path: PathBuf,
+    pub backup: usize,
+}
+
+impl SIncremental {
+    fn new(
+        previous: Vec<PathBuf>,
+        current: PathBuf,
+        mode: EIncrementalMode,
+        compare_hash: bool,
+        key: Option<&SKey>,
+    ) -> SIncremental {
+        let manifests = previous
+            .iter()
+            .map(|backup| {
+                SManifest::load(backup, key)
+                    .map(SManifest::by_path)
+                    .unwrap_or_default()
+            })
+            .collect();
+        SIncremental {
+            previous,
+            manifests,
+            current,
+            mode,
+            compare_hash,
+        }
+    }
+
+    /// The previous copy of `item` if the source did not change since it was made
+    pub fn unchanged_copy(&self, item: &SCopyItem) -> Result<Option<SPreviousCopy>, BackupError> {
+        let Ok(relative) = item.to.strip_prefix(&self.current) else {
+            return Ok(None);
+        };
+        let relative_path = manifest::relative_path(&item.to, &self.current).unwrap_or_default();
+        let Some((backup, previous, metadata)) =
+            self.previous.iter().enumerate().find_map(|(i, backup)| {
+                let previous = backup.join(relative);
+                fs::symlink_metadata(&previous)
+                    .ok()
+                    .map(|metadata| (i, previous, metadata))
+            })
+        else {
+            return Ok(None);
+        };
+
+        // A stored copy differs in size from the file, the manifest has the original size
+        let size = self.manifests[backup]
+            .get(&relative_path)
+            .map_or(metadata.len(), |entry| entry.size);
+        if !metadata.is_file()
+            || size != item.size
+            || item.modified.is_none()
+            || metadata.modified().ok() != item.modified
+        {
+            return Ok(None);
+        }
+        let copy = SPreviousCopy {
+            path: previous,
+            backup,
+        };
+        if self.compare_hash
+            && hash_file(&item.from)?.to_hex().as_str() != self.previous_hash(&copy)?
+        {
+            return Ok(None);
+        }
+        Ok(Some(copy))
+    }
+
+    /// Taken from the earlier backup's manifest when it has one, so unchanged files are not read
+    pub fn previous_hash(&self, copy: &SPreviousCopy) -> Result<String, BackupError> {
+        let known = manifest::relative_path(&copy.path, &self.previous[copy.backup])
+            .and_then(|relative| self.manifests[copy.backup].get(&relative));
+        match known {
+            Some(entry) => Ok(entry.hash.clone()),
+            None => Ok(hash_file(&copy.path)?.to_hex().to_string()),
+        }
+    }
+}
+
+/// Collects what a backup copied into its manifest
+pub struct SManifestBuilder {
+    root: PathBuf,
+    pub manifest: SManifest,
+    /// Manifest paths of the first files of hard link groups once added, by source
+    hardlinks: HashMap<PathBuf, Option<String>>,
+    /// Of a folder backup, gets every file added to the manifest
+    pub journal: Option<SJournal>,
+}
+
+impl SManifestBuilder {
+    pub fn new(root: &Path, items: &[SCopyItem]) -> SManifestBuilder {
+        SManifestBuilder {
+            root: root.to_path_buf(),
+            manifest: SManifest::new(),
+            hardlinks: hardlink_sources(items)
+                .into_iter()
+                .map(|from| (from.to_path_buf(), None))
+                .collect(),
+            journal: None,
+        }
+    }
+
+    /// The hash and snapshot of an item the interrupted run this one continues already
+    /// copied, when neither the source nor the copy changed since
+    pub fn resumed(&self, item: &SCopyItem) -> Option<(String, Option<String>)> {
+        let path = manifest::relative_path(&item.to, &self.root)?;
+        let entry = self.journal.as_ref()?.done(&path)?;
+        is_resumed(entry, item, &item.to).then(|| (entry.hash.clone(), entry.snapshot.clone()))
+    }
+
+    /// Writes what was added since the last sync into the journal, see `SJournal::sync`
+    pub fn sync_journal(&mut self, force: bool) -> Result<(), BackupError> {
+        match &mut self.journal {
+            Some(journal) => journal.sync(force),
+            None => Ok(()),
+        }
+    }
+
+    pub fn add(&mut self, item: &SCopyItem, hash: String, snapshot: Option<String>) {
+        let Some(path) = manifest::relative_path(&item.to, &self.root) else {
+            return;
+        };
+        let hardlink = item
+            .hardlink
+            .as_ref()
+            .and_then(|first| self.hardlinks.get(first).cloned().flatten());
+        if let Some(first) = self.hardlinks.get_mut(&item.from) {
+            *first = Some(path.clone());
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.add(
+                &item.to,
+                SJournalEntry {
+                    path: path.clone(),
+                    source: item.from.to_string_lossy().to_string(),
+                    size: item.size,
+                    modified: item.modified.map(DateTime::from),
+                    hash: hash.clone(),
+                    snapshot: snapshot.clone(),
+                },
+            );
+        }
+        self.manifest.files.push(SManifestEntry {
+            path,
+            source: item.from.to_string_lossy().to_string(),
+            size: item.size,
+            modified: item.modified.map(DateTime::from),
+            hash,
+            snapshot,
+            compression: item.write_as.compression,
+            metadata: item.metadata.clone(),
+            symlink: item
+                .symlink
+                .as_ref()
+                .map(|target| target.to_string_lossy().to_string()),
+            hardlink,
+            sparse: item.sparse,
+        });
+    }
+
+    pub fn add_folder(&mut self, item: &SCopyItem) {
+        let Some(path) = manifest::relative_path(&item.to, &self.root) else {
+            return;
+        };
+        self.manifest.folders.push(SManifestFolder {
+            path,
+            source: item.from.to_string_lossy().to_string(),
+            modified: item.modified.map(DateTime::from),
+            metadata: item.metadata.clone(),
+        });
+    }
+}
+
+/// Where a backup is written
+struct SBackupTarget<'a> {
+    destination: &'a Path,
+    storage: Arc<dyn StorageBackend>,
+    catalog: &'a SCatalog,
+    backup_folder: PathBuf,
+    /// Set when the destination is encrypted
+    key: Option<SKey>,
+    /// Continue the interrupted backup in the staging folder instead of starting over
+    resume: bool,
+}
+
+/// Copies every element into a new folder in the destination and records it in the catalog
+pub fn backup(
+    config: &SBackupConfig,
+    details: &SBackupUI,
+    observer: &mut dyn ProgressObserver,
+) -> Result<SSnapshotInfo, BackupError> {
+    throttle::lower_priority(config.idle_io, config.nice)?;
+    let destination = Path::new(&details.folder_path);
+    let observer = &mut SThrottledObserver::new(
+        observer,
+        config.bandwidth_limit_for(destination, details.bandwidth_limit),
+    );
+
+    let SBackupSetup {
+        time,
+        profile,
+        folder_name,
+        archive_format,
+        mut catalog,
+        storage,
+    } = backup_setup(config, details)?;
+    let backup_folder = destination.join(&folder_name);
+    let key = destination_key(destination, &*storage, &catalog, details.key.as_ref(), true)?;
+    let encrypted = key.is_some();
+
+    let scan = scan_elements(config, details, &backup_folder)?;
+    let mut items = scan.items;
+    for item in &mut items {
+        item.write_as.key = key.clone();
+    }
+
+    let target = SBackupTarget {
+        destination,
+        storage: storage.clone(),
+        catalog: &catalog,
+        backup_folder,
+        key,
+        resume: details.resume.is_some(),
+    };
+    let mut progress = new_progress(&items);
+    let to_repository =
+        SRepository::is_repository(&*storage) || details.format == EDestinationFormat::Repository;
+    let result = if let Some(format) = archive_format {
+        backup_to_archive(
+            config,
+            &target,
+            format,
+            details.compression,
+            &mut items,
+            &mut progress,
+            observer,
+        )?
+    } else if to_repository {
+        backup_to_repository(config, &target, &items, &mut progress, observer)?
+    } else {
+        backup_to_folder(config, &target, &mut items, &mut progress, observer)?
+    };
+    // A failed archive is removed and a failed repository backup saves no snapshot, only
+    // the staging folder of a folder backup is left to resume
+    let result = match result {
+        Err(e) if archive_format.is_some() || to_repository => return Err(e),
+        result => result,
+    };
+
+    let snapshot = SSnapshotInfo {
+        name: folder_name,
+        profile,
+        time,
+        source_config: source_config(config),
+        files: progress.files_done,
+        size: progress.bytes_done,
+        status: match result {
+            Ok(()) => ESnapshotStatus::Complete,
+            Err(_) => ESnapshotStatus::Incomplete,
+        },
+        skipped: scan
+            .skipped
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect(),
+    };
+    // The catalog is read without the key, so an encrypted destination keeps the paths of
+    // the sources out of it
+    let mut recorded = snapshot.clone();
+    if encrypted {
+        recorded.source_config.clear();
+        recorded.skipped.clear();
+    }
+    catalog.record(recorded);
+    catalog.save(&*storage)?;
+
+    result.map(|()| snapshot)
+}
+
+/// Whether the journaled copy of `item` at `stored` can be kept: neither the source nor
+/// the copy changed since
+fn is_resumed(entry: &SJournalEntry, item: &SCopyItem, stored: &Path) -> bool {
+    if entry.source != item.from.to_string_lossy()
+        || entry.size != item.size
+        || entry.modified != item.modified.map(DateTime::from)
+    {
+        return false;
+    }
+    // A file skipped in favour of an earlier backup has no copy in this one
+    if entry.snapshot.is_some() {
+        return true;
+    }
+    fs::symlink_metadata(stored).is_ok_and(|stored| match item.symlink {
+        Some(_) => stored.is_symlink(),
+        None => stored.is_file() && stored.modified().ok() == item.modified,
+    })
+}
+
+/// How the catalog and journals name the config a backup was made from
+fn source_config(config: &SBackupConfig) -> String {
+    fs::canonicalize(&config.path)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|_| config.path.clone())
+}
+
+/// A folder backup of the same config into the same destination that was interrupted
+pub struct SResumableBackup {
+    pub name: String,
+    pub time: DateTime<Local>,
+    /// Copied before it was interrupted
+    pub files: usize,
+    pub size: u64,
+}
+
+/// The newest interrupted folder backup `backup` can continue when `details.resume` names it.
+/// Archives and repositories are not resumed, a repository reuses the chunks stored anyway.
+pub fn resumable_backup(
+    config: &SBackupConfig,
+    details: &SBackupUI,
+) -> Result<Option<SResumableBackup>, BackupError> {
+    let destination = Path::new(&details.folder_path);
+    if details.format != EDestinationFormat::Folder {
+        return Ok(None);
+    }
+    let storage = SLocalStorage::new(destination);
+    if SRepository::is_repository(&storage) {
+        return Ok(None);
+    }
+    let catalog = SCatalog::load_from(&storage)?;
+    let key = destination_key(destination, &storage, &catalog, details.key.as_ref(), false)?;
+    let Ok(entries) = fs::read_dir(destination) else {
+        return Ok(None);
+    };
+    let profile = config.profile_name();
+    let source_config = source_config(config);
+    let mut found: Option<SResumableBackup> = None;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !is_staging(&path) || !path.is_dir() {
+            continue;
+        }
+        // Journals sealed with another key or cut off before their header are not resumed
+        let Ok(Some((header, files))) = SJournal::load(&path, key.as_ref()) else {
+            continue;
+        };
+        if header.profile != profile
+            || header.source_config != source_config
+            || staging_path(destination, &header.name) != path
+            || found.as_ref().is_some_and(|found| found.time > header.time)
+        {
+            continue;
+        }
+        found = Some(SResumableBackup {
+            name: header.name,
+            time: header.time,
+            files: files.len(),
+            size: files.iter().map(|file| file.size).sum(),
+        });
+    }
+    Ok(found)
+}
+
+/// Starts over instead of resuming: removes the interrupted backup and its catalog entry
+pub fn discard_resumable(details: &SBackupUI, name: &str) -> Result<(), BackupError> {
+    let destination = Path::new(&details.folder_path);
+    let staging = staging_path(destination, name);
+    if staging.is_dir() {
+        fs::remove_dir_all(&staging).map_err(BackupError::io("remove staging folder", &staging))?;
+    }
+    let storage = SLocalStorage::new(destination);
+    let mut catalog = SCatalog::load_from(&storage)?;
+    let known = catalog
+        .snapshots
+        .iter()
+        .any(|snapshot| snapshot.name == name && snapshot.status == ESnapshotStatus::Incomplete);
+    if known && !destination.join(name).exists() {
+        catalog.remove(name);
+        catalog.save(&storage)?;
+    }
+    Ok(())
+}
+
+/// Where a backup goes, worked out before anything is written
+struct SBackupSetup {
+    time: DateTime<Local>,
+    profile: String,
+    /// Of the backup folder or archive inside the destination
+    folder_name: String,
+    archive_format: Option<EArchiveFormat>,
+    catalog: SCatalog,
+    storage: Arc<dyn StorageBackend>,
+}
+
+fn backup_setup(config: &SBackupConfig, details: &SBackupUI) -> Result<SBackupSetup, BackupError> {
+    let time = Local::now();
+    let profile = config.profile_name();
+    let folder_name = match &details.resume {
+        Some(name) => name.clone(),
+        None => expand_name(&details.folder_name, &profile, &time)?,
+    };
+    let destination = Path::new(&details.folder_path);
+    let storage = storage::open(destination);
+    let is_repository = SRepository::is_repository(&*storage);
+    let archive_format = match details.format {
+        EDestinationFormat::Archive(format) if !is_repository => Some(format),
+        _ => None,
+    };
+    // An archive is a snapshot of its own, named like a folder plus its extension
+    let folder_name = match archive_format {
+        Some(format) => format!("{}{}", folder_name, format.extension()),
+        None => folder_name,
+    };
+    let catalog = SCatalog::load_from(&*storage)?;
+    if archive_format.is_some()
+        && (details.key.is_some() || SEncryptionHeader::load_from(&*storage)?.is_some())
+    {
+        return Err(BackupError::invalid_path(
+            "write an archive into an encrypted destination",
+            destination,
+        ));
+    }
+    Ok(SBackupSetup {
+        time,
+        profile,
+        folder_name,
+        archive_format,
+        catalog,
+        storage,
+    })
+}
+
+/// Every file and folder of the elements with the compression each one is stored with
+fn scan_elements(
+    config: &SBackupConfig,
+    details: &SBackupUI,
+    backup_folder: &Path,
+) -> Result<SScan, BackupError> {
+    let threads = parallel::threads(config.threads);
+    let mut scan = SScan::new(config.follow_symlinks, threads);
+    for element in &config.elements {
+        let element_name = Path::new(&element.path)
+            .file_name()
+            .ok_or_else(|| BackupError::invalid_path("get file name", &element.path))?;
+        let destination_path = backup_folder.join(element_name);
+        let first = scan.items.len();
+        if element.content_type == EElementType::Folder {
+            let mut filter = SPathFilter::new(config, element)?;
+            scan_dir(
+                Path::new(&element.path),
+                &destination_path,
+                Some(&mut filter),
+                &mut scan,
+            )?;
+        } else {
+            scan_file(Path::new(&element.path), &destination_path, &mut scan);
+        }
+        read_found(&mut scan)?;
+        let compression = element.compression.unwrap_or(details.compression);
+        set_compression(&mut scan.items[first..], compression, threads)?;
+    }
+    Ok(scan)
+}
+
+/// What `backup` would copy, skip or write over, found the same way without writing anything
+pub fn backup_plan(config: &SBackupConfig, details: &SBackupUI) -> Result<SPlan, BackupError> {
+    let SBackupSetup {
+        folder_name,
+        archive_format,
+        catalog,
+        storage,
+        ..
+    } = backup_setup(config, details)?;
+    let destination = Path::new(&details.folder_path);
+    let backup_folder = destination.join(&folder_name);
+    let key = destination_key(
+        destination,
+        &*storage,
+        &catalog,
+        details.key.as_ref(),
+        false,
+    )?;
+    let mut scan = scan_elements(config, details, &backup_folder)?;
+
+    let mut plan = SPlan::new(format!("Backup to {}", backup_folder.display()));
+    if archive_format.is_some() {
+        if let Ok(existing) = fs::metadata(&backup_folder) {
+            plan.push(
+                EPlanAction::Overwrite,
+                &backup_folder,
+                existing.len(),
+                "archive replaced",
+            );
+        }
+        for item in scan.items.iter().filter(|item| !item.is_dir) {
+            plan.push(EPlanAction::Copy, &item.from, item.size, item_note(item));
+        }
+    } else if SRepository::is_repository(&*storage)
+        || details.format == EDestinationFormat::Repository
+    {
+        // A repository that is not there yet has no chunks to reuse
+        let repository = if SRepository::is_repository(&*storage) {
+            let repository = SRepository::open(storage.clone(), key)?;
+            let previous = previous_snapshot_files(config, &repository, &catalog, &folder_name)?;
+            Some((repository, previous))
+        } else {
+            check_new_repository(destination, &catalog)?;
+            None
+        };
+        for item in scan.items.iter().filter(|item| !item.is_dir) {
+            let unchanged = repository.as_ref().is_some_and(|(repository, previous)| {
+                item.symlink.is_none()
+                    && item.hardlink.is_none()
+                    && manifest::relative_path(&item.to, &backup_folder).is_some_and(|relative| {
+                        unchanged_entry(repository, previous, &relative, item, config.compare_hash)
+                            .is_some()
+                    })
+            });
+            if unchanged {
+                plan.push(
+                    EPlanAction::Skip,
+                    &item.from,
+                    item.size,
+                    "unchanged, chunks reused",
+                );
+            } else {
+                plan.push(EPlanAction::Copy, &item.from, item.size, item_note(item));
+            }
+        }
+    } else {
+        add_compression_extensions(&mut scan.items);
+        let profile = config.profile_name();
+        let incremental = folder_incremental(
+            config,
+            destination,
+            &catalog,
+            &profile,
+            &backup_folder,
+            key.as_ref(),
+        );
+        let staging = staging_path(destination, &folder_name);
+        let journaled: HashMap<String, SJournalEntry> = match details.resume {
+            Some(_) => SJournal::load(&staging, key.as_ref())?
+                .map(|(_, files)| {
+                    files
+                        .into_iter()
+                        .map(|file| (file.path.clone(), file))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            None => HashMap::new(),
+        };
+        for item in scan.items.iter().filter(|item| !item.is_dir) {
+            let resumed = manifest::relative_path(&item.to, &backup_folder).is_some_and(|path| {
+                journaled
+                    .get(&path)
+                    .is_some_and(|entry| is_resumed(entry, item, &staging.join(&path)))
+            });
+            if resumed {
+                plan.push(
+                    EPlanAction::Skip,
+                    &item.from,
+                    item.size,
+                    "copied before the backup was interrupted",
+                );
+                continue;
+            }
+            let previous = match &incremental {
+                Some(incremental) if item.symlink.is_none() && item.hardlink.is_none() => {
+                    incremental
+                        .unchanged_copy(item)?
+                        .map(|previous| (incremental, previous))
+                }
+                _ => None,
+            };
+            if let Some((incremental, previous)) = previous {
+                let name = incremental.previous[previous.backup]
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                let note = match incremental.mode {
+                    EIncrementalMode::Skip => format!("unchanged, kept in {}", name),
+                    _ => format!("unchanged, hard-linked from {}", name),
+                };
+                plan.push(EPlanAction::Skip, &item.from, item.size, note);
+            } else if fs::symlink_metadata(&item.to).is_ok() {
+                plan.push(
+                    EPlanAction::Overwrite,
+                    &item.from,
+                    item.size,
+                    item_note(item),
+                );
+            } else {
+                plan.push(EPlanAction::Copy, &item.from, item.size, item_note(item));
+            }
+        }
+    }
+    for path in &scan.skipped {
+        plan.push(EPlanAction::Skip, path, 0, "FIFO, socket or device node");
+    }
+    Ok(plan)
+}
+
+/// Returns the outer error when nothing was written yet, the inner one marks the backup incomplete
+fn backup_to_folder(
+    config: &SBackupConfig,
+    target: &SBackupTarget,
+    items: &mut [SCopyItem],
+    progress: &mut SProgress,
+    observer: &mut dyn ProgressObserver,
+) -> Result<Result<(), BackupError>, BackupError> {
+    let SBackupTarget {
+        destination,
+        catalog,
+        backup_folder,
+        key,
+        resume,
+        ..
+    } = target;
+    let key = key.as_ref();
+    let profile = config.profile_name();
+    let folder_name = backup_folder
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // Everything is written into the staging folder, which only takes the name of the
+    // backup once it is complete and on the disk
+    let staging = staging_path(destination, &folder_name);
+    for item in items.iter_mut() {
+        if let Ok(relative) = item.to.strip_prefix(backup_folder) {
+            item.to = staging.join(relative);
+        }
+    }
+    add_compression_extensions(items);
+    let incremental = folder_incremental(config, destination, catalog, &profile, &staging, key);
+
+    let journal = match resume {
+        true => SJournal::resume(&staging, key)?,
+        false => None,
+    };
+    if journal.is_some() {
+        // The run continued wrote them when it stopped
+        for marker in [INCOMPLETE_MARKER, COMMIT_MARKER] {
+            let path = staging.join(marker);
+            if path.exists() {
+                fs::remove_file(&path).map_err(BackupError::io("remove marker", &path))?;
+            }
+        }
+    } else if staging.exists() {
+        // Left by a run that is not continued
+        fs::remove_dir_all(&staging).map_err(BackupError::io("remove staging folder", &staging))?;
+    }
+    fs::create_dir_all(&staging).map_err(BackupError::io("create staging folder", &staging))?;
+
+    // Each backup carries the header so it can be restored on its own
+    if key.is_some() {
+        let header = destination.join(ENCRYPTION_FILE);
+        fs::copy(&header, staging.join(ENCRYPTION_FILE))
+            .map_err(BackupError::io("copy encryption header", &header))?;
+    }
+    config.save_to_backup(&staging, key)?;
+    let journal = match journal {
+        Some(journal) => journal,
+        None => {
+            let header = SJournalHeader {
+                name: folder_name.clone(),
+                profile: profile.clone(),
+                source_config: source_config(config),
+                time: Local::now(),
+            };
+            SJournal::create(&staging, &header, key)?
+        }
+    };
+
+    let mut manifest = SManifestBuilder::new(&staging, items);
+    manifest.journal = Some(journal);
+    let result = copy_items(
+        items,
+        progress,
+        false,
+        incremental.as_ref(),
+        Some(&mut manifest),
+        observer,
+        parallel::threads(config.threads),
+    );
+    // What was copied before a failure is kept for a rerun to continue from
+    let journaled = manifest.sync_journal(true);
+    manifest.journal = None;
+    // A failed backup still gets the manifest of what it did copy
+    let saved = manifest.manifest.save(&staging, key);
+    let result = result
+        .and(journaled)
+        .and(saved)
+        .and_then(|()| {
+            let path = staging.join(JOURNAL_FILE);
+            fs::remove_file(&path).map_err(BackupError::io("remove journal", &path))
+        })
+        .and_then(|()| sync_folder(&staging));
+    let status = match &result {
+        Ok(()) => ESnapshotStatus::Complete,
+        Err(_) => ESnapshotStatus::Incomplete,
+    };
+    SCommitMarker {
+        status,
+        time: Local::now(),
+    }
+    .save(&staging)?;
+    if let Err(e) = &result {
+        // The error names source paths, so it is encrypted like the manifest.
+        // A failed backup stays in the staging folder.
+        let marker = staging.join(INCOMPLETE_MARKER);
+        encryption::write_file(&marker, format!("{}\n", e).as_bytes(), key)?;
+        return Ok(result);
+    }
+    Ok(commit_staging(&staging, backup_folder))
+}
+
+/// Where a backup named `name` is written until it is complete, hidden next to where it
+/// ends up
+pub fn staging_path(destination: &Path, name: &str) -> PathBuf {
+    destination.join(format!(".{}{}", name, STAGING_SUFFIX))
+}
+
+pub fn is_staging(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| name.starts_with('.') && name.ends_with(STAGING_SUFFIX))
+}
+
+/// Gives a complete backup its name. A backup already under that name is moved aside
+/// first and only removed once the new one is in place.
+fn commit_staging(staging: &Path, backup_folder: &Path) -> Result<(), BackupError> {
+    let mut replaced = staging.as_os_str().to_os_string();
+    replaced.push(".replaced");
+    let replaced = PathBuf::from(replaced);
+    let replacing = fs::symlink_metadata(backup_folder).is_ok();
+    if replacing {
+        if replaced.exists() {
+            fs::remove_dir_all(&replaced)
+                .map_err(BackupError::io("remove replaced backup", &replaced))?;
+        }
+        fs::rename(backup_folder, &replaced)
+            .map_err(BackupError::io("move replaced backup", backup_folder))?;
+    }
+    fs::rename(staging, backup_folder).map_err(BackupError::io("commit backup", staging))?;
+    if let Some(destination) = backup_folder.parent() {
+        sync_folder_entry(destination)?;
+    }
+    if replacing {
+        fs::remove_dir_all(&replaced)
+            .map_err(BackupError::io("remove replaced backup", &replaced))?;
+    }
+    Ok(())
+}
+
+/// Flushes every file and folder under `folder` to the disk, symlinks are not followed
+fn sync_folder(folder: &Path) -> Result<(), BackupError> {
+    let entries = fs::read_dir(folder).map_err(BackupError::io("read folder", folder))?;
+    for entry in entries {
+        let entry = entry.map_err(BackupError::io("read folder", folder))?;
+        let path = entry.path();
+        let file_type = entry
+            .file_type()
+            .map_err(BackupError::io("read metadata", &path))?;
+        if file_type.is_dir() {
+            sync_folder(&path)?;
+        } else if file_type.is_file() {
+            File::open(&path)
+                .and_then(|file| file.sync_all())
+                .map_err(BackupError::io("sync file", &path))?;
+        }
+    }
+    sync_folder_entry(folder)
+}
+
+/// Flushes the names in a folder, only unix lets a folder be opened for it
+fn sync_folder_entry(folder: &Path) -> Result<(), BackupError> {
+    #[cfg(unix)]
+    File::open(folder)
+        .and_then(|file| file.sync_all())
+        .map_err(BackupError::io("sync folder", folder))?;
+    #[cfg(not(unix))]
+    let _ = folder;
+    Ok(())
+}
+
+/// Compressed files are stored under their name with the codec's extension
+fn add_compression_extensions(items: &mut [SCopyItem]) {
+    for item in items
+        .iter_mut()
+        .filter(|item| !item.write_as.compression.is_none())
+    {
+        let mut name = item.to.file_name().unwrap_or_default().to_os_string();
+        name.push(item.write_as.compression.extension());
+        item.to.set_file_name(name);
+    }
+}
+
+/// `current` is the folder the backup is written into
+fn folder_incremental(
+    config: &SBackupConfig,
+    destination: &Path,
+    catalog: &SCatalog,
+    profile: &str,
+    current: &Path,
+    key: Option<&SKey>,
+) -> Option<SIncremental> {
+    let folder_name = current
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match config.incremental {
+        EIncrementalMode::Off => None,
+        mode => Some(SIncremental::new(
+            find_previous_backups(destination, catalog, profile, &folder_name),
+            current.to_path_buf(),
+            mode,
+            config.compare_hash,
+            key,
+        )),
+    }
+}
+
+/// Stores the chunks of every file and saves the snapshot once all of them are in.
+/// A failed backup leaves no snapshot, only chunks the next one can reuse.
+fn backup_to_repository(
+    config: &SBackupConfig,
+    target: &SBackupTarget,
+    items: &[SCopyItem],
+    progress: &mut SProgress,
+    observer: &mut dyn ProgressObserver,
+) -> Result<Result<(), BackupError>, BackupError> {
+    let SBackupTarget {
+        destination,
+        storage,
+        catalog,
+        backup_folder,
+        key,
+        ..
+    } = target;
+    let mut repository = if SRepository::is_repository(&**storage) {
+        SRepository::open(storage.clone(), key.clone())?
+    } else {
+        check_new_repository(destination, catalog)?;
+        SRepository::init(storage.clone(), key.clone())?
+    };
+
+    let name = backup_folder
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let previous = previous_snapshot_files(config, &repository, catalog, &name)?;
+
+    let result = store_items(
+        &mut repository,
+        items,
+        backup_folder,
+        &previous,
+        config.compare_hash,
+        progress,
+        observer,
+    );
+    // Chunks stored before a failure are kept for the next backup
+    let flushed = repository.flush();
+    Ok(result.and_then(|root| {
+        flushed?;
+        let tree = root.save(&mut |tree| repository.save_tree(tree))?;
+        repository.save_snapshot(&SSnapshotObject {
+            name,
+            time: Local::now(),
+            tree,
+            config: config.clone(),
+        })
+    }))
+}
+
+/// Files of the newest other snapshot of the profile, empty when incremental backups are off.
+/// Files whose size and modification time did not change keep their chunks.
+fn previous_snapshot_files(
+    config: &SBackupConfig,
+    repository: &SRepository,
+    catalog: &SCatalog,
+    name: &str,
+) -> Result<HashMap<String, STreeEntry>, BackupError> {
+    if config.incremental == EIncrementalMode::Off {
+        return Ok(HashMap::new());
+    }
+    let profile = config.profile_name();
+    Ok(catalog
+        .complete_snapshots()
+        .filter(|snapshot| snapshot.profile == profile && snapshot.name != name)
+        .find(|snapshot| repository.has_snapshot(&snapshot.name))
+        .map(|snapshot| repository.snapshot_files(&snapshot.name))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// Streams every item into one archive after the config, the manifest goes last.
+/// The archive is written under a temporary name and only a complete one keeps its name.
+/// Incremental modes do not apply, every archive holds every file.
+fn backup_to_archive(
+    config: &SBackupConfig,
+    target: &SBackupTarget,
+    format: EArchiveFormat,
+    compression: ECompression,
+    items: &mut [SCopyItem],
+    progress: &mut SProgress,
+    observer: &mut dyn ProgressObserver,
+) -> Result<Result<(), BackupError>, BackupError> {
+    let archive_path = &target.backup_folder;
+    let name = archive_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let partial = staging_path(target.destination, &name);
+
+    // The archive compresses, files go in as they are. Zip deflates the files
+    // of elements that are compressed, tar.zst compresses everything.
+    let deflate: Vec<bool> = items
+        .iter_mut()
+        .map(|item| !mem::take(&mut item.write_as.compression).is_none())
+        .collect();
+
+    let mut writer = EArchiveWriter::create(&partial, format, compression)
+        .map_err(BackupError::io("create archive", &partial))?;
+    let contents = toml::to_string(config).map_err(|source| BackupError::SerializeToml {
+        path: archive_path.join(BACKUP_CONFIG_FILE),
+        source,
+    })?;
+    writer
+        .add_bytes(BACKUP_CONFIG_FILE, contents.as_bytes())
+        .map_err(BackupError::io("write archive", &partial))?;
+
+    let mut manifest = SManifestBuilder::new(archive_path, items);
+    let result = archive_items(
+        &mut writer,
+        items,
+        &deflate,
+        &mut manifest,
+        progress,
+        observer,
+    )
+    .and_then(|()| {
+        let contents =
+            toml::to_string(&manifest.manifest).map_err(|source| BackupError::SerializeToml {
+                path: archive_path.join(MANIFEST_FILE),
+                source,
+            })?;
+        writer
+            .add_bytes(MANIFEST_FILE, contents.as_bytes())
+            .and_then(|()| writer.finish())
+            .map_err(BackupError::io("write archive", &partial))
+    })
+    .and_then(|()| {
+        fs::rename(&partial, archive_path).map_err(BackupError::io("rename archive", &partial))
+    })
+    .and_then(|()| sync_folder_entry(target.destination));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    Ok(result)
+}
+
+fn archive_items(
+    writer: &mut EArchiveWriter,
+    items: &[SCopyItem],
+    deflate: &[bool],
+    manifest: &mut SManifestBuilder,
+    progress: &mut SProgress,
+    observer: &mut dyn ProgressObserver,
+) -> Result<(), BackupError> {
+    observer.on_progress(EProgressEvent::Started, progress);
+
+    for (item, &deflate) in items.iter().zip(deflate) {
+        if observer.is_cancelled() {
+            return Err(BackupError::Cancelled);
+        }
+
+        let Some(name) = manifest::relative_path(&item.to, &manifest.root) else {
+            continue;
+        };
+        if item.is_dir {
+            writer
+                .add_dir(&name, item.modified)
+                .map_err(BackupError::io("write archive", &manifest.root))?;
+            manifest.add_folder(item);
+            continue;
+        }
+
+        progress.current_path = item.from.clone();
+        if let Some(target) = &item.symlink {
+            writer
+                .add_symlink(&name, &target.to_string_lossy(), item.modified)
+                .map_err(BackupError::io("archive symlink", &item.from))?;
+            manifest.add(item, link_hash(target), None);
+            progress.files_done += 1;
+            observer.on_progress(EProgressEvent::FileDone, progress);
+            continue;
+        }
+        // Hard links go in as copies, the manifest has them linked again on restore
+        let file = File::open(&item.from).map_err(BackupError::io("open file", &item.from))?;
+        let mut reader = SHashingReader::new(file, progress, observer);
+        writer
+            .add_file(&name, item.size, item.modified, deflate, &mut reader)
+            .map_err(BackupError::io("archive file", &item.from))?;
+        let hash = reader.finalize();
+        manifest.add(item, hash.to_hex().to_string(), None);
+        progress.files_done += 1;
+        observer.on_progress(EProgressEvent::FileDone, progress);
+    }
+
+    progress.finish();
+    observer.on_progress(EProgressEvent::Finished, progress);
+    Ok(())
+}
+
+/// The key of an encrypted destination. A destination is encrypted from its first backup on,
+/// encrypted and unencrypted backups never share one. The header of a destination encrypted
+/// for the first time is only written when `create` is set, a dry run leaves it out.
+fn destination_key(
+    destination: &Path,
+    storage: &dyn StorageBackend,
+    catalog: &SCatalog,
+    source: Option<&EKeySource>,
+    create: bool,
+) -> Result<Option<SKey>, BackupError> {
+    if SEncryptionHeader::load_from(storage)?.is_some() {
+        return encryption::unlock(storage, source);
+    }
+    let Some(source) = source else {
+        return Ok(None);
+    };
+    if has_plain_backups(destination, catalog) || SRepository::is_repository(storage) {
+        return Err(BackupError::invalid_path(
+            "encrypt a destination that holds unencrypted backups",
+            destination,
+        ));
+    }
+    if !create {
+        return Ok(None);
+    }
+    let (header, key) = SEncryptionHeader::create(source)?;
+    header.save(storage)?;
+    Ok(Some(key))
+}
+
+fn check_new_repository(destination: &Path, catalog: &SCatalog) -> Result<(), BackupError> {
+    if has_plain_backups(destination, catalog) {
+        return Err(BackupError::invalid_path(
+            "create a repository next to plain backups",
+            destination,
+        ));
+    }
+    Ok(())
+}
+
+/// Backups made before the catalog existed are only found by their config file
+fn has_plain_backups(destination: &Path, catalog: &SCatalog) -> bool {
+    !catalog.snapshots.is_empty()
+        || fs::read_dir(destination).is_ok_and(|entries| {
+            entries
+                .flatten()
+                .any(|entry| entry.path().join(BACKUP_CONFIG_FILE).is_file())
+        })
+}
+
+fn store_items(
+    repository: &mut SRepository,
+    items: &[SCopyItem],
+    backup_folder: &Path,
+    previous: &HashMap<String, STreeEntry>,
+    compare_hash: bool,
+    progress: &mut SProgress,
+    observer: &mut dyn ProgressObserver,
+) -> Result<STreeBuilder, BackupError> {
+    observer.on_progress(EProgressEvent::Started, progress);
+
+    let mut root = STreeBuilder::default();
+    // Snapshot paths and chunks of the first files of hard link groups once stored
+    let mut hardlinks: HashMap<&Path, Option<(String, Vec<String>)>> = hardlink_sources(items)
+        .into_iter()
+        .map(|from| (from, None))
+        .collect();
+    for item in items {
+        if observer.is_cancelled() {
+            return Err(BackupError::Cancelled);
+        }
+
+        let Some(relative) = manifest::relative_path(&item.to, backup_folder) else {
+            continue;
+        };
+        let components: Vec<String> = relative.split('/').map(str::to_string).collect();
+        if item.is_dir {
+            let dir = root.add_dir(&components);
+            dir.modified = item.modified.map(DateTime::from);
+            dir.metadata = item.metadata.clone();
+            continue;
+        }
+
+        progress.current_path = item.from.clone();
+        let modified: Option<DateTime<Local>> = item.modified.map(DateTime::from);
+        let name = components.last().cloned().unwrap_or_default();
+        if let Some(target) = &item.symlink {
+            root.add_file(
+                &components,
+                STreeEntry {
+                    name,
+                    kind: ETreeEntryKind::Symlink,
+                    size: 0,
+                    modified,
+                    chunks: Vec::new(),
+                    tree: None,
+                    metadata: None,
+                    target: Some(target.to_string_lossy().to_string()),
+                    hardlink: None,
+                    sparse: false,
+                },
+            );
+            progress.files_done += 1;
+            observer.on_progress(EProgressEvent::FileDone, progress);
+            continue;
+        }
+
+        // A hard link shares the chunks of the first file of its group
+        let first = item
+            .hardlink
+            .as_deref()
+            .and_then(|first| hardlinks.get(first).cloned().flatten());
+        let unchanged = unchanged_entry(repository, previous, &relative, item, compare_hash);
+        let chunks = match (&first, unchanged) {
+            (Some((_, chunks)), _) => {
+                progress.bytes_done += item.size;
+                chunks.clone()
+            }
+            (None, Some(entry)) => {
+                progress.bytes_done += item.size;
+                progress.files_reused += 1;
+                progress.bytes_reused += item.size;
+                entry.chunks.clone()
+            }
+            (None, None) => {
+                repository.store_file(&item.from, item.write_as.compression, progress, observer)?
+            }
+        };
+        if let Some(stored) = hardlinks.get_mut(item.from.as_path()) {
+            *stored = Some((relative.clone(), chunks.clone()));
+        }
+        root.add_file(
+            &components,
+            STreeEntry {
+                name,
+                kind: ETreeEntryKind::File,
+                size: item.size,
+                modified,
+                chunks,
+                tree: None,
+                metadata: item.metadata.clone(),
+                target: None,
+                hardlink: first.map(|(path, _)| path),
+                sparse: item.sparse,
+            },
+        );
+        progress.files_done += 1;
+        observer.on_progress(EProgressEvent::FileDone, progress);
+    }
+
+    progress.finish();
+    observer.on_progress(EProgressEvent::Finished, progress);
+    Ok(root)
+}
+
+/// The entry of the previous snapshot whose chunks `item` keeps
+fn unchanged_entry<'a>(
+    repository: &SRepository,
+    previous: &'a HashMap<String, STreeEntry>,
+    relative: &str,
+    item: &SCopyItem,
+    compare_hash: bool,
+) -> Option<&'a STreeEntry> {
+    let modified: Option<DateTime<Local>> = item.modified.map(DateTime::from);
+    previous.get(relative).filter(|entry| {
+        !compare_hash
+            && entry.kind == ETreeEntryKind::File
+            && entry.size == item.size
+            && modified.is_some()
+            && entry.modified == modified
+            && entry.chunks.iter().all(|hash| repository.has_chunk(hash))
+    })
+}
+
+/// Complete backups of `profile` in `destination` other than `current_name`, newest first
+fn find_previous_backups(
+    destination: &Path,
+    catalog: &SCatalog,
+    profile: &str,
+    current_name: &str,
+) -> Vec<PathBuf> {
+    catalog
+        .complete_snapshots()
+        .filter(|snapshot| snapshot.profile == profile && snapshot.name != current_name)
+        .map(|snapshot| destination.join(&snapshot.name))
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup_service::{recovery, verify};
+    use crate::catalog::CATALOG_FILE;
+    use crate::testing::{backup_details, folder_config, restore_panel, SNoProgress, STestFolder};
+
+    struct SCancelled;
+
+    impl ProgressObserver for SCancelled {
+        fn on_progress(&mut self, _event: EProgressEvent, _progress: &SProgress) {}
+
+        fn is_cancelled(&self) -> bool {
+            true
+        }
+    }
+
+    /// Cancels the job once `files` files are done
+    struct SCancelAfter {
+        files: u64,
+        cancelled: bool,
+    }
+
+    impl ProgressObserver for SCancelAfter {
+        fn on_progress(&mut self, _event: EProgressEvent, progress: &SProgress) {
+            self.cancelled |= progress.files_done >= self.files;
+        }
+
+        fn is_cancelled(&self) -> bool {
+            self.cancelled
+        }
+    }
+
+    #[test]
+    fn interrupted_folder_backup_is_resumed() {
+        let folder = STestFolder::new("resume");
+        for i in 0..20 {
+            folder.write(&format!("src/data/{:02}.txt", i), i.to_string().as_bytes());
+        }
+        let source = folder.path().join("src/data");
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let mut config = folder_config(&source, EIncrementalMode::Off);
+        config.threads = 1;
+        let mut details = backup_details(&destination, "one");
+
+        let mut observer = SCancelAfter {
+            files: 5,
+            cancelled: false,
+        };
+        assert!(backup(&config, &details, &mut observer).is_err());
+        assert!(!destination.join("one").exists());
+        let resumable = resumable_backup(&config, &details).unwrap().unwrap();
+        assert_eq!(resumable.name, "one");
+        assert!(resumable.files > 0 && resumable.files < 20);
+
+        details.resume = Some(resumable.name);
+        let snapshot = backup(&config, &details, &mut SNoProgress).unwrap();
+        assert_eq!(snapshot.files, 20);
+        assert!(!staging_path(&destination, "one").exists());
+        let report = verify(&destination.join("one"), None, &mut SNoProgress).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.checked, 20);
+    }
+
+    #[test]
+    fn failed_archive_and_repository_backups_are_not_recorded() {
+        let folder = STestFolder::new("failed-record");
+        folder.write("src/data/a.txt", b"a");
+        let config = folder_config(&folder.path().join("src/data"), EIncrementalMode::Off);
+        for (name, format) in [
+            ("zip", EDestinationFormat::Archive(EArchiveFormat::Zip)),
+            ("repository", EDestinationFormat::Repository),
+        ] {
+            let destination = folder.path().join(name);
+            fs::create_dir(&destination).unwrap();
+            let mut details = backup_details(&destination, "one");
+            details.format = format;
+            assert!(backup(&config, &details, &mut SCancelled).is_err());
+            let catalog = SCatalog::load(&destination).unwrap();
+            assert!(catalog.snapshots.is_empty());
+        }
+    }
+
+    #[test]
+    fn folder_backup_is_committed_from_its_staging_folder() {
+        let folder = STestFolder::new("commit");
+        folder.write("src/data/a.txt", b"a");
+        let config = folder_config(&folder.path().join("src/data"), EIncrementalMode::Off);
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+
+        assert!(backup(
+            &config,
+            &backup_details(&destination, "one"),
+            &mut SCancelled
+        )
+        .is_err());
+        let staging = staging_path(&destination, "one");
+        assert!(staging.is_dir() && !destination.join("one").exists());
+        let panel = restore_panel(&staging, &folder.path().join("out"));
+        assert!(matches!(
+            recovery(&panel, &mut SNoProgress),
+            Err(BackupError::InvalidPath { .. })
+        ));
+
+        backup(
+            &config,
+            &backup_details(&destination, "two"),
+            &mut SNoProgress,
+        )
+        .unwrap();
+        let marker = SCommitMarker::load(&destination.join("two"))
+            .unwrap()
+            .unwrap();
+        assert!(marker.status == ESnapshotStatus::Complete);
+        assert!(!staging_path(&destination, "two").exists());
+    }
+
+    #[test]
+    fn failed_folder_backup_is_recorded_as_incomplete() {
+        let folder = STestFolder::new("failed-folder");
+        folder.write("src/data/a.txt", b"a");
+        let config = folder_config(&folder.path().join("src/data"), EIncrementalMode::Off);
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        assert!(backup(
+            &config,
+            &backup_details(&destination, "one"),
+            &mut SCancelled
+        )
+        .is_err());
+        let catalog = SCatalog::load(&destination).unwrap();
+        assert_eq!(catalog.snapshots.len(), 1);
+        assert!(catalog.snapshots[0].status == ESnapshotStatus::Incomplete);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn catalog_of_encrypted_destination_leaves_out_source_paths() {
+        let folder = STestFolder::new("encrypted-catalog");
+        folder.write("src/data/a.txt", b"a");
+        let _socket =
+            std::os::unix::net::UnixListener::bind(folder.path().join("src/data/socket")).unwrap();
+        let key_file = folder.write("key", b"a key file of the tests");
+        let mut config = folder_config(&folder.path().join("src/data"), EIncrementalMode::Off);
+        config.path = folder
+            .write("config.toml", b"")
+            .to_string_lossy()
+            .to_string();
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let mut details = backup_details(&destination, "one");
+        details.key = Some(EKeySource::KeyFile(key_file));
+
+        let snapshot = backup(&config, &details, &mut SNoProgress).unwrap();
+        assert_eq!(snapshot.skipped.len(), 1);
+        let catalog = fs::read_to_string(destination.join(CATALOG_FILE)).unwrap();
+        assert!(!catalog.contains(&folder.path().to_string_lossy().to_string()));
+        let recorded = &SCatalog::load(&destination).unwrap().snapshots[0];
+        assert!(recorded.source_config.is_empty() && recorded.skipped.is_empty());
+    }
+
+    #[test]
+    fn backup_copies_what_its_dry_run_plans() {
+        let folder = STestFolder::new("backup-plan");
+        folder.write("src/data/a.txt", b"a");
+        folder.write("src/data/sub/b.txt", b"b");
+        let source = folder.path().join("src/data");
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let config = folder_config(&source, EIncrementalMode::Skip);
+        backup(
+            &config,
+            &backup_details(&destination, "one"),
+            &mut SNoProgress,
+        )
+        .unwrap();
+        folder.write("src/data/a.txt", b"a2");
+        folder.write("src/data/c.txt", b"c");
+
+        let details = backup_details(&destination, "two");
+        let plan = backup_plan(&config, &details).unwrap();
+        let planned: Vec<(&str, PathBuf)> = plan
+            .entries
+            .iter()
+            .map(|entry| (entry.action.label(), entry.path.clone()))
+            .collect();
+        assert_eq!(
+            planned,
+            [
+                ("copy", source.join("a.txt")),
+                ("copy", source.join("c.txt")),
+                ("skip", source.join("sub/b.txt")),
+            ]
+        );
+        assert!(!destination.join("two").exists());
+        assert!(!staging_path(&destination, "two").exists());
+
+        backup(&config, &details, &mut SNoProgress).unwrap();
+        let two = destination.join("two");
+        assert_eq!(fs::read(two.join("data/a.txt")).unwrap(), b"a2");
+        assert_eq!(fs::read(two.join("data/c.txt")).unwrap(), b"c");
+        assert!(!two.join("data/sub/b.txt").exists());
+    }
+}
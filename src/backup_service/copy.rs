@@ -0,0 +1,739 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{
+    self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError,
+};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::compression::{self, EEncoder};
+use crate::config::EIncrementalMode;
+use crate::encryption::{self, EEncryptor};
+use crate::error::BackupError;
+use crate::progress::{EProgressEvent, ProgressObserver, SProgress};
+use crate::sparse::SSparseWriter;
+use crate::throttle::SThrottle;
+
+use super::backup::{SIncremental, SManifestBuilder};
+use super::restore::SLeftBehind;
+use super::{hardlink_sources, hash_file, link_hash, SCopyItem, SStoredFormat, COPY_BUFFER_SIZE};
+
+/// Copies the items on `threads` threads. Folders, links and unchanged files are handled
+/// on this thread as they come, files that are copied go to the workers through a bounded
+/// queue. Whatever thread copied it, each item is recorded in the manifest in its order.
+pub fn copy_items(
+    items: &[SCopyItem],
+    progress: &mut SProgress,
+    move_files: bool,
+    incremental: Option<&SIncremental>,
+    manifest: Option<&mut SManifestBuilder>,
+    observer: &mut dyn ProgressObserver,
+    threads: usize,
+) -> Result<Vec<SLeftBehind>, BackupError> {
+    observer.on_progress(EProgressEvent::Started, progress);
+
+    let throttle = observer.throttle().cloned();
+    let threads = threads.max(1);
+    let cancel = AtomicBool::new(false);
+    let (jobs, queue) = mpsc::sync_channel(threads * COPY_QUEUE_PER_THREAD);
+    let queue = Mutex::new(queue);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let observer = SWorkerObserver {
+                sender,
+                reported: 0,
+                throttle: throttle.clone(),
+                cancel: &cancel,
+            };
+            let queue = &queue;
+            scope.spawn(move || copy_worker(items, queue, observer, move_files));
+        }
+        drop(sender);
+
+        let copier = SCopier {
+            items,
+            progress,
+            move_files,
+            incremental,
+            manifest,
+            observer,
+            receiver,
+            cancel: &cancel,
+            copied: BTreeMap::new(),
+            recorded: 0,
+            in_flight: 0,
+            hardlinks: hardlink_sources(items)
+                .into_iter()
+                .map(|from| (from, None))
+                .collect(),
+            left_behind: Vec::new(),
+            error: None,
+        };
+        copier.run(jobs)
+    })
+}
+
+/// Files waiting for a copy worker, per worker
+const COPY_QUEUE_PER_THREAD: usize = 4;
+
+/// What happened to an item, recorded in the order of the items
+enum ECopied {
+    Folder,
+    File {
+        /// Of the original contents, empty for a moved file
+        hash: String,
+        /// Names the earlier backup that holds a file left out of this one
+        snapshot: Option<String>,
+        /// Taken over unchanged from an earlier backup
+        reused: bool,
+        left_behind: Option<SLeftBehind>,
+    },
+}
+
+/// Sent by a copy worker
+enum ECopyMessage {
+    /// Copied since its last message
+    Bytes(u64),
+    Done(usize, Result<ECopied, BackupError>),
+}
+
+/// State of `copy_items` on the thread that owns the progress, the manifest and the observer
+struct SCopier<'a> {
+    items: &'a [SCopyItem],
+    progress: &'a mut SProgress,
+    move_files: bool,
+    incremental: Option<&'a SIncremental>,
+    manifest: Option<&'a mut SManifestBuilder>,
+    observer: &'a mut dyn ProgressObserver,
+    receiver: Receiver<ECopyMessage>,
+    /// Workers skip what is still queued once it is set
+    cancel: &'a AtomicBool,
+    /// Done but not recorded yet, by index
+    copied: BTreeMap<usize, Result<ECopied, BackupError>>,
+    /// Items before this index are recorded
+    recorded: usize,
+    in_flight: usize,
+    /// Where the first files of hard link groups were copied to, with their hashes
+    hardlinks: HashMap<&'a Path, Option<(&'a Path, String)>>,
+    left_behind: Vec<SLeftBehind>,
+    /// The first failure, nothing is queued after it
+    error: Option<BackupError>,
+}
+
+impl<'a> SCopier<'a> {
+    fn run(mut self, jobs: SyncSender<usize>) -> Result<Vec<SLeftBehind>, BackupError> {
+        for (index, item) in self.items.iter().enumerate() {
+            if self.observer.is_cancelled() {
+                self.fail(BackupError::Cancelled);
+            }
+            if self.error.is_some() {
+                break;
+            }
+            match self.copy_in_place(item) {
+                Ok(Some(copied)) => {
+                    self.copied.insert(index, Ok(copied));
+                }
+                Ok(None) => self.queue(&jobs, index),
+                Err(e) => self.fail(e),
+            }
+            while self.receive(false) {}
+            self.record_ready();
+        }
+
+        // Lets the workers end once the queue is empty
+        drop(jobs);
+        if self.error.is_some() {
+            self.cancel.store(true, Ordering::Relaxed);
+        }
+        self.wait();
+        // Whatever did get copied before a failure is still recorded
+        for (index, copied) in mem::take(&mut self.copied) {
+            self.record(index, copied);
+        }
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        self.progress.finish();
+        self.observer
+            .on_progress(EProgressEvent::Finished, self.progress);
+        Ok(self.left_behind)
+    }
+
+    /// What happened to `item` when it is not copied by a worker
+    fn copy_in_place(&mut self, item: &'a SCopyItem) -> Result<Option<ECopied>, BackupError> {
+        if item.is_dir {
+            fs::create_dir_all(&item.to).map_err(BackupError::io("create folder", &item.to))?;
+            return Ok(Some(ECopied::Folder));
+        }
+
+        self.progress.current_path = item.from.clone();
+        if let Some(manifest) = self.manifest.as_deref_mut() {
+            manifest.sync_journal(false)?;
+            if let Some((hash, snapshot)) = manifest.resumed(item) {
+                self.progress.bytes_done += item.size;
+                return Ok(Some(ECopied::File {
+                    hash,
+                    snapshot,
+                    reused: false,
+                    left_behind: None,
+                }));
+            }
+            // Left by the interrupted run, maybe cut off. It can be hard-linked to an
+            // earlier backup, so it is removed rather than written over.
+            if manifest.journal.is_some() {
+                remove_file_in_the_way(&item.to)?;
+            }
+        }
+
+        if let Some(target) = &item.symlink {
+            create_symlink(target, &item.to)?;
+            let left_behind = match self.move_files {
+                true => remove_moved(&item.from, check_restored(&item.to, 0, Some(target), None)),
+                false => None,
+            };
+            return Ok(Some(ECopied::File {
+                hash: link_hash(target),
+                snapshot: None,
+                reused: false,
+                left_behind,
+            }));
+        }
+
+        // Linked to the copy of the first file of its group, copied when that fails.
+        // The first file may still be with a worker.
+        if item.hardlink.is_some() {
+            self.wait();
+        }
+        let first = item
+            .hardlink
+            .as_deref()
+            .and_then(|first| self.hardlinks.get(first).cloned().flatten());
+        if let Some((first, hash)) = first {
+            if create_hard_link(first, &item.to).is_ok() {
+                let left_behind = match self.move_files {
+                    true => {
+                        remove_moved(&item.from, check_restored(&item.to, item.size, None, None))
+                    }
+                    false => None,
+                };
+                self.progress.bytes_done += item.size;
+                return Ok(Some(ECopied::File {
+                    hash,
+                    snapshot: None,
+                    reused: false,
+                    left_behind,
+                }));
+            }
+        }
+
+        let Some(incremental) = self.incremental else {
+            return Ok(None);
+        };
+        let Some(previous) = incremental.unchanged_copy(item)? else {
+            return Ok(None);
+        };
+        let mut snapshot = None;
+        let hash = match incremental.mode {
+            EIncrementalMode::HardLink if fs::hard_link(&previous.path, &item.to).is_err() => {
+                // The destination does not support hard links. The previous copy
+                // is copied as it is stored, compressed and encrypted or not.
+                let bytes_before = self.progress.bytes_done;
+                copy_file(
+                    &previous.path,
+                    &item.to,
+                    &SStoredFormat::default(),
+                    &SStoredFormat::default(),
+                    item.sparse,
+                    self.progress,
+                    self.observer,
+                )?;
+                self.progress.bytes_done = bytes_before + item.size;
+                incremental.previous_hash(&previous)?
+            }
+            mode => {
+                if mode == EIncrementalMode::Skip {
+                    snapshot = incremental.previous[previous.backup]
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string());
+                }
+                self.progress.bytes_done += item.size;
+                incremental.previous_hash(&previous)?
+            }
+        };
+        Ok(Some(ECopied::File {
+            hash,
+            snapshot,
+            reused: true,
+            left_behind: None,
+        }))
+    }
+
+    /// Waits for room in the queue, passing on the progress of the workers meanwhile
+    fn queue(&mut self, jobs: &SyncSender<usize>, index: usize) {
+        let mut job = index;
+        loop {
+            match jobs.try_send(job) {
+                Ok(()) => break,
+                Err(TrySendError::Full(full)) => job = full,
+                Err(TrySendError::Disconnected(_)) => {
+                    self.fail(BackupError::WorkerStopped);
+                    return;
+                }
+            }
+            if self.observer.is_cancelled() {
+                self.fail(BackupError::Cancelled);
+                return;
+            }
+            self.receive(true);
+            self.record_ready();
+        }
+        self.in_flight += 1;
+    }
+
+    /// Handles one message of the workers. Returns whether there was one, `block` waits
+    /// a moment for it.
+    fn receive(&mut self, block: bool) -> bool {
+        let message = match block {
+            true => self
+                .receiver
+                .recv_timeout(Duration::from_millis(50))
+                .map_err(|e| e == RecvTimeoutError::Disconnected),
+            false => self
+                .receiver
+                .try_recv()
+                .map_err(|e| e == TryRecvError::Disconnected),
+        };
+        match message {
+            Ok(ECopyMessage::Bytes(bytes)) => {
+                self.progress.bytes_done += bytes;
+                self.observer
+                    .on_progress(EProgressEvent::Bytes, self.progress);
+            }
+            Ok(ECopyMessage::Done(index, copied)) => {
+                self.in_flight -= 1;
+                self.copied.insert(index, copied);
+            }
+            // Every worker is gone, what was queued will not be copied
+            Err(true) if self.in_flight > 0 => {
+                self.in_flight = 0;
+                self.fail(BackupError::WorkerStopped);
+                return false;
+            }
+            Err(_) => return false,
+        }
+        true
+    }
+
+    /// Until every queued file is copied
+    fn wait(&mut self) {
+        while self.in_flight > 0 {
+            // Also wakes workers held back by the bandwidth limit
+            if self.observer.is_cancelled() {
+                self.cancel.store(true, Ordering::Relaxed);
+            }
+            self.receive(true);
+        }
+        self.record_ready();
+    }
+
+    /// Records the items done since the last one recorded, up to the first still copied
+    fn record_ready(&mut self) {
+        while let Some(copied) = self.copied.remove(&self.recorded) {
+            self.record(self.recorded, copied);
+            self.recorded += 1;
+        }
+    }
+
+    fn record(&mut self, index: usize, copied: Result<ECopied, BackupError>) {
+        let item = &self.items[index];
+        match copied {
+            Ok(ECopied::Folder) => {
+                if let Some(manifest) = self.manifest.as_deref_mut() {
+                    manifest.add_folder(item);
+                }
+            }
+            Ok(ECopied::File {
+                hash,
+                snapshot,
+                reused,
+                left_behind,
+            }) => {
+                // A skipped file is not in this backup to be linked to
+                if let Some(first) = self.hardlinks.get_mut(item.from.as_path()) {
+                    if snapshot.is_none() {
+                        *first = Some((&item.to, hash.clone()));
+                    }
+                }
+                if let Some(manifest) = self.manifest.as_deref_mut() {
+                    manifest.add(item, hash, snapshot);
+                }
+                self.left_behind.extend(left_behind);
+                if reused {
+                    self.progress.files_reused += 1;
+                    self.progress.bytes_reused += item.size;
+                }
+                self.progress.files_done += 1;
+                self.observer
+                    .on_progress(EProgressEvent::FileDone, self.progress);
+            }
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn fail(&mut self, e: BackupError) {
+        if self.error.is_none() {
+            self.error = Some(e);
+        }
+    }
+}
+
+/// Copies the files queued by `SCopier` until the queue is closed
+fn copy_worker(
+    items: &[SCopyItem],
+    queue: &Mutex<Receiver<usize>>,
+    mut observer: SWorkerObserver,
+    move_files: bool,
+) {
+    loop {
+        // Held only while waiting, another worker takes the next file meanwhile
+        let next = queue.lock().map(|queue| queue.recv());
+        let Ok(Ok(index)) = next else {
+            return;
+        };
+        let copied = match observer.is_cancelled() {
+            true => Err(BackupError::Cancelled),
+            // The copier waits for every queued file, so a panic is passed on as an error
+            false => panic::catch_unwind(AssertUnwindSafe(|| {
+                copy_queued(&items[index], move_files, &mut observer)
+            }))
+            .unwrap_or(Err(BackupError::WorkerStopped)),
+        };
+        if observer
+            .sender
+            .send(ECopyMessage::Done(index, copied))
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Across file systems, or when the backup cannot be changed, a moved file is copied
+/// and removed once the copy is checked
+fn copy_queued(
+    item: &SCopyItem,
+    move_files: bool,
+    observer: &mut SWorkerObserver,
+) -> Result<ECopied, BackupError> {
+    let mut progress = SProgress::new(0, 0);
+    observer.reported = 0;
+    let renamed = move_files && item.read_as.is_plain() && fs::rename(&item.from, &item.to).is_ok();
+    if renamed {
+        progress.bytes_done = item.size;
+        observer.on_progress(EProgressEvent::Bytes, &progress);
+        // Only backups keep hashes, a restore has no manifest to write them into
+        return Ok(ECopied::File {
+            hash: String::new(),
+            snapshot: None,
+            reused: false,
+            left_behind: None,
+        });
+    }
+    let hash = copy_file(
+        &item.from,
+        &item.to,
+        &item.read_as,
+        &item.write_as,
+        item.sparse,
+        &mut progress,
+        observer,
+    )?
+    .to_hex()
+    .to_string();
+    let left_behind = match move_files {
+        true => remove_moved(
+            &item.from,
+            check_restored(&item.to, item.size, None, Some(&hash)),
+        ),
+        false => None,
+    };
+    Ok(ECopied::File {
+        hash,
+        snapshot: None,
+        reused: false,
+        left_behind,
+    })
+}
+
+/// Passes the bytes a worker copies on to `SCopier`
+struct SWorkerObserver<'a> {
+    sender: Sender<ECopyMessage>,
+    /// Of the current file
+    reported: u64,
+    /// Of the job, its threads share it
+    throttle: Option<Arc<SThrottle>>,
+    /// Set when the copier stops, queued files are skipped
+    cancel: &'a AtomicBool,
+}
+
+impl ProgressObserver for SWorkerObserver<'_> {
+    fn on_progress(&mut self, _event: EProgressEvent, progress: &SProgress) {
+        let bytes = progress.bytes_done - self.reported;
+        self.reported = progress.bytes_done;
+        let _ = self.sender.send(ECopyMessage::Bytes(bytes));
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    fn throttle(&self) -> Option<&Arc<SThrottle>> {
+        self.throttle.as_ref()
+    }
+}
+
+/// Whether `path` holds what was restored to it: the target of a symlink, else the size
+/// and, when it is known, the hash of the contents, read back from the disk
+pub fn check_restored(
+    path: &Path,
+    size: u64,
+    symlink: Option<&Path>,
+    hash: Option<&str>,
+) -> Result<(), String> {
+    if let Some(target) = symlink {
+        return match fs::read_link(path) {
+            Ok(restored) if restored == target => Ok(()),
+            Ok(restored) => Err(format!(
+                "the restored link points to {}",
+                restored.display()
+            )),
+            Err(e) => Err(format!("the restored link cannot be read: {}", e)),
+        };
+    }
+    let metadata = fs::symlink_metadata(path)
+        .map_err(|e| format!("the restored copy cannot be read: {}", e))?;
+    if metadata.len() != size {
+        return Err(format!(
+            "the restored copy has {} bytes instead of {}",
+            metadata.len(),
+            size
+        ));
+    }
+    if let Some(hash) = hash {
+        let restored = hash_file(path).map_err(|e| e.to_string())?;
+        if restored.to_hex().as_str() != hash {
+            return Err("the restored copy differs".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Removes the original of a moved file once its restored copy passed `check`.
+/// Returns the file when it stays in the backup.
+fn remove_moved(from: &Path, check: Result<(), String>) -> Option<SLeftBehind> {
+    let check = check.and_then(|()| {
+        fs::remove_file(from).map_err(|e| format!("the original cannot be removed: {}", e))
+    });
+    failed_check(from.to_path_buf(), check)
+}
+
+pub fn failed_check(path: PathBuf, check: Result<(), String>) -> Option<SLeftBehind> {
+    check.err().map(|reason| SLeftBehind { path, reason })
+}
+
+/// Returns the hash of the original contents. A `sparse` file keeps its holes when it is
+/// written as it is.
+fn copy_file(
+    from: &Path,
+    to: &Path,
+    read_as: &SStoredFormat,
+    write_as: &SStoredFormat,
+    sparse: bool,
+    progress: &mut SProgress,
+    observer: &mut dyn ProgressObserver,
+) -> Result<blake3::Hash, BackupError> {
+    let source = File::open(from).map_err(BackupError::io("open file", from))?;
+    let metadata = source
+        .metadata()
+        .map_err(BackupError::io("read metadata", from))?;
+    let source = encryption::decryptor(read_as.key.as_ref(), source);
+    let mut source = compression::decoder(read_as.compression, source)
+        .map_err(BackupError::io("open file", from))?;
+    let (replacing, destination) = SReplacingFile::create(to)?;
+    let destination = SSparseWriter::new(destination, sparse && write_as.is_plain());
+    let destination = EEncryptor::new(write_as.key.as_ref(), destination)
+        .map_err(BackupError::io("create file", to))?;
+    let mut destination = EEncoder::new(write_as.compression, destination)
+        .map_err(BackupError::io("create file", to))?;
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut hasher = blake3::Hasher::new();
+
+    loop {
+        let read = source
+            .read(&mut buffer)
+            .map_err(BackupError::io("read file", from))?;
+        if read == 0 {
+            break;
+        }
+        destination
+            .write_all(&buffer[..read])
+            .map_err(BackupError::io("write file", to))?;
+        hasher.update(&buffer[..read]);
+        observer.on_transfer(read as u64);
+        progress.bytes_done += read as u64;
+        observer.on_progress(EProgressEvent::Bytes, progress);
+    }
+
+    let destination = destination
+        .finish()
+        .and_then(EEncryptor::finish)
+        .and_then(SSparseWriter::finish)
+        .map_err(BackupError::io("write file", to))?;
+    // The modification time is kept so that incremental backups can compare it
+    if let Ok(modified) = metadata.modified() {
+        destination
+            .set_modified(modified)
+            .map_err(BackupError::io("set modification time", to))?;
+    }
+    destination
+        .set_permissions(metadata.permissions())
+        .map_err(BackupError::io("set permissions", to))?;
+    replacing.commit()?;
+    Ok(hasher.finalize())
+}
+
+/// Distinguishes the temporary files of copies running at once
+static REPLACING_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// A new file at `path`. When something is there already it is written under a temporary
+/// name next to it and renamed over it once complete, so what was there, like a symlink,
+/// is replaced and never written through.
+pub struct SReplacingFile {
+    temp_path: Option<PathBuf>,
+    path: PathBuf,
+}
+
+impl SReplacingFile {
+    pub fn create(path: &Path) -> Result<(SReplacingFile, File), BackupError> {
+        let temp_path = fs::symlink_metadata(path).is_ok().then(|| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let number = REPLACING_FILES.fetch_add(1, Ordering::Relaxed);
+            path.with_file_name(format!(".{}.{}-{}.tmp", name, std::process::id(), number))
+        });
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(temp_path.as_deref().unwrap_or(path))
+            .map_err(BackupError::io("create file", path))?;
+        let replacing = SReplacingFile {
+            temp_path,
+            path: path.to_path_buf(),
+        };
+        Ok((replacing, file))
+    }
+
+    pub fn commit(mut self) -> Result<(), BackupError> {
+        match self.temp_path.take() {
+            Some(temp_path) => fs::rename(&temp_path, &self.path).map_err(|e| {
+                let _ = fs::remove_file(&temp_path);
+                BackupError::io("write file", &self.path)(e)
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SReplacingFile {
+    fn drop(&mut self) {
+        if let Some(temp_path) = &self.temp_path {
+            let _ = fs::remove_file(temp_path);
+        }
+    }
+}
+
+/// Replaces a file already at `link`, like a restore writing over it would
+pub fn create_symlink(target: &Path, link: &Path) -> Result<(), BackupError> {
+    remove_file_in_the_way(link)?;
+    #[cfg(unix)]
+    let result = std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    let result = std::os::windows::fs::symlink_file(target, link);
+    result.map_err(BackupError::io("create symlink", link))
+}
+
+pub fn create_hard_link(original: &Path, link: &Path) -> Result<(), BackupError> {
+    remove_file_in_the_way(link)?;
+    fs::hard_link(original, link).map_err(BackupError::io("create hard link", link))
+}
+
+fn remove_file_in_the_way(path: &Path) -> Result<(), BackupError> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => {
+            fs::remove_file(path).map_err(BackupError::io("remove file", path))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup_service::new_progress;
+    use crate::backup_service::scan::read_file;
+    use crate::testing::{SNoProgress, STestFolder};
+
+    #[test]
+    fn copier_records_items_in_their_order_whatever_thread_copies_them() {
+        let folder = STestFolder::new("copier-order");
+        let target = folder.path().join("backup");
+        fs::create_dir(&target).unwrap();
+        // Large and small files mixed, so later ones finish first
+        let items: Vec<SCopyItem> = (0..64)
+            .map(|i| {
+                let size = if i % 7 == 0 { 3 * COPY_BUFFER_SIZE } else { i };
+                let from = folder.write(&format!("src/{:02}", i), &vec![i as u8; size]);
+                read_file(&from, &target.join(format!("{:02}", i)))
+                    .unwrap()
+                    .unwrap()
+                    .0
+            })
+            .collect();
+
+        let mut manifest = SManifestBuilder::new(&target, &items);
+        let mut progress = new_progress(&items);
+        let left_behind = copy_items(
+            &items,
+            &mut progress,
+            false,
+            None,
+            Some(&mut manifest),
+            &mut SNoProgress,
+            4,
+        )
+        .unwrap();
+        assert!(left_behind.is_empty());
+        assert_eq!(progress.files_done, 64);
+
+        let paths: Vec<&str> = manifest
+            .manifest
+            .files
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+        let expected: Vec<String> = (0..64).map(|i| format!("{:02}", i)).collect();
+        assert_eq!(paths, expected);
+        for (entry, item) in manifest.manifest.files.iter().zip(&items) {
+            assert_eq!(entry.hash, hash_file(&item.from).unwrap().to_hex().as_str());
+            assert_eq!(fs::read(&item.to).unwrap(), fs::read(&item.from).unwrap());
+        }
+    }
+}
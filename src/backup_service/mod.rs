@@ -0,0 +1,172 @@
+mod backup;
+mod copy;
+mod restore;
+mod scan;
+mod verify;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::ECompression;
+use crate::encryption::SKey;
+use crate::error::BackupError;
+use crate::manifest::MANIFEST_FILE;
+use crate::metadata::SFileMetadata;
+use crate::progress::{EProgressEvent, ProgressObserver, SProgress};
+
+pub use backup::{
+    backup, backup_plan, discard_resumable, resumable_backup, staging_path, SResumableBackup,
+};
+pub use restore::{
+    backup_contents, find_conflicts, recovery, recovery_plan, SBackupEntry, SRestoreConflict,
+    SRestoreReport,
+};
+pub use verify::{verify, SVerifyReport};
+
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+const BACKUP_CONFIG_FILE: &str = "backup_config.toml";
+/// Left in a backup folder whose backup was cancelled or failed
+pub const INCOMPLETE_MARKER: &str = ".incomplete";
+
+/// One file or folder to be copied, collected before anything is written
+/// so that the totals for progress reporting are known up front
+struct SCopyItem {
+    from: PathBuf,
+    to: PathBuf,
+    size: u64,
+    modified: Option<SystemTime>,
+    is_dir: bool,
+    /// How `to` is written during a backup
+    write_as: SStoredFormat,
+    /// How `from` is read during a restore
+    read_as: SStoredFormat,
+    /// Of `from` when it is scanned. A restore puts the one from the manifest back on `to`.
+    metadata: Option<SFileMetadata>,
+    /// Where a symlink points, it is copied as a link
+    symlink: Option<PathBuf>,
+    /// `from` of the first file of its hard link group, which `to` is linked to once copied
+    hardlink: Option<PathBuf>,
+    /// Blocks of zeros are written as holes
+    sparse: bool,
+}
+
+impl SCopyItem {
+    /// A file found by a walk, before anything about it is read
+    fn found(from: &Path, to: &Path) -> SCopyItem {
+        SCopyItem {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            size: 0,
+            modified: None,
+            is_dir: false,
+            write_as: SStoredFormat::default(),
+            read_as: SStoredFormat::default(),
+            metadata: None,
+            symlink: None,
+            hardlink: None,
+            sparse: false,
+        }
+    }
+}
+
+/// How the bytes of a file are stored in a backup, compression is applied before encryption
+#[derive(Clone, Default)]
+struct SStoredFormat {
+    compression: ECompression,
+    key: Option<SKey>,
+}
+
+impl SStoredFormat {
+    fn is_plain(&self) -> bool {
+        self.compression.is_none() && self.key.is_none()
+    }
+}
+
+/// What a plan says about a file that is copied as a link, empty for a regular one
+fn link_note(symlink: Option<&Path>, hardlink: Option<&Path>) -> String {
+    match (symlink, hardlink) {
+        (Some(target), _) => format!("symlink to {}", target.display()),
+        (None, Some(first)) => format!("hard link to {}", first.display()),
+        (None, None) => String::new(),
+    }
+}
+
+fn item_note(item: &SCopyItem) -> String {
+    link_note(item.symlink.as_deref(), item.hardlink.as_deref())
+}
+
+/// Hashes what is read through it and reports it as progress
+struct SHashingReader<'a, R> {
+    inner: R,
+    hasher: blake3::Hasher,
+    progress: &'a mut SProgress,
+    observer: &'a mut dyn ProgressObserver,
+}
+
+impl<'a, R: Read> SHashingReader<'a, R> {
+    fn new(
+        inner: R,
+        progress: &'a mut SProgress,
+        observer: &'a mut dyn ProgressObserver,
+    ) -> SHashingReader<'a, R> {
+        SHashingReader {
+            inner,
+            hasher: blake3::Hasher::new(),
+            progress,
+            observer,
+        }
+    }
+
+    fn finalize(&self) -> blake3::Hash {
+        self.hasher.finalize()
+    }
+}
+
+impl<R: Read> Read for SHashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.observer.on_transfer(read as u64);
+        self.progress.bytes_done += read as u64;
+        self.observer
+            .on_progress(EProgressEvent::Bytes, self.progress);
+        Ok(read)
+    }
+}
+
+fn new_progress(items: &[SCopyItem]) -> SProgress {
+    let files_total = items.iter().filter(|item| !item.is_dir).count() as u64;
+    let bytes_total = items.iter().map(|item| item.size).sum();
+    SProgress::new(files_total, bytes_total)
+}
+
+/// Sources of the first files of hard link groups
+fn hardlink_sources(items: &[SCopyItem]) -> HashSet<&Path> {
+    items
+        .iter()
+        .filter_map(|item| item.hardlink.as_deref())
+        .collect()
+}
+
+/// What the manifest keeps as the hash of a symlink
+fn link_hash(target: &Path) -> String {
+    blake3::hash(target.as_os_str().as_encoded_bytes())
+        .to_hex()
+        .to_string()
+}
+
+pub fn hash_file(path: &Path) -> Result<blake3::Hash, BackupError> {
+    let mut hasher = blake3::Hasher::new();
+    let file = File::open(path).map_err(BackupError::io("open file", path))?;
+    hasher
+        .update_reader(file)
+        .map_err(BackupError::io("read file", path))?;
+    Ok(hasher.finalize())
+}
+
+fn is_archive_metadata(name: &str) -> bool {
+    name == BACKUP_CONFIG_FILE || name == MANIFEST_FILE
+}
@@ -0,0 +1,1472 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde::de::DeserializeOwned;
+
+use crate::archive::{self, EArchiveFormat};
+use crate::catalog::{ESnapshotStatus, SCatalog, SCommitMarker};
+use crate::config::{EElementType, SBackupConfig};
+use crate::encryption;
+use crate::error::BackupError;
+use crate::filter::SRestoreSelection;
+use crate::manifest::{self, SManifest, SManifestEntry, SManifestFolder, MANIFEST_FILE};
+use crate::metadata::{SFileMetadata, SMetadataFailure};
+use crate::parallel;
+use crate::plan::{EPlanAction, SPlan};
+use crate::progress::{EProgressEvent, ProgressObserver, SProgress};
+use crate::repository::{ETreeEntryKind, SRepository};
+use crate::retention;
+use crate::sparse::SSparseWriter;
+use crate::storage::{SLocalStorage, StorageBackend};
+use crate::throttle::{self, SThrottledObserver};
+use crate::ui::recovery::{EConflictPolicy, EFileAction, ERestoreLayout, SRecoveryPanel};
+
+use super::backup::is_staging;
+use super::copy::{
+    check_restored, copy_items, create_hard_link, create_symlink, failed_check, SReplacingFile,
+};
+use super::scan::{read_file, read_found, scan_dir, scan_file, SScan};
+use super::{
+    is_archive_metadata, link_note, SCopyItem, SStoredFormat, BACKUP_CONFIG_FILE, INCOMPLETE_MARKER,
+};
+
+/// A file a restore would write over
+#[derive(Clone)]
+pub struct SRestoreConflict {
+    pub path: PathBuf,
+    /// Of the copy in the backup
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Of the file already at `path`
+    pub existing_size: u64,
+    pub existing_modified: Option<SystemTime>,
+}
+
+/// Files a restore of `config.backup_folder` would write over, before any conflict policy
+pub fn find_conflicts(config: &SRecoveryPanel) -> Result<Vec<SRestoreConflict>, BackupError> {
+    Ok(SOpenedBackup::open(config)?
+        .entries
+        .into_iter()
+        .filter(|entry| !entry.is_dir)
+        .filter_map(|entry| {
+            let existing = fs::symlink_metadata(&entry.target).ok()?;
+            Some(SRestoreConflict {
+                path: entry.target,
+                size: entry.size,
+                modified: entry.modified,
+                existing_size: existing.len(),
+                existing_modified: existing.modified().ok(),
+            })
+        })
+        .collect())
+}
+
+/// A file or folder of a backup, `path` is where it lives inside the backup, `/` separated
+/// and under its original name
+#[derive(Clone)]
+pub struct SBackupEntry {
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// Everything a restore of `config.backup_folder` can take, whatever is selected
+pub fn backup_contents(config: &SRecoveryPanel) -> Result<Vec<SBackupEntry>, BackupError> {
+    let mut config = config.clone();
+    config.selection.clear();
+    Ok(SOpenedBackup::open(&config)?
+        .entries
+        .into_iter()
+        .map(|entry| SBackupEntry {
+            path: entry.path,
+            is_dir: entry.is_dir,
+            size: entry.size,
+        })
+        .collect())
+}
+
+/// What a restore could not do, the contents of the files are all written
+#[derive(Clone, Default)]
+pub struct SRestoreReport {
+    /// Like the owner of a file when not running as root
+    pub metadata_failures: Vec<SMetadataFailure>,
+    /// Files a move did not take out of the backup, which is then kept
+    pub left_behind: Vec<SLeftBehind>,
+}
+
+/// A file of a backup a move left in place
+#[derive(Clone)]
+pub struct SLeftBehind {
+    /// Inside the backup folder, archive or snapshot
+    pub path: PathBuf,
+    /// Like `the restored copy differs`
+    pub reason: String,
+}
+
+/// A file or folder of a backup folder, snapshot or archive with where a restore writes it
+struct SRestoreEntry {
+    /// Inside the backup, `/` separated and under its original name
+    path: String,
+    /// The copy in a backup folder, which a skip-mode backup can keep in an earlier one.
+    /// In a snapshot or an archive it only names the entry.
+    stored: PathBuf,
+    target: PathBuf,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+    metadata: Option<SFileMetadata>,
+    /// Where a symlink points
+    symlink: Option<PathBuf>,
+    /// `stored` of the first file of its hard link group
+    hardlink: Option<PathBuf>,
+    sparse: bool,
+    /// Of a file in a backup folder
+    read_as: SStoredFormat,
+    /// Of a file in a repository snapshot
+    chunks: Vec<String>,
+}
+
+impl SRestoreEntry {
+    fn copy_item(&self) -> SCopyItem {
+        SCopyItem {
+            size: self.size,
+            modified: self.modified,
+            is_dir: self.is_dir,
+            read_as: self.read_as.clone(),
+            metadata: self.metadata.clone(),
+            symlink: self.symlink.clone(),
+            hardlink: self.hardlink.clone(),
+            sparse: self.sparse,
+            ..SCopyItem::found(&self.stored, &self.target)
+        }
+    }
+}
+
+enum EBackupKind {
+    Folder,
+    Snapshot {
+        repository: Box<SRepository>,
+        name: String,
+    },
+    Archive(EArchiveFormat),
+}
+
+/// A backup with the entries a restore of it takes
+struct SOpenedBackup {
+    kind: EBackupKind,
+    /// The backup folder, the snapshot inside its repository or the archive
+    path: PathBuf,
+    entries: Vec<SRestoreEntry>,
+}
+
+impl SOpenedBackup {
+    /// Only the entries `config.selection` matches are kept
+    fn open(config: &SRecoveryPanel) -> Result<SOpenedBackup, BackupError> {
+        let mut path = PathBuf::from(&config.backup_folder);
+        let (kind, mut entries) = if let Some((root, name)) = SRepository::locate_snapshot(&path) {
+            path = root.location(&name);
+            let (repository, entries) = snapshot_entries(root, &name, &path, config)?;
+            (
+                EBackupKind::Snapshot {
+                    repository: Box::new(repository),
+                    name,
+                },
+                entries,
+            )
+        } else if let Some(format) = EArchiveFormat::of_file(&path) {
+            let entries = archive_entries(&path, format, config)?;
+            (EBackupKind::Archive(format), entries)
+        } else {
+            (EBackupKind::Folder, folder_entries(config)?)
+        };
+        let selection = SRestoreSelection::new(&config.selection)?;
+        entries.retain(|entry| selection.contains(&entry.path));
+        Ok(SOpenedBackup {
+            kind,
+            path,
+            entries,
+        })
+    }
+
+    /// A moved snapshot or archive is removed as a whole, so only all of it can be moved
+    fn check_whole_move(&self, config: &SRecoveryPanel) -> Result<(), BackupError> {
+        let operation = match self.kind {
+            EBackupKind::Folder => return Ok(()),
+            EBackupKind::Snapshot { .. } => "move a part of a snapshot",
+            EBackupKind::Archive(_) => "move a part of an archive",
+        };
+        if config.file_action == EFileAction::Moved
+            && !SRestoreSelection::new(&config.selection)?.is_all()
+        {
+            return Err(BackupError::invalid_path(operation, &self.path));
+        }
+        Ok(())
+    }
+}
+
+pub fn recovery(
+    config: &SRecoveryPanel,
+    observer: &mut dyn ProgressObserver,
+) -> Result<SRestoreReport, BackupError> {
+    throttle::lower_priority(config.idle_io, config.nice)?;
+    let observer = &mut SThrottledObserver::new(observer, config.bandwidth_limit);
+
+    let backup = SOpenedBackup::open(config)?;
+    backup.check_whole_move(config)?;
+    let SOpenedBackup {
+        kind,
+        path: backup_path,
+        mut entries,
+    } = backup;
+    // A backup later skip-mode backups keep files in is only copied out of
+    let needed_by = match kind {
+        EBackupKind::Folder => needed_by(config)?,
+        _ => Vec::new(),
+    };
+    let moved = config.file_action == EFileAction::Moved && needed_by.is_empty();
+    let mut left_behind = Vec::new();
+    if !needed_by.is_empty() {
+        left_behind.push(SLeftBehind {
+            path: backup_path.clone(),
+            reason: format!("needed by {}", needed_by.join(", ")),
+        });
+    }
+    left_behind.extend(resolve_conflicts(config, &mut entries, moved, &backup_path));
+    create_parent_folders(
+        entries
+            .iter()
+            .filter(|entry| !entry.is_dir)
+            .map(|entry| entry.target.as_path()),
+    )?;
+
+    let files_total = entries.iter().filter(|entry| !entry.is_dir).count() as u64;
+    let bytes_total = entries.iter().map(|entry| entry.size).sum();
+    let mut progress = SProgress::new(files_total, bytes_total);
+    match &kind {
+        EBackupKind::Folder => left_behind.extend(restore_folder_entries(
+            config,
+            &entries,
+            moved,
+            &backup_path,
+            &mut progress,
+            observer,
+        )?),
+        EBackupKind::Snapshot { repository, .. } => {
+            // Hard links go last, once the file they link to is written
+            entries.sort_by_key(|entry| entry.hardlink.is_some());
+            let mut writer = SEntryWriter::new(moved, &mut progress, observer);
+            for entry in &entries {
+                writer.restore(entry, |file| {
+                    for hash in &entry.chunks {
+                        let data = repository.read_chunk(hash)?;
+                        file.write_all(&data)
+                            .map_err(BackupError::io("write file", &entry.target))?;
+                    }
+                    Ok(())
+                })?;
+            }
+            left_behind.extend(writer.finish());
+        }
+        EBackupKind::Archive(format) => {
+            let by_name: HashMap<&str, &SRestoreEntry> = entries
+                .iter()
+                .map(|entry| (entry.path.as_str(), entry))
+                .collect();
+            let mut writer = SEntryWriter::new(moved, &mut progress, observer);
+            // A hard link is stored as a copy of the file it links to, which comes before it
+            archive::read(&backup_path, *format, |archived, contents| {
+                let Some(entry) = by_name.get(archived.name.as_str()) else {
+                    return Ok(());
+                };
+                writer.restore(entry, |file| {
+                    io::copy(contents, file)
+                        .map(drop)
+                        .map_err(BackupError::io("extract file", &entry.target))
+                })
+            })?;
+            left_behind.extend(writer.finish());
+        }
+    }
+    let metadata_failures = restore_metadata(&entries);
+
+    // Moving a part of a backup folder leaves the rest of it in place, and so does a move
+    // that could not take every file out of the backup
+    if moved && left_behind.is_empty() {
+        match kind {
+            EBackupKind::Folder => {
+                if SRestoreSelection::new(&config.selection)?.is_all() {
+                    fs::remove_dir_all(&backup_path)
+                        .map_err(BackupError::io("remove backup folder", &backup_path))?;
+                    remove_from_catalog(&backup_path)?;
+                }
+            }
+            EBackupKind::Snapshot {
+                mut repository,
+                name,
+            } => {
+                repository.remove_snapshot(&name)?;
+                repository.collect_garbage()?;
+                remove_from_catalog(&backup_path)?;
+            }
+            EBackupKind::Archive(_) => {
+                fs::remove_file(&backup_path)
+                    .map_err(BackupError::io("remove archive", &backup_path))?;
+                remove_from_catalog(&backup_path)?;
+            }
+        }
+    }
+
+    Ok(SRestoreReport {
+        metadata_failures,
+        left_behind,
+    })
+}
+
+/// Copies the files of a backup folder on the copy workers. Files a skip-mode backup keeps
+/// in earlier backups stay there, a move only copies them.
+fn restore_folder_entries(
+    config: &SRecoveryPanel,
+    entries: &[SRestoreEntry],
+    moved: bool,
+    backup_folder: &Path,
+    progress: &mut SProgress,
+    observer: &mut dyn ProgressObserver,
+) -> Result<Vec<SLeftBehind>, BackupError> {
+    let (kept_elsewhere, items): (Vec<SCopyItem>, Vec<SCopyItem>) = entries
+        .iter()
+        .map(SRestoreEntry::copy_item)
+        .partition(|item| moved && !item.from.starts_with(backup_folder));
+    let threads = parallel::threads(config.threads);
+    let mut left_behind = Vec::new();
+    if !kept_elsewhere.is_empty() {
+        left_behind.extend(copy_items(
+            &kept_elsewhere,
+            progress,
+            false,
+            None,
+            None,
+            observer,
+            threads,
+        )?);
+    }
+    left_behind.extend(copy_items(
+        &items, progress, moved, None, None, observer, threads,
+    )?);
+    Ok(left_behind)
+}
+
+/// Writes the entries of a snapshot or an archive one at a time. A moved one is only
+/// removed once every restored file reads back as it should.
+struct SEntryWriter<'a> {
+    moved: bool,
+    progress: &'a mut SProgress,
+    observer: &'a mut dyn ProgressObserver,
+    /// Where each written file went, by `stored`
+    restored: HashMap<PathBuf, PathBuf>,
+    left_behind: Vec<SLeftBehind>,
+}
+
+impl<'a> SEntryWriter<'a> {
+    fn new(
+        moved: bool,
+        progress: &'a mut SProgress,
+        observer: &'a mut dyn ProgressObserver,
+    ) -> SEntryWriter<'a> {
+        observer.on_progress(EProgressEvent::Started, progress);
+        SEntryWriter {
+            moved,
+            progress,
+            observer,
+            restored: HashMap::new(),
+            left_behind: Vec::new(),
+        }
+    }
+
+    /// `write` fills the new file of a regular file entry with its contents
+    fn restore(
+        &mut self,
+        entry: &SRestoreEntry,
+        write: impl FnOnce(&mut dyn Write) -> Result<(), BackupError>,
+    ) -> Result<(), BackupError> {
+        if self.observer.is_cancelled() {
+            return Err(BackupError::Cancelled);
+        }
+        let path = &entry.target;
+        if entry.is_dir {
+            return fs::create_dir_all(path).map_err(BackupError::io("create folder", path));
+        }
+
+        self.progress.current_path = path.clone();
+        let first = entry
+            .hardlink
+            .as_ref()
+            .and_then(|first| self.restored.get(first));
+        let hash = if let Some(target) = &entry.symlink {
+            create_symlink(target, path)?;
+            self.progress.bytes_done += entry.size;
+            None
+        } else if first.is_some_and(|first| create_hard_link(first, path).is_ok()) {
+            self.progress.bytes_done += entry.size;
+            None
+        } else {
+            let (replacing, file) = SReplacingFile::create(path)?;
+            let file = SSparseWriter::new(file, entry.sparse);
+            let mut file = SHashingWriter::new(file, &mut *self.progress, &mut *self.observer);
+            write(&mut file)?;
+            let hash = file.finalize().to_hex().to_string();
+            let file = file
+                .inner
+                .finish()
+                .map_err(BackupError::io("write file", path))?;
+            if let Some(modified) = entry.modified {
+                file.set_modified(modified)
+                    .map_err(BackupError::io("set modification time", path))?;
+            }
+            replacing.commit()?;
+            self.restored.insert(entry.stored.clone(), path.clone());
+            Some(hash)
+        };
+        if self.moved {
+            let check = check_restored(path, entry.size, entry.symlink.as_deref(), hash.as_deref());
+            self.left_behind
+                .extend(failed_check(entry.stored.clone(), check));
+        }
+        self.progress.files_done += 1;
+        self.observer
+            .on_progress(EProgressEvent::FileDone, self.progress);
+        Ok(())
+    }
+
+    fn finish(self) -> Vec<SLeftBehind> {
+        self.progress.finish();
+        self.observer
+            .on_progress(EProgressEvent::Finished, self.progress);
+        self.left_behind
+    }
+}
+
+/// Hashes what is written through it and reports it as progress
+struct SHashingWriter<'a, W> {
+    inner: W,
+    hasher: blake3::Hasher,
+    progress: &'a mut SProgress,
+    observer: &'a mut dyn ProgressObserver,
+}
+
+impl<'a, W: Write> SHashingWriter<'a, W> {
+    fn new(
+        inner: W,
+        progress: &'a mut SProgress,
+        observer: &'a mut dyn ProgressObserver,
+    ) -> SHashingWriter<'a, W> {
+        SHashingWriter {
+            inner,
+            hasher: blake3::Hasher::new(),
+            progress,
+            observer,
+        }
+    }
+
+    fn finalize(&self) -> blake3::Hash {
+        self.hasher.finalize()
+    }
+}
+
+impl<W: Write> Write for SHashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.observer.on_transfer(written as u64);
+        self.progress.bytes_done += written as u64;
+        self.observer
+            .on_progress(EProgressEvent::Bytes, self.progress);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Files go first and folders last, deepest first, as writing into a folder changes
+/// its modification time
+fn restore_metadata(entries: &[SRestoreEntry]) -> Vec<SMetadataFailure> {
+    let mut restored: Vec<(&SRestoreEntry, &SFileMetadata)> = entries
+        .iter()
+        .filter_map(|entry| Some((entry, entry.metadata.as_ref()?)))
+        .collect();
+    restored.sort_by_key(|(entry, _)| (entry.is_dir, Reverse(entry.target.components().count())));
+    restored
+        .iter()
+        .flat_map(|(entry, metadata)| metadata.apply(&entry.target, entry.modified))
+        .collect()
+}
+
+/// Leaves out the files whose existing copy the conflict policy keeps, which a moved backup
+/// then keeps too. The others get the path they are written to.
+fn resolve_conflicts(
+    config: &SRecoveryPanel,
+    entries: &mut Vec<SRestoreEntry>,
+    moved: bool,
+    backup_path: &Path,
+) -> Vec<SLeftBehind> {
+    let mut left_behind = Vec::new();
+    entries.retain_mut(|entry| {
+        if entry.is_dir {
+            return true;
+        }
+        match resolve_conflict(config, &entry.target, entry.modified) {
+            Some(target) => {
+                entry.target = target;
+                true
+            }
+            None => {
+                if moved && entry.stored.starts_with(backup_path) {
+                    left_behind.push(kept_in_place(&entry.stored, &entry.target));
+                }
+                false
+            }
+        }
+    });
+    left_behind
+}
+
+/// Forgets a backup folder, snapshot or archive a move removed, in the catalog of its
+/// destination
+fn remove_from_catalog(backup_path: &Path) -> Result<(), BackupError> {
+    let (Some(destination), Some(name)) = (backup_path.parent(), backup_path.file_name()) else {
+        return Ok(());
+    };
+    let storage = SLocalStorage::new(destination);
+    let mut catalog = SCatalog::load_from(&storage)?;
+    let name = name.to_string_lossy();
+    if catalog
+        .snapshots
+        .iter()
+        .any(|snapshot| snapshot.name == name)
+    {
+        catalog.remove(&name);
+        catalog.save(&storage)?;
+    }
+    Ok(())
+}
+
+/// A file of a moved backup that is not restored since the file at `target` is kept
+fn kept_in_place(path: &Path, target: &Path) -> SLeftBehind {
+    SLeftBehind {
+        path: path.to_path_buf(),
+        reason: format!("{} was kept", target.display()),
+    }
+}
+
+/// What `recovery` would write, skip or remove, found the same way without writing anything
+pub fn recovery_plan(config: &SRecoveryPanel) -> Result<SPlan, BackupError> {
+    let backup = SOpenedBackup::open(config)?;
+    backup.check_whole_move(config)?;
+    let backup_folder = backup.path.as_path();
+    // What a move removes once the files are restored, a part of a backup folder is
+    // moved out of it file by file
+    let mut needed = Vec::new();
+    let removed = match backup.kind {
+        EBackupKind::Folder => {
+            needed = needed_by(config)?;
+            SRestoreSelection::new(&config.selection)?
+                .is_all()
+                .then_some("backup folder removed")
+        }
+        EBackupKind::Snapshot { .. } => Some("snapshot and its unused chunks removed"),
+        EBackupKind::Archive(_) => Some("archive removed"),
+    };
+    let files: Vec<&SRestoreEntry> = backup
+        .entries
+        .iter()
+        .filter(|entry| !entry.is_dir)
+        .collect();
+
+    let moved = config.file_action == EFileAction::Moved && needed.is_empty();
+    let mut plan = SPlan::new(format!("Restore from {}", backup_folder.display()));
+    let mut kept = false;
+    for file in &files {
+        let note = link_note(file.symlink.as_deref(), file.hardlink.as_deref());
+        match resolve_conflict(config, &file.target, file.modified) {
+            None => {
+                plan.push(
+                    EPlanAction::Skip,
+                    &file.target,
+                    file.size,
+                    "existing file kept",
+                );
+                kept |= file.stored.starts_with(backup_folder);
+                continue;
+            }
+            Some(target) if target != file.target => plan.push(
+                EPlanAction::Copy,
+                target,
+                file.size,
+                "renamed, existing file kept",
+            ),
+            Some(target) if fs::symlink_metadata(&target).is_ok() => {
+                plan.push(EPlanAction::Overwrite, target, file.size, note)
+            }
+            Some(target) => plan.push(EPlanAction::Copy, target, file.size, note),
+        }
+        if moved && removed.is_none() && file.stored.starts_with(backup_folder) {
+            plan.push(
+                EPlanAction::Delete,
+                &file.stored,
+                file.size,
+                "moved out of the backup",
+            );
+        }
+    }
+    if let (true, Some(note)) = (moved, removed) {
+        let size = files.iter().map(|file| file.size).sum();
+        // A file that is not restored stays in the backup, and so does the backup
+        if kept {
+            plan.push(
+                EPlanAction::Skip,
+                backup_folder,
+                size,
+                "kept, not every file is restored",
+            );
+        } else {
+            plan.push(EPlanAction::Delete, backup_folder, size, note);
+        }
+    }
+    if !needed.is_empty() {
+        let size = files.iter().map(|file| file.size).sum();
+        let note = format!("kept, needed by {}", needed.join(", "));
+        plan.push(EPlanAction::Skip, backup_folder, size, note);
+    }
+    Ok(plan)
+}
+
+/// The backups of the destination that keep some of their files in the backup folder a
+/// restore moves, empty when it copies
+fn needed_by(config: &SRecoveryPanel) -> Result<Vec<String>, BackupError> {
+    let backup_folder = Path::new(&config.backup_folder);
+    let (Some(destination), Some(name)) = (backup_folder.parent(), backup_folder.file_name())
+    else {
+        return Ok(Vec::new());
+    };
+    if config.file_action != EFileAction::Moved {
+        return Ok(Vec::new());
+    }
+    let key = encryption::unlock(&SLocalStorage::new(backup_folder), config.key.as_ref())?;
+    let name = name.to_string_lossy();
+    let mut needed_by = Vec::new();
+    for snapshot in SCatalog::load(destination)?.complete_snapshots() {
+        if snapshot.name != name
+            && retention::needed_snapshots(&destination.join(&snapshot.name), key.as_ref())?
+                .contains(name.as_ref())
+        {
+            needed_by.push(snapshot.name.clone());
+        }
+    }
+    Ok(needed_by)
+}
+
+/// The files and folders of a backup folder. Compressed files are restored under their
+/// original name, with their attributes from the manifest, and unchanged files a skip-mode
+/// backup left out from the earlier backup keeping them.
+fn folder_entries(config: &SRecoveryPanel) -> Result<Vec<SRestoreEntry>, BackupError> {
+    let backup_folder = Path::new(&config.backup_folder);
+    check_complete(backup_folder)?;
+    let key = encryption::unlock(&SLocalStorage::new(backup_folder), config.key.as_ref())?;
+    let backup_config = SBackupConfig::load_from_backup(backup_folder, key.as_ref())?;
+    let targets = element_targets(config, &backup_config)?;
+
+    let mut scan = SScan::new(false, parallel::threads(config.threads));
+    for element in &backup_config.elements {
+        let stored = backup_folder.join(element_name(&element.path)?);
+        if element.content_type == EElementType::Folder {
+            scan_dir(&stored, &stored, None, &mut scan)?;
+        } else {
+            scan_file(&stored, &stored, &mut scan);
+        }
+    }
+    read_found(&mut scan)?;
+    let mut items: Vec<(String, SCopyItem)> = scan
+        .items
+        .into_iter()
+        .filter_map(|item| Some((manifest::relative_path(&item.from, backup_folder)?, item)))
+        .collect();
+
+    let (files, folders) = if backup_folder.join(MANIFEST_FILE).is_file() {
+        let mut manifest = SManifest::load(backup_folder, key.as_ref())?;
+        let folders: HashMap<String, SManifestFolder> = mem::take(&mut manifest.folders)
+            .into_iter()
+            .map(|folder| (folder.path.clone(), folder))
+            .collect();
+        (manifest.by_path(), folders)
+    } else {
+        (HashMap::new(), HashMap::new())
+    };
+
+    let mut skipped: Vec<&SManifestEntry> = files
+        .values()
+        .filter(|entry| entry.snapshot.is_some())
+        .collect();
+    skipped.sort_by(|a, b| a.path.cmp(&b.path));
+    for entry in skipped {
+        let stored = entry.stored_path(backup_folder);
+        let Some((item, _)) = read_file(&stored, &stored)? else {
+            return Err(BackupError::invalid_path(
+                "restore a file that is not kept as one",
+                &stored,
+            ));
+        };
+        items.push((entry.path.clone(), item));
+    }
+
+    let mut entries = Vec::new();
+    for (stored_path, item) in items {
+        let file = files.get(&stored_path).filter(|_| !item.is_dir);
+        let compression = file.map(|file| file.compression).unwrap_or_default();
+        let path = match stored_path.strip_suffix(compression.extension()) {
+            Some(original) => original.to_string(),
+            None => stored_path.clone(),
+        };
+        let Some(target) = entry_target(&targets, backup_folder, &path)? else {
+            continue;
+        };
+        let mut entry = SRestoreEntry {
+            path,
+            stored: item.from,
+            target,
+            is_dir: item.is_dir,
+            size: item.size,
+            modified: item.modified,
+            metadata: item.metadata,
+            symlink: item.symlink,
+            // Also when the backup was copied without its hard links
+            hardlink: item.hardlink,
+            sparse: item.sparse,
+            read_as: SStoredFormat::default(),
+            chunks: Vec::new(),
+        };
+        if entry.is_dir {
+            if let Some(folder) = folders.get(&stored_path) {
+                entry.modified = folder.modified.map(SystemTime::from);
+                entry.metadata = folder.metadata.clone();
+            }
+        } else {
+            entry.read_as.key = key.clone();
+        }
+        if let Some(file) = file {
+            entry.read_as.compression = file.compression;
+            entry.size = file.size;
+            entry.modified = file.modified.map(SystemTime::from).or(entry.modified);
+            entry.metadata = file.metadata.clone();
+            entry.sparse |= file.sparse;
+            if let Some(first) = &file.hardlink {
+                entry.hardlink = Some(backup_folder.join(first));
+            }
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// A backup left by a failed or interrupted run is not restored. Folders written before
+/// backups were committed have no commit marker and count as complete.
+fn check_complete(backup_folder: &Path) -> Result<(), BackupError> {
+    let committed = SCommitMarker::load(backup_folder)?
+        .is_none_or(|marker| marker.status == ESnapshotStatus::Complete);
+    if is_staging(backup_folder) || backup_folder.join(INCOMPLETE_MARKER).exists() || !committed {
+        return Err(BackupError::invalid_path(
+            "restore an incomplete backup",
+            backup_folder,
+        ));
+    }
+    Ok(())
+}
+
+/// Where an element is restored to, its original path unless there is a restore root
+fn element_target(config: &SRecoveryPanel, element_path: &str) -> Result<PathBuf, BackupError> {
+    let original = Path::new(element_path);
+    let Some(root) = &config.restore_root else {
+        return Ok(original.to_path_buf());
+    };
+    match config.restore_layout {
+        // Only the plain names are kept, so nothing ends up outside the root
+        ERestoreLayout::FullPath => Ok(original
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .fold(root.clone(), |path, component| path.join(component))),
+        ERestoreLayout::Flat => {
+            let name = original
+                .file_name()
+                .ok_or_else(|| BackupError::invalid_path("get file name", original))?;
+            Ok(root.join(name))
+        }
+    }
+}
+
+/// The name the entries of an element are under inside a backup
+fn element_name(element_path: &str) -> Result<String, BackupError> {
+    Path::new(element_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| BackupError::invalid_path("get file name", element_path))
+}
+
+/// Where each element of `backup_config` is restored to, by its name inside the backup
+fn element_targets(
+    config: &SRecoveryPanel,
+    backup_config: &SBackupConfig,
+) -> Result<HashMap<String, PathBuf>, BackupError> {
+    let mut targets = HashMap::new();
+    for element in &backup_config.elements {
+        targets.insert(
+            element_name(&element.path)?,
+            element_target(config, &element.path)?,
+        );
+    }
+    Ok(targets)
+}
+
+/// Where the entry at `path` inside a backup is restored to, `None` for entries of no
+/// element. Names that could leave the element, like `..` or absolute paths, are refused.
+fn entry_target(
+    targets: &HashMap<String, PathBuf>,
+    backup_path: &Path,
+    path: &str,
+) -> Result<Option<PathBuf>, BackupError> {
+    let mut parts = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts.push(part),
+            _ => {
+                return Err(BackupError::invalid_path(
+                    "restore backup entry",
+                    backup_path.join(path),
+                ))
+            }
+        }
+    }
+    let Some((first, rest)) = parts.split_first() else {
+        return Ok(None);
+    };
+    Ok(targets.get(first.to_string_lossy().as_ref()).map(|target| {
+        rest.iter()
+            .fold(target.clone(), |path, part| path.join(part))
+    }))
+}
+
+/// Folders above restored files, which do not exist yet under a new restore root
+fn create_parent_folders<'a>(files: impl Iterator<Item = &'a Path>) -> Result<(), BackupError> {
+    let parents: HashSet<&Path> = files.filter_map(Path::parent).collect();
+    for parent in parents {
+        fs::create_dir_all(parent).map_err(BackupError::io("create folder", parent))?;
+    }
+    Ok(())
+}
+
+/// Where a restored file is written under the conflict policy, `None` when the file
+/// already at `target` is kept
+fn resolve_conflict(
+    config: &SRecoveryPanel,
+    target: &Path,
+    modified: Option<SystemTime>,
+) -> Option<PathBuf> {
+    let Ok(existing) = fs::symlink_metadata(target) else {
+        return Some(target.to_path_buf());
+    };
+    match config.conflict_policy_for(target) {
+        EConflictPolicy::Replace => Some(target.to_path_buf()),
+        EConflictPolicy::Skip => None,
+        EConflictPolicy::KeepNewer => {
+            let existing_modified = existing.modified().ok();
+            (modified.is_some() && existing_modified < modified).then(|| target.to_path_buf())
+        }
+        EConflictPolicy::Rename => Some(restored_name(target)),
+    }
+}
+
+/// `name.restored.ext` next to `target`, numbered when that is taken too
+fn restored_name(target: &Path) -> PathBuf {
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = target
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut number = 1;
+    loop {
+        let suffix = match number {
+            1 => "restored".to_string(),
+            number => format!("restored-{}", number),
+        };
+        let path = target.with_file_name(format!("{}.{}{}", stem, suffix, extension));
+        if fs::symlink_metadata(&path).is_err() {
+            return path;
+        }
+        number += 1;
+    }
+}
+
+/// Every folder and file of a repository snapshot
+fn snapshot_entries(
+    root: Arc<dyn StorageBackend>,
+    name: &str,
+    snapshot_folder: &Path,
+    config: &SRecoveryPanel,
+) -> Result<(SRepository, Vec<SRestoreEntry>), BackupError> {
+    let key = encryption::unlock(&*root, config.key.as_ref())?;
+    let repository = SRepository::open(root, key)?;
+    let snapshot = repository.load_snapshot(name)?;
+    let tree = repository.load_tree(&snapshot.tree)?;
+    let targets = element_targets(config, &snapshot.config)?;
+
+    let mut entries = Vec::new();
+    for element in &tree.entries {
+        let mut flattened = Vec::new();
+        repository.flatten(element, Path::new(&element.name), &mut flattened)?;
+        for (path, entry) in flattened {
+            let Some(path) = manifest::relative_path(&path, Path::new("")) else {
+                continue;
+            };
+            let Some(target) = entry_target(&targets, snapshot_folder, &path)? else {
+                continue;
+            };
+            entries.push(SRestoreEntry {
+                stored: snapshot_folder.join(&path),
+                path,
+                target,
+                is_dir: entry.kind == ETreeEntryKind::Dir,
+                size: entry.size,
+                modified: entry.modified.map(SystemTime::from),
+                metadata: entry.metadata,
+                symlink: entry.target.map(PathBuf::from),
+                hardlink: entry.hardlink.map(|first| snapshot_folder.join(first)),
+                sparse: entry.sparse,
+                read_as: SStoredFormat::default(),
+                chunks: entry.chunks,
+            });
+        }
+    }
+    Ok((repository, entries))
+}
+
+/// The entries of an archive that belong to an element of its config. The manifest at its
+/// end says which files are sparse or hard links and holds their attributes.
+fn archive_entries(
+    archive_path: &Path,
+    format: EArchiveFormat,
+    config: &SRecoveryPanel,
+) -> Result<Vec<SRestoreEntry>, BackupError> {
+    let backup_config: SBackupConfig =
+        load_archived_toml(archive_path, format, BACKUP_CONFIG_FILE)?.ok_or_else(|| {
+            BackupError::invalid_path("find backup_config.toml in archive", archive_path)
+        })?;
+    let targets = element_targets(config, &backup_config)?;
+    let manifest: SManifest =
+        load_archived_toml(archive_path, format, MANIFEST_FILE)?.unwrap_or_else(SManifest::new);
+    let files: HashMap<&str, &SManifestEntry> = manifest
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    let folders: HashMap<&str, &SManifestFolder> = manifest
+        .folders
+        .iter()
+        .map(|folder| (folder.path.as_str(), folder))
+        .collect();
+
+    let listed = archive::list(archive_path, format)
+        .map_err(BackupError::io("read archive", archive_path))?;
+    let mut entries = Vec::new();
+    for archived in listed {
+        if is_archive_metadata(&archived.name) {
+            continue;
+        }
+        let Some(target) = entry_target(&targets, archive_path, &archived.name)? else {
+            continue;
+        };
+        let file = files.get(archived.name.as_str());
+        let folder = folders.get(archived.name.as_str());
+        let modified = file
+            .and_then(|file| file.modified)
+            .or_else(|| folder.and_then(|folder| folder.modified));
+        entries.push(SRestoreEntry {
+            stored: archive_path.join(&archived.name),
+            target,
+            is_dir: archived.is_dir,
+            size: archived.size,
+            modified: modified.map(SystemTime::from).or(archived.modified),
+            metadata: file
+                .and_then(|file| file.metadata.clone())
+                .or_else(|| folder.and_then(|folder| folder.metadata.clone())),
+            symlink: archived.symlink.map(PathBuf::from),
+            hardlink: file
+                .and_then(|file| file.hardlink.as_ref())
+                .map(|first| archive_path.join(first)),
+            sparse: file.is_some_and(|file| file.sparse),
+            read_as: SStoredFormat::default(),
+            chunks: Vec::new(),
+            path: archived.name,
+        });
+    }
+    Ok(entries)
+}
+
+/// A toml file of an archive, `None` when the archive has none
+fn load_archived_toml<T: DeserializeOwned>(
+    archive_path: &Path,
+    format: EArchiveFormat,
+    name: &str,
+) -> Result<Option<T>, BackupError> {
+    let Some(contents) = archive::read_file(archive_path, format, name)? else {
+        return Ok(None);
+    };
+    toml::from_str(&String::from_utf8_lossy(&contents))
+        .map(Some)
+        .map_err(|source| BackupError::ParseToml {
+            path: archive_path.join(name),
+            source,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::Duration;
+
+    use chrono::Local;
+
+    use super::*;
+    use crate::archive::EArchiveWriter;
+    use crate::backup_service::backup;
+    use crate::compression;
+    use crate::config::{ECompression, EIncrementalMode};
+    use crate::repository::{EDestinationFormat, SSnapshotObject, STree, STreeEntry};
+    use crate::storage;
+    use crate::testing::{
+        backup_details, folder_config, restore_panel, skip_backups, SNoProgress, STestFolder,
+    };
+
+    #[test]
+    fn skip_mode_backup_restores_unchanged_files_from_earlier_backup() {
+        let folder = STestFolder::new("skip-restore");
+        let (source, destination) = skip_backups(&folder);
+        let two = destination.join("two");
+        assert!(!two.join("data/sub/b.txt").exists());
+
+        let panel = restore_panel(&two, &folder.path().join("out"));
+        let contents: Vec<String> = backup_contents(&panel)
+            .unwrap()
+            .into_iter()
+            .filter(|entry| !entry.is_dir)
+            .map(|entry| entry.path)
+            .collect();
+        assert!(contents.contains(&"data/a.txt".to_string()));
+        assert!(contents.contains(&"data/sub/b.txt".to_string()));
+
+        recovery(&panel, &mut SNoProgress).unwrap();
+        let target = element_target(&panel, &source.to_string_lossy()).unwrap();
+        assert_eq!(fs::read(target.join("a.txt")).unwrap(), b"a2");
+        assert_eq!(fs::read(target.join("sub/b.txt")).unwrap(), b"b");
+    }
+
+    #[test]
+    fn moving_skip_mode_backup_keeps_earlier_backup() {
+        let folder = STestFolder::new("skip-move");
+        let (source, destination) = skip_backups(&folder);
+
+        let mut panel = restore_panel(&destination.join("two"), &folder.path().join("out"));
+        panel.file_action = EFileAction::Moved;
+        let report = recovery(&panel, &mut SNoProgress).unwrap();
+        assert!(report.left_behind.is_empty());
+        assert!(!destination.join("two").exists());
+        let catalog = SCatalog::load(&destination).unwrap();
+        assert!(catalog
+            .snapshots
+            .iter()
+            .all(|snapshot| snapshot.name != "two"));
+        assert_eq!(
+            fs::read(destination.join("one/data/sub/b.txt")).unwrap(),
+            b"b"
+        );
+        let target = element_target(&panel, &source.to_string_lossy()).unwrap();
+        assert_eq!(fs::read(target.join("sub/b.txt")).unwrap(), b"b");
+    }
+
+    #[test]
+    fn moving_backup_a_skip_mode_backup_needs_only_copies_it() {
+        let folder = STestFolder::new("skip-move-needed");
+        let (source, destination) = skip_backups(&folder);
+
+        let mut panel = restore_panel(&destination.join("one"), &folder.path().join("out"));
+        panel.file_action = EFileAction::Moved;
+        let report = recovery(&panel, &mut SNoProgress).unwrap();
+        assert_eq!(report.left_behind.len(), 1);
+        assert_eq!(report.left_behind[0].reason, "needed by two");
+        assert_eq!(
+            fs::read(destination.join("one/data/sub/b.txt")).unwrap(),
+            b"b"
+        );
+        let target = element_target(&panel, &source.to_string_lossy()).unwrap();
+        assert_eq!(fs::read(target.join("a.txt")).unwrap(), b"a");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restore_replaces_symlinks_in_the_way_instead_of_writing_through_them() {
+        let folder = STestFolder::new("restore-symlink");
+        folder.write("src/data/a.txt", b"restored");
+        folder.write("src/data/b.txt", b"restored");
+        let secret = folder.write("secret", b"secret");
+        let source = folder.path().join("src/data");
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let config = folder_config(&source, EIncrementalMode::Off);
+        backup(
+            &config,
+            &backup_details(&destination, "one"),
+            &mut SNoProgress,
+        )
+        .unwrap();
+
+        for policy in [EConflictPolicy::Replace, EConflictPolicy::KeepNewer] {
+            let mut panel = restore_panel(&destination.join("one"), &folder.path().join("out"));
+            panel.conflict_policy = policy;
+            let target = element_target(&panel, &source.to_string_lossy()).unwrap();
+            fs::create_dir_all(&target).unwrap();
+            for name in ["a.txt", "b.txt"] {
+                let link = target.join(name);
+                let _ = fs::remove_file(&link);
+                std::os::unix::fs::symlink(&secret, &link).unwrap();
+                // Older than the backed up files, so keep newer replaces it too
+                let path = std::ffi::CString::new(link.as_os_str().as_encoded_bytes()).unwrap();
+                let times = [libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: 0,
+                }; 2];
+                let flags = libc::AT_SYMLINK_NOFOLLOW;
+                let set =
+                    unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), &times[0], flags) };
+                assert_eq!(set, 0);
+            }
+
+            recovery(&panel, &mut SNoProgress).unwrap();
+            assert_eq!(fs::read(&secret).unwrap(), b"secret");
+            assert_eq!(fs::read_dir(&target).unwrap().count(), 2);
+            for name in ["a.txt", "b.txt"] {
+                let path = target.join(name);
+                assert!(fs::symlink_metadata(&path).unwrap().is_file());
+                assert_eq!(fs::read(&path).unwrap(), b"restored");
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_entries_that_leave_their_folder_are_refused() {
+        let folder = STestFolder::new("restore-dot-dot");
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let repository = SRepository::init(storage::open(&destination), None).unwrap();
+        let entry = |name: &str, kind, tree| STreeEntry {
+            name: name.to_string(),
+            kind,
+            size: 0,
+            modified: None,
+            chunks: Vec::new(),
+            tree,
+            metadata: None,
+            target: None,
+            hardlink: None,
+            sparse: false,
+        };
+        let escaping = STree {
+            entries: vec![entry("..", ETreeEntryKind::File, None)],
+        };
+        let escaping = repository.save_tree(&escaping).unwrap();
+        let root = STree {
+            entries: vec![entry("data", ETreeEntryKind::Dir, Some(escaping))],
+        };
+        repository
+            .save_snapshot(&SSnapshotObject {
+                name: "one".to_string(),
+                time: Local::now(),
+                tree: repository.save_tree(&root).unwrap(),
+                config: folder_config(&folder.path().join("src/data"), EIncrementalMode::Off),
+            })
+            .unwrap();
+
+        let out = folder.path().join("out");
+        let panel = restore_panel(&destination.join("one"), &out);
+        assert!(backup_contents(&panel).is_err());
+        assert!(recovery(&panel, &mut SNoProgress).is_err());
+        assert!(!out.exists());
+    }
+
+    /// Backs up a folder into an archive of `format` and restores it
+    fn archive_round_trip(format: EArchiveFormat, name: &str) {
+        let folder = STestFolder::new(name);
+        folder.write("src/data/a.txt", b"a");
+        folder.write("src/data/sub/b.txt", &[7; 100_000]);
+        folder.write("src/data/empty/.keep", b"");
+        let source = folder.path().join("src/data");
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let mut details = backup_details(&destination, "one");
+        details.format = EDestinationFormat::Archive(format);
+        let config = folder_config(&source, EIncrementalMode::Off);
+        backup(&config, &details, &mut SNoProgress).unwrap();
+
+        let archive = destination.join(format!("one{}", format.extension()));
+        let panel = restore_panel(&archive, &folder.path().join("out"));
+        recovery(&panel, &mut SNoProgress).unwrap();
+        let target = element_target(&panel, &source.to_string_lossy()).unwrap();
+        assert_eq!(fs::read(target.join("a.txt")).unwrap(), b"a");
+        assert_eq!(fs::read(target.join("sub/b.txt")).unwrap(), [7; 100_000]);
+        assert!(target.join("empty/.keep").is_file());
+        assert!(!target.join(BACKUP_CONFIG_FILE).exists());
+        assert!(!target.join(MANIFEST_FILE).exists());
+    }
+
+    #[test]
+    fn tar_zst_backup_is_restored() {
+        archive_round_trip(EArchiveFormat::TarZst, "restore-tar-zst");
+    }
+
+    #[test]
+    fn zip_backup_is_restored() {
+        archive_round_trip(EArchiveFormat::Zip, "restore-zip");
+    }
+
+    #[test]
+    fn archive_entries_that_leave_their_folder_are_refused() {
+        let folder = STestFolder::new("restore-archive-dot-dot");
+        let config = folder_config(&folder.path().join("src/data"), EIncrementalMode::Off);
+        let archive = folder.path().join("one.zip");
+        let mut writer =
+            EArchiveWriter::create(&archive, EArchiveFormat::Zip, ECompression::None).unwrap();
+        let contents = toml::to_string(&config).unwrap();
+        writer
+            .add_bytes(BACKUP_CONFIG_FILE, contents.as_bytes())
+            .unwrap();
+        writer
+            .add_bytes("data/../../escaped.txt", b"escaped")
+            .unwrap();
+        writer.finish().unwrap();
+
+        let out = folder.path().join("out");
+        let panel = restore_panel(&archive, &out);
+        assert!(backup_contents(&panel).is_err());
+        assert!(recovery(&panel, &mut SNoProgress).is_err());
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn elements_go_under_the_restore_root_with_their_full_path_or_flat() {
+        let mut panel = SRecoveryPanel::new();
+        assert_eq!(
+            element_target(&panel, "/home/user/docs").unwrap(),
+            Path::new("/home/user/docs")
+        );
+
+        panel.restore_root = Some(PathBuf::from("/restore"));
+        assert_eq!(
+            element_target(&panel, "/home/user/docs").unwrap(),
+            Path::new("/restore/home/user/docs")
+        );
+        assert_eq!(
+            element_target(&panel, "/home/../../etc").unwrap(),
+            Path::new("/restore/home/etc")
+        );
+
+        panel.restore_layout = ERestoreLayout::Flat;
+        assert_eq!(
+            element_target(&panel, "/home/user/docs").unwrap(),
+            Path::new("/restore/docs")
+        );
+        assert!(element_target(&panel, "/").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn modification_times_and_permissions_are_restored() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let folder = STestFolder::new("restore-attributes");
+        let file = folder.write("src/data/sub/a.txt", b"a");
+        let sub = folder.path().join("src/data/sub");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        fs::set_permissions(&sub, fs::Permissions::from_mode(0o750)).unwrap();
+        File::open(&sub).unwrap().set_modified(modified).unwrap();
+        let source = folder.path().join("src/data");
+        let config = folder_config(&source, EIncrementalMode::Off);
+
+        let formats = [
+            (EDestinationFormat::Folder, "one"),
+            (EDestinationFormat::Repository, "one"),
+            (EDestinationFormat::Archive(EArchiveFormat::Tar), "one.tar"),
+        ];
+        for (i, (format, backup_name)) in formats.into_iter().enumerate() {
+            let destination = folder.path().join(format!("dest-{}", i));
+            fs::create_dir(&destination).unwrap();
+            let mut details = backup_details(&destination, "one");
+            details.format = format;
+            backup(&config, &details, &mut SNoProgress).unwrap();
+
+            let out = folder.path().join(format!("out-{}", i));
+            let panel = restore_panel(&destination.join(backup_name), &out);
+            let report = recovery(&panel, &mut SNoProgress).unwrap();
+            assert!(report.metadata_failures.is_empty());
+            let target = element_target(&panel, &source.to_string_lossy()).unwrap();
+            for (path, mode) in [
+                (target.join("sub/a.txt"), 0o640),
+                (target.join("sub"), 0o750),
+            ] {
+                let metadata = fs::metadata(&path).unwrap();
+                assert_eq!(metadata.modified().unwrap(), modified, "{}", path.display());
+                assert_eq!(metadata.permissions().mode() & 0o7777, mode);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_holes_and_special_files_are_kept_as_they_are() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::MetadataExt;
+
+        let folder = STestFolder::new("restore-special");
+        let file = folder.write("src/data/a.txt", b"linked");
+        fs::hard_link(&file, folder.path().join("src/data/b.txt")).unwrap();
+        std::os::unix::fs::symlink("a.txt", folder.path().join("src/data/link")).unwrap();
+        let sparse = File::create(folder.path().join("src/data/sparse")).unwrap();
+        sparse.set_len(8 << 20).unwrap();
+        let fifo = folder.path().join("src/data/fifo");
+        let fifo = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        // SAFETY: the path is a nul terminated string that lives through the call
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        let source = folder.path().join("src/data");
+        let config = folder_config(&source, EIncrementalMode::Off);
+
+        let formats = [
+            (EDestinationFormat::Folder, "one"),
+            (EDestinationFormat::Repository, "one"),
+            (EDestinationFormat::Archive(EArchiveFormat::Tar), "one.tar"),
+        ];
+        for (i, (format, backup_name)) in formats.into_iter().enumerate() {
+            let destination = folder.path().join(format!("dest-{}", i));
+            fs::create_dir(&destination).unwrap();
+            let mut details = backup_details(&destination, "one");
+            details.format = format;
+            let snapshot = backup(&config, &details, &mut SNoProgress).unwrap();
+            assert_eq!(snapshot.skipped, [source.join("fifo").to_string_lossy()]);
+
+            let out = folder.path().join(format!("out-{}", i));
+            let panel = restore_panel(&destination.join(backup_name), &out);
+            recovery(&panel, &mut SNoProgress).unwrap();
+            let target = element_target(&panel, &source.to_string_lossy()).unwrap();
+            assert_eq!(
+                fs::read_link(target.join("link")).unwrap(),
+                Path::new("a.txt")
+            );
+            let a = fs::metadata(target.join("a.txt")).unwrap();
+            let b = fs::metadata(target.join("b.txt")).unwrap();
+            assert_eq!((a.dev(), a.ino()), (b.dev(), b.ino()));
+            assert_eq!(fs::read(target.join("b.txt")).unwrap(), b"linked");
+            let sparse = fs::metadata(target.join("sparse")).unwrap();
+            assert_eq!(sparse.len(), 8 << 20);
+            assert!(sparse.blocks() * 512 < sparse.len(), "{}", backup_name);
+            assert!(fs::symlink_metadata(target.join("fifo")).is_err());
+        }
+    }
+
+    #[test]
+    fn restore_writes_what_its_dry_run_plans() {
+        let folder = STestFolder::new("restore-plan");
+        folder.write("src/data/a.txt", b"a");
+        folder.write("src/data/sub/b.txt", b"b");
+        let source = folder.path().join("src/data");
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let config = folder_config(&source, EIncrementalMode::Off);
+        backup(
+            &config,
+            &backup_details(&destination, "one"),
+            &mut SNoProgress,
+        )
+        .unwrap();
+
+        let mut panel = restore_panel(&destination.join("one"), &folder.path().join("out"));
+        panel.conflict_policy = EConflictPolicy::Rename;
+        let target = element_target(&panel, &source.to_string_lossy()).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("a.txt"), b"existing").unwrap();
+
+        let plan = recovery_plan(&panel).unwrap();
+        let planned: Vec<(&str, PathBuf)> = plan
+            .entries
+            .iter()
+            .map(|entry| (entry.action.label(), entry.path.clone()))
+            .collect();
+        assert_eq!(
+            planned,
+            [
+                ("copy", target.join("a.restored.txt")),
+                ("copy", target.join("sub/b.txt")),
+            ]
+        );
+        assert!(!target.join("a.restored.txt").exists() && !target.join("sub").exists());
+
+        recovery(&panel, &mut SNoProgress).unwrap();
+        assert_eq!(fs::read(target.join("a.restored.txt")).unwrap(), b"a");
+        assert_eq!(fs::read(target.join("sub/b.txt")).unwrap(), b"b");
+        assert_eq!(fs::read(target.join("a.txt")).unwrap(), b"existing");
+    }
+
+    #[test]
+    fn moved_file_failing_its_check_stays_in_the_backup() {
+        let folder = STestFolder::new("move-check");
+        let text = b"the same line over and over\n".repeat(1_000);
+        folder.write("src/data/a.txt", &text);
+        folder.write("src/data/b.txt", &text);
+        let source = folder.path().join("src/data");
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let zstd = ECompression::Zstd(ECompression::DEFAULT_ZSTD_LEVEL);
+        let mut details = backup_details(&destination, "one");
+        details.compression = zstd;
+        let config = folder_config(&source, EIncrementalMode::Off);
+        backup(&config, &details, &mut SNoProgress).unwrap();
+        let one = destination.join("one");
+        let stored_a = one.join(format!("data/a.txt{}", zstd.extension()));
+        let stored_b = one.join(format!("data/b.txt{}", zstd.extension()));
+        fs::write(&stored_a, compression::compress(zstd, b"short").unwrap()).unwrap();
+
+        let mut panel = restore_panel(&one, &folder.path().join("out"));
+        panel.file_action = EFileAction::Moved;
+        let report = recovery(&panel, &mut SNoProgress).unwrap();
+        assert_eq!(report.left_behind.len(), 1);
+        assert_eq!(report.left_behind[0].path, stored_a);
+        assert!(report.left_behind[0].reason.contains("bytes instead of"));
+        assert!(stored_a.exists());
+        assert!(!stored_b.exists());
+        let catalog = SCatalog::load(&destination).unwrap();
+        assert!(catalog
+            .snapshots
+            .iter()
+            .any(|snapshot| snapshot.name == "one"));
+        let target = element_target(&panel, &source.to_string_lossy()).unwrap();
+        assert_eq!(fs::read(target.join("b.txt")).unwrap(), text);
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use crate::compression;
+use crate::config::ECompression;
+use crate::error::BackupError;
+use crate::filter::SPathFilter;
+use crate::metadata::{self, SFileMetadata};
+use crate::parallel;
+
+use super::SCopyItem;
+
+/// What a walk over the files of the elements or of a backup folder collects
+#[derive(Default)]
+pub struct SScan {
+    pub items: Vec<SCopyItem>,
+    /// Walk into what symlinks point to instead of keeping the links
+    follow_symlinks: bool,
+    /// `from` of the first file found of each hard link group, by device and inode
+    hardlinks: HashMap<(u64, u64), PathBuf>,
+    /// Folders being walked, a followed symlink back to one of them is a loop
+    walking: HashSet<(u64, u64)>,
+    /// FIFOs, sockets and device nodes, which are not backed up
+    pub skipped: Vec<PathBuf>,
+    /// Indexes into `items` of the files and symlinks found since `read_found` last ran,
+    /// whether each is a symlink. Only their paths are set until then.
+    found: Vec<(usize, bool)>,
+    /// Reading `found`
+    threads: usize,
+}
+
+impl SScan {
+    pub fn new(follow_symlinks: bool, threads: usize) -> SScan {
+        SScan {
+            follow_symlinks,
+            threads,
+            ..SScan::default()
+        }
+    }
+}
+
+/// Only notes the file, `read_found` reads it
+pub fn scan_file(from: &Path, to: &Path, scan: &mut SScan) {
+    scan.found.push((scan.items.len(), false));
+    scan.items.push(SCopyItem::found(from, to));
+}
+
+fn scan_symlink(from: &Path, to: &Path, scan: &mut SScan) {
+    scan.found.push((scan.items.len(), true));
+    scan.items.push(SCopyItem::found(from, to));
+}
+
+/// Reads the files and symlinks found by the walk on `scan.threads` threads. Hard link
+/// groups are formed afterwards in the order of the walk, so its first file is the same
+/// however the threads ran.
+pub fn read_found(scan: &mut SScan) -> Result<(), BackupError> {
+    let found = mem::take(&mut scan.found);
+    let read = parallel::map(&found, scan.threads, |&(index, symlink)| {
+        let item = &scan.items[index];
+        match symlink {
+            true => read_symlink(&item.from, &item.to).map(|item| Some((item, None))),
+            false => read_file(&item.from, &item.to),
+        }
+    });
+    let mut skipped = HashSet::new();
+    for ((index, _), read) in found.into_iter().zip(read) {
+        let Some((mut item, id)) = read? else {
+            skipped.insert(index);
+            scan.skipped.push(scan.items[index].from.clone());
+            continue;
+        };
+        if let Some(id) = id {
+            match scan.hardlinks.get(&id) {
+                Some(first) => item.hardlink = Some(first.clone()),
+                None => {
+                    scan.hardlinks.insert(id, item.from.clone());
+                }
+            }
+        }
+        scan.items[index] = item;
+    }
+    if !skipped.is_empty() {
+        let mut index = 0;
+        scan.items.retain(|_| {
+            index += 1;
+            !skipped.contains(&(index - 1))
+        });
+    }
+    Ok(())
+}
+
+/// A file read by the walk with its device and inode when it has other hard links
+pub type ReadFile = (SCopyItem, Option<(u64, u64)>);
+
+/// `None` when it is no regular file after all
+pub fn read_file(from: &Path, to: &Path) -> Result<Option<ReadFile>, BackupError> {
+    let metadata = fs::metadata(from).map_err(BackupError::io("read metadata", from))?;
+    if !metadata.is_file() {
+        return Ok(None);
+    }
+    let id = metadata::file_id(&metadata).filter(|_| metadata::is_hard_linked(&metadata));
+    let item = SCopyItem {
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+        // Read before the copy, which changes the access time
+        metadata: Some(SFileMetadata::read(from)),
+        sparse: metadata::is_sparse(&metadata),
+        ..SCopyItem::found(from, to)
+    };
+    Ok(Some((item, id)))
+}
+
+fn read_symlink(from: &Path, to: &Path) -> Result<SCopyItem, BackupError> {
+    let target = fs::read_link(from).map_err(BackupError::io("read symlink", from))?;
+    let modified = fs::symlink_metadata(from)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    Ok(SCopyItem {
+        modified,
+        symlink: Some(target),
+        ..SCopyItem::found(from, to)
+    })
+}
+
+/// Files that would barely shrink keep `ECompression::None`
+pub fn set_compression(
+    items: &mut [SCopyItem],
+    compression: ECompression,
+    threads: usize,
+) -> Result<(), BackupError> {
+    if compression.is_none() {
+        return Ok(());
+    }
+    let chosen = parallel::map(items, threads, |item| {
+        if item.is_dir || item.symlink.is_some() {
+            return Ok(ECompression::None);
+        }
+        compression::choose(compression, &item.from)
+            .map_err(BackupError::io("read file", &item.from))
+    });
+    for (item, chosen) in items.iter_mut().zip(chosen) {
+        item.write_as.compression = chosen?;
+    }
+    Ok(())
+}
+
+/// Entries rejected by `filter` are left out, excluded folders are not walked into.
+/// Symlinks are not walked into unless `scan.follow_symlinks` is set.
+pub fn scan_dir(
+    from: &Path,
+    to: &Path,
+    mut filter: Option<&mut SPathFilter>,
+    scan: &mut SScan,
+) -> Result<(), BackupError> {
+    let metadata = fs::metadata(from).map_err(BackupError::io("read metadata", from))?;
+    let id = metadata::file_id(&metadata);
+    if id.is_some_and(|id| !scan.walking.insert(id)) {
+        return Ok(());
+    }
+    scan.items.push(SCopyItem {
+        modified: metadata.modified().ok(),
+        is_dir: true,
+        metadata: Some(SFileMetadata::read(from)),
+        ..SCopyItem::found(from, to)
+    });
+
+    let entered = filter.as_mut().is_some_and(|filter| filter.enter_dir(from));
+
+    // Sorted so that the items, and the manifest, come out the same on every run
+    let mut entries = fs::read_dir(from)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .map_err(BackupError::io("read folder", from))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let to = to.join(entry.file_name());
+        let mut file_type = entry
+            .file_type()
+            .map_err(BackupError::io("read metadata", &path))?;
+        if file_type.is_symlink() && scan.follow_symlinks {
+            // A broken link is kept as a link
+            if let Ok(metadata) = fs::metadata(&path) {
+                file_type = metadata.file_type();
+            }
+        }
+        let is_dir = file_type.is_dir();
+
+        if filter
+            .as_ref()
+            .is_some_and(|filter| filter.is_excluded(&path, is_dir))
+        {
+            continue;
+        }
+
+        if is_dir {
+            scan_dir(&path, &to, filter.as_deref_mut(), scan)?;
+        } else if file_type.is_symlink() {
+            scan_symlink(&path, &to, scan);
+        } else if file_type.is_file() {
+            scan_file(&path, &to, scan);
+        } else {
+            scan.skipped.push(path);
+        }
+    }
+
+    if let Some(filter) = filter {
+        filter.leave_dir(entered);
+    }
+    if let Some(id) = id {
+        scan.walking.remove(&id);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+
+use crate::archive::{self, EArchiveFormat, SArchiveEntry};
+use crate::catalog::COMMIT_MARKER;
+use crate::compression;
+use crate::encryption::{self, EKeySource, SKey, ENCRYPTION_FILE};
+use crate::error::BackupError;
+use crate::journal::JOURNAL_FILE;
+use crate::manifest::{self, SManifest, MANIFEST_FILE};
+use crate::progress::{EProgressEvent, ProgressObserver, SProgress};
+use crate::repository::{SRepository, STreeEntry};
+use crate::storage::{SLocalStorage, StorageBackend};
+
+use super::{
+    is_archive_metadata, link_hash, SHashingReader, SStoredFormat, BACKUP_CONFIG_FILE,
+    COPY_BUFFER_SIZE, INCOMPLETE_MARKER,
+};
+
+/// What `verify` found in a backup folder, paths are relative to it
+pub struct SVerifyReport {
+    pub checked: u64,
+    pub missing: Vec<String>,
+    pub corrupted: Vec<String>,
+    /// Files in the folder the manifest does not list
+    pub extra: Vec<String>,
+}
+
+impl SVerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// Re-hashes every file the manifest of `backup_folder` lists and looks for files it does not list
+pub fn verify(
+    backup_folder: &Path,
+    key: Option<&EKeySource>,
+    observer: &mut dyn ProgressObserver,
+) -> Result<SVerifyReport, BackupError> {
+    if let Some((root, name)) = SRepository::locate_snapshot(backup_folder) {
+        let key = encryption::unlock(&*root, key)?;
+        return verify_repository_snapshot(root, &name, key, observer);
+    }
+
+    if let Some(format) = EArchiveFormat::of_file(backup_folder) {
+        return verify_archive(backup_folder, format, observer);
+    }
+
+    let key = encryption::unlock(&SLocalStorage::new(backup_folder), key)?;
+    let manifest = SManifest::load(backup_folder, key.as_ref())?;
+    let bytes_total = manifest.files.iter().map(|entry| entry.size).sum();
+    let mut progress = SProgress::new(manifest.files.len() as u64, bytes_total);
+    let mut report = SVerifyReport {
+        checked: 0,
+        missing: Vec::new(),
+        corrupted: Vec::new(),
+        extra: Vec::new(),
+    };
+    observer.on_progress(EProgressEvent::Started, &progress);
+
+    for entry in &manifest.files {
+        if observer.is_cancelled() {
+            return Err(BackupError::Cancelled);
+        }
+
+        // Found where a restore reads it, so a backup that verifies can be restored
+        let path = entry.stored_path(backup_folder);
+        progress.current_path = path.clone();
+
+        if entry.symlink.is_some() {
+            match fs::read_link(&path) {
+                Ok(target) if link_hash(&target) == entry.hash => {}
+                Ok(_) => report.corrupted.push(entry.path.clone()),
+                Err(_) => report.missing.push(entry.path.clone()),
+            }
+        } else if !path.is_file() {
+            report.missing.push(entry.path.clone());
+            progress.bytes_done += entry.size;
+        } else {
+            let bytes_before = progress.bytes_done;
+            let stored_as = SStoredFormat {
+                compression: entry.compression,
+                key: key.clone(),
+            };
+            let result = hash_file_with_progress(&path, &stored_as, &mut progress, observer);
+            let size = progress.bytes_done - bytes_before;
+            let intact = match result {
+                Ok(hash) => size == entry.size && hash.to_hex().as_str() == entry.hash,
+                // A damaged compressed or encrypted file fails to decode
+                Err(BackupError::Io { .. }) if !stored_as.is_plain() => false,
+                Err(e) => return Err(e),
+            };
+            if !intact {
+                report.corrupted.push(entry.path.clone());
+                progress.bytes_done = bytes_before + entry.size;
+            }
+        }
+        report.checked += 1;
+        progress.files_done += 1;
+        observer.on_progress(EProgressEvent::FileDone, &progress);
+    }
+
+    let listed: HashSet<&str> = manifest
+        .files
+        .iter()
+        .filter(|entry| entry.snapshot.is_none())
+        .map(|entry| entry.path.as_str())
+        .collect();
+    let mut files = Vec::new();
+    list_files(backup_folder, &mut files)?;
+    for file in files {
+        let Some(relative) = manifest::relative_path(&file, backup_folder) else {
+            continue;
+        };
+        let is_metadata = [
+            BACKUP_CONFIG_FILE,
+            MANIFEST_FILE,
+            INCOMPLETE_MARKER,
+            COMMIT_MARKER,
+            JOURNAL_FILE,
+            ENCRYPTION_FILE,
+        ]
+        .contains(&relative.as_str());
+        if !is_metadata && !listed.contains(relative.as_str()) {
+            report.extra.push(relative);
+        }
+    }
+    report.extra.sort();
+
+    progress.finish();
+    observer.on_progress(EProgressEvent::Finished, &progress);
+    Ok(report)
+}
+
+/// Reads back every chunk of a repository snapshot. A file is missing when one of its
+/// chunks is not in any pack and corrupted when one no longer matches its hash.
+fn verify_repository_snapshot(
+    root: Arc<dyn StorageBackend>,
+    name: &str,
+    key: Option<SKey>,
+    observer: &mut dyn ProgressObserver,
+) -> Result<SVerifyReport, BackupError> {
+    let snapshot_folder = root.location(name);
+    let repository = SRepository::open(root, key)?;
+    let mut files: Vec<(String, STreeEntry)> =
+        repository.snapshot_files(name)?.into_iter().collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let bytes_total = files.iter().map(|(_, entry)| entry.size).sum();
+    let mut progress = SProgress::new(files.len() as u64, bytes_total);
+    let mut report = SVerifyReport {
+        checked: 0,
+        missing: Vec::new(),
+        corrupted: Vec::new(),
+        extra: Vec::new(),
+    };
+    observer.on_progress(EProgressEvent::Started, &progress);
+
+    for (path, entry) in &files {
+        if observer.is_cancelled() {
+            return Err(BackupError::Cancelled);
+        }
+
+        progress.current_path = snapshot_folder.join(path);
+        let bytes_before = progress.bytes_done;
+        let mut size = 0;
+        let mut missing = false;
+        let mut corrupted = false;
+        for hash in &entry.chunks {
+            match repository.read_chunk(hash) {
+                Ok(data) => size += data.len() as u64,
+                Err(BackupError::MissingChunk { .. }) => missing = true,
+                Err(BackupError::Io { source, .. }) if source.kind() == ErrorKind::NotFound => {
+                    missing = true
+                }
+                Err(BackupError::Io { source, .. })
+                    if source.kind() == ErrorKind::UnexpectedEof =>
+                {
+                    corrupted = true
+                }
+                Err(BackupError::CorruptedObject { .. }) => corrupted = true,
+                Err(e) => return Err(e),
+            }
+            if missing || corrupted {
+                break;
+            }
+            progress.bytes_done = bytes_before + size;
+            observer.on_progress(EProgressEvent::Bytes, &progress);
+        }
+        if missing {
+            report.missing.push(path.clone());
+        } else if corrupted || size != entry.size {
+            report.corrupted.push(path.clone());
+        }
+        progress.bytes_done = bytes_before + entry.size;
+        report.checked += 1;
+        progress.files_done += 1;
+        observer.on_progress(EProgressEvent::FileDone, &progress);
+    }
+
+    progress.finish();
+    observer.on_progress(EProgressEvent::Finished, &progress);
+    Ok(report)
+}
+
+/// Hashes every file of an archive and compares them with the manifest it ends with
+fn verify_archive(
+    archive_path: &Path,
+    format: EArchiveFormat,
+    observer: &mut dyn ProgressObserver,
+) -> Result<SVerifyReport, BackupError> {
+    let listed = archive::list(archive_path, format)
+        .map_err(BackupError::io("read archive", archive_path))?;
+    let files: Vec<&SArchiveEntry> = listed
+        .iter()
+        .filter(|entry| !entry.is_dir && !is_archive_metadata(&entry.name))
+        .collect();
+    let bytes_total = files.iter().map(|entry| entry.size).sum();
+    let mut progress = SProgress::new(files.len() as u64, bytes_total);
+    observer.on_progress(EProgressEvent::Started, &progress);
+
+    // Size and hash of each file, `None` when it could not be read back
+    let mut found: HashMap<String, Option<(u64, String)>> = HashMap::new();
+    let mut manifest: Option<SManifest> = None;
+    archive::read(archive_path, format, |entry, contents| {
+        if observer.is_cancelled() {
+            return Err(BackupError::Cancelled);
+        }
+        if entry.is_dir || entry.name == BACKUP_CONFIG_FILE {
+            return Ok(());
+        }
+        if entry.name == MANIFEST_FILE {
+            manifest = Some(read_archived_toml(archive_path, entry, contents)?);
+            return Ok(());
+        }
+
+        progress.current_path = archive_path.join(&entry.name);
+        let bytes_before = progress.bytes_done;
+        if let Some(target) = &entry.symlink {
+            found.insert(entry.name.clone(), Some((0, link_hash(Path::new(target)))));
+            progress.bytes_done = bytes_before + entry.size;
+            progress.files_done += 1;
+            observer.on_progress(EProgressEvent::FileDone, &progress);
+            return Ok(());
+        }
+        let mut reader = SHashingReader::new(contents, &mut progress, &mut *observer);
+        // Zip checks the checksum of each entry as it is read
+        let stored = io::copy(&mut reader, &mut io::sink())
+            .ok()
+            .map(|size| (size, reader.finalize().to_hex().to_string()));
+        found.insert(entry.name.clone(), stored);
+        progress.bytes_done = bytes_before + entry.size;
+        progress.files_done += 1;
+        observer.on_progress(EProgressEvent::FileDone, &progress);
+        Ok(())
+    })?;
+
+    let manifest = manifest
+        .ok_or_else(|| BackupError::invalid_path("find manifest.toml in archive", archive_path))?;
+    let mut report = SVerifyReport {
+        checked: 0,
+        missing: Vec::new(),
+        corrupted: Vec::new(),
+        extra: Vec::new(),
+    };
+    for entry in &manifest.files {
+        match found.remove(&entry.path) {
+            None => report.missing.push(entry.path.clone()),
+            Some(Some((size, hash))) if size == entry.size && hash == entry.hash => {}
+            Some(_) => report.corrupted.push(entry.path.clone()),
+        }
+        report.checked += 1;
+    }
+    report.extra = found.into_keys().collect();
+    report.extra.sort();
+
+    progress.finish();
+    observer.on_progress(EProgressEvent::Finished, &progress);
+    Ok(report)
+}
+
+fn list_files(folder: &Path, files: &mut Vec<PathBuf>) -> Result<(), BackupError> {
+    for entry in fs::read_dir(folder).map_err(BackupError::io("read folder", folder))? {
+        let entry = entry.map_err(BackupError::io("read folder", folder))?;
+        let path = entry.path();
+        // Symlinks in a backup are files of their own, not folders to walk into
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        if is_dir {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Hashes the original contents of a file stored as `stored_as`
+fn hash_file_with_progress(
+    path: &Path,
+    stored_as: &SStoredFormat,
+    progress: &mut SProgress,
+    observer: &mut dyn ProgressObserver,
+) -> Result<blake3::Hash, BackupError> {
+    let file = File::open(path).map_err(BackupError::io("open file", path))?;
+    let file = encryption::decryptor(stored_as.key.as_ref(), file);
+    let mut file = compression::decoder(stored_as.compression, file)
+        .map_err(BackupError::io("open file", path))?;
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut hasher = blake3::Hasher::new();
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(BackupError::io("read file", path))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        observer.on_transfer(read as u64);
+        progress.bytes_done += read as u64;
+        observer.on_progress(EProgressEvent::Bytes, progress);
+    }
+    Ok(hasher.finalize())
+}
+
+fn read_archived_toml<T: DeserializeOwned>(
+    archive_path: &Path,
+    entry: &SArchiveEntry,
+    contents: &mut dyn Read,
+) -> Result<T, BackupError> {
+    let path = archive_path.join(&entry.name);
+    let mut text = String::new();
+    contents
+        .read_to_string(&mut text)
+        .map_err(BackupError::io("read archive", &path))?;
+    toml::from_str(&text).map_err(|source| BackupError::ParseToml { path, source })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup_service::recovery;
+    use crate::testing::{restore_panel, skip_backups, SNoProgress, STestFolder};
+
+    #[test]
+    fn verify_of_skip_mode_backup_checks_files_where_restore_reads_them() {
+        let folder = STestFolder::new("skip-verify");
+        let (_, destination) = skip_backups(&folder);
+        let two = destination.join("two");
+        let report = verify(&two, None, &mut SNoProgress).unwrap();
+        assert_eq!(report.checked, 2);
+        assert!(report.missing.is_empty() && report.corrupted.is_empty());
+
+        fs::remove_file(destination.join("one/data/sub/b.txt")).unwrap();
+        let report = verify(&two, None, &mut SNoProgress).unwrap();
+        assert_eq!(report.missing, ["data/sub/b.txt"]);
+        let panel = restore_panel(&two, &folder.path().join("out"));
+        assert!(recovery(&panel, &mut SNoProgress).is_err());
+    }
+}
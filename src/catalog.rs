@@ -0,0 +1,175 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::error::BackupError;
+use crate::storage::{SLocalStorage, StorageBackend};
+
+/// Lists every backup made into a destination folder
+pub const CATALOG_FILE: &str = "catalog.toml";
+/// Written into a backup folder last, once its run is over
+pub const COMMIT_MARKER: &str = ".commit";
+pub const DEFAULT_NAME_TEMPLATE: &str = "{profile}-{date:%Y%m%d-%H%M%S}";
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ESnapshotStatus {
+    Complete,
+    /// Cancelled or failed, the folder holds only part of the files
+    Incomplete,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SSnapshotInfo {
+    /// Folder name inside the destination
+    pub name: String,
+    /// `SBackupConfig::profile_name` of the config it was made from
+    #[serde(default)]
+    pub profile: String,
+    pub time: DateTime<Local>,
+    /// Path of the config it was made from, empty in an encrypted destination
+    pub source_config: String,
+    pub files: u64,
+    pub size: u64,
+    pub status: ESnapshotStatus,
+    /// FIFOs, sockets and device nodes found in the elements and left out, only kept in the
+    /// catalog of a destination that is not encrypted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+}
+
+/// How the run that wrote a backup folder ended
+#[derive(Serialize, Deserialize)]
+pub struct SCommitMarker {
+    pub status: ESnapshotStatus,
+    pub time: DateTime<Local>,
+}
+
+impl SCommitMarker {
+    /// `None` for folders written before backups were committed
+    pub fn load(folder: &Path) -> Result<Option<SCommitMarker>, BackupError> {
+        let path = folder.join(COMMIT_MARKER);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents =
+            fs::read_to_string(&path).map_err(BackupError::io("read commit marker", &path))?;
+        toml::from_str(&contents)
+            .map(Some)
+            .map_err(|source| BackupError::ParseToml { path, source })
+    }
+
+    /// Flushed to the disk before it returns
+    pub fn save(&self, folder: &Path) -> Result<(), BackupError> {
+        let path = folder.join(COMMIT_MARKER);
+        let contents = toml::to_string(self).map_err(|source| BackupError::SerializeToml {
+            path: path.clone(),
+            source,
+        })?;
+        File::create(&path)
+            .and_then(|mut file| {
+                file.write_all(contents.as_bytes())?;
+                file.sync_all()
+            })
+            .map_err(BackupError::io("write commit marker", &path))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct SCatalog {
+    #[serde(default)]
+    pub snapshots: Vec<SSnapshotInfo>,
+}
+
+impl SCatalog {
+    /// A destination without a catalog has an empty one
+    pub fn load(destination: &Path) -> Result<SCatalog, BackupError> {
+        SCatalog::load_from(&SLocalStorage::new(destination))
+    }
+
+    pub fn load_from(destination: &dyn StorageBackend) -> Result<SCatalog, BackupError> {
+        if destination.stat(CATALOG_FILE)?.is_none() {
+            return Ok(SCatalog::default());
+        }
+        let contents = destination.get(CATALOG_FILE)?;
+        toml::from_str(&String::from_utf8_lossy(&contents)).map_err(|source| {
+            BackupError::ParseToml {
+                path: destination.location(CATALOG_FILE),
+                source,
+            }
+        })
+    }
+
+    pub fn save(&self, destination: &dyn StorageBackend) -> Result<(), BackupError> {
+        let contents = toml::to_string(self).map_err(|source| BackupError::SerializeToml {
+            path: destination.location(CATALOG_FILE),
+            source,
+        })?;
+        destination.put(CATALOG_FILE, contents.as_bytes())
+    }
+
+    /// Replaces the entry of a backup made again into the same folder
+    pub fn record(&mut self, snapshot: SSnapshotInfo) {
+        self.snapshots.retain(|known| known.name != snapshot.name);
+        self.snapshots.push(snapshot);
+        self.snapshots.sort_by_key(|snapshot| snapshot.time);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.snapshots.retain(|snapshot| snapshot.name != name);
+    }
+
+    /// Complete snapshots, newest first
+    pub fn complete_snapshots(&self) -> impl Iterator<Item = &SSnapshotInfo> {
+        self.snapshots
+            .iter()
+            .rev()
+            .filter(|snapshot| snapshot.status == ESnapshotStatus::Complete)
+    }
+}
+
+/// Fills `{profile}`, `{date}` and `{date:<strftime format>}` into a backup name template
+pub fn expand_name(
+    template: &str,
+    profile: &str,
+    time: &DateTime<Local>,
+) -> Result<String, BackupError> {
+    let invalid = || BackupError::InvalidTemplate {
+        template: template.to_string(),
+    };
+
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(invalid)? + start;
+        let placeholder = &rest[start + 1..end];
+
+        if placeholder == "profile" {
+            name.push_str(profile);
+        } else if placeholder == "date" || placeholder.starts_with("date:") {
+            let format = placeholder
+                .strip_prefix("date:")
+                .unwrap_or(DEFAULT_DATE_FORMAT);
+            // Formatting a broken format string panics, so it is checked first
+            let items: Vec<Item> = StrftimeItems::new(format).collect();
+            if items.iter().any(|item| matches!(item, Item::Error)) {
+                return Err(invalid());
+            }
+            name.push_str(&time.format_with_items(items.into_iter()).to_string());
+        } else {
+            return Err(invalid());
+        }
+        rest = &rest[end + 1..];
+    }
+    name.push_str(rest);
+
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(invalid());
+    }
+    Ok(name)
+}
@@ -0,0 +1,852 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+
+use crate::archive::EArchiveFormat;
+use crate::backup_service;
+use crate::catalog::{ESnapshotStatus, SCatalog, DEFAULT_NAME_TEMPLATE};
+use crate::config::{ECompression, EIncrementalMode, SBackupConfig, SRetention};
+use crate::encryption::{self, EKeyDerivation, EKeySource, SEncryptionHeader};
+use crate::error::BackupError;
+use crate::manifest::MANIFEST_FILE;
+use crate::plan::SPlan;
+use crate::progress::{format_duration, format_size, parse_size, EProgressEvent, SProgress};
+use crate::repository::{EDestinationFormat, SRepository};
+use crate::retention;
+use crate::storage;
+use crate::ui::backup::SBackupUI;
+use crate::ui::recovery::{EConflictPolicy, EFileAction, ERestoreLayout, SRecoveryPanel};
+
+/// Exit code for a backup or restore that started but failed, or a backup that failed verification
+const EXIT_FAILURE: u8 = 1;
+/// Exit code for a bad config file, destination or backup folder.
+/// Usage errors are reported by clap with code 2.
+const EXIT_INVALID_INPUT: u8 = 3;
+/// Read instead of asking for the passphrase, for scripts
+const PASSPHRASE_ENV: &str = "BACKUP_NF_PASSPHRASE";
+
+/// Backup and restore files. Opens the interactive menu when no command is given
+#[derive(Parser)]
+#[command(name = "backup-nf", version, about)]
+pub struct SCli {
+    #[command(subcommand)]
+    pub command: Option<ECommand>,
+}
+
+#[derive(Subcommand)]
+pub enum ECommand {
+    /// Copy every element of a config into DEST/NAME
+    Backup {
+        /// Path to backup_config.toml
+        #[arg(long)]
+        config: String,
+        /// Folder the backup folder is created in
+        #[arg(long)]
+        dest: String,
+        /// Name of the backup folder, `{profile}`, `{date}` and `{date:<strftime format>}` are filled in
+        #[arg(long, default_value = DEFAULT_NAME_TEMPLATE)]
+        name: String,
+        /// Overrides the config's handling of files unchanged since the previous backup
+        #[arg(long, value_enum)]
+        incremental: Option<EIncrementalArg>,
+        /// Compare file contents too when looking for unchanged files
+        #[arg(long)]
+        compare_hash: bool,
+        /// Files read and copied at once, overrides the config. 0 for one per CPU
+        #[arg(long)]
+        threads: Option<usize>,
+        /// How the backup is stored, a destination that already is a repository stays one.
+        /// Archives are written to DEST/NAME.tar, NAME.tar.zst or NAME.zip
+        #[arg(long, value_enum)]
+        format: Option<EFormatArg>,
+        /// Compression for elements that do not set their own: none, gzip, zstd:<level> or lz4
+        #[arg(long, default_value = "none")]
+        compression: String,
+        /// Encrypt a new destination with a passphrase, asked for or taken from BACKUP_NF_PASSPHRASE
+        #[arg(long, conflicts_with = "key_file")]
+        encrypt: bool,
+        /// Encrypt a new destination with the contents of this file, or unlock one encrypted with it
+        #[arg(long)]
+        key_file: Option<PathBuf>,
+        /// Only list the files the backup would copy, overwrite or skip
+        #[arg(long)]
+        dry_run: bool,
+        /// Start over instead of continuing an interrupted folder backup of the same config
+        /// into DEST, which is deleted
+        #[arg(long)]
+        no_resume: bool,
+        #[command(flatten)]
+        throttle: SThrottleArgs,
+    },
+    /// Put the files of a backup folder back to their original paths
+    Restore {
+        /// Backup folder containing backup_config.toml, a backup archive, or DEST/NAME for a repository snapshot
+        #[arg(long)]
+        from: String,
+        /// Copy files out of the backup or move them and delete the backup
+        #[arg(long, value_enum, default_value_t = ERestoreMode::Copy)]
+        mode: ERestoreMode,
+        /// Restore under this folder instead of to the original paths
+        #[arg(long)]
+        to: Option<PathBuf>,
+        /// Put each backed up file or folder right under --to instead of under its full original path
+        #[arg(long, requires = "to")]
+        flat: bool,
+        /// What happens to files already at the paths the backup restores to
+        #[arg(long, value_enum, default_value_t = EConflictArg::Replace)]
+        on_conflict: EConflictArg,
+        /// Only restore this file or folder, a `/` separated path inside the backup such as
+        /// docs/report.txt. Globs are allowed and the option can be repeated
+        #[arg(long = "path", value_name = "PATH")]
+        paths: Vec<String>,
+        /// Key file of an encrypted backup, a passphrase is asked for otherwise
+        #[arg(long)]
+        key_file: Option<PathBuf>,
+        /// Only list the files the restore would copy, overwrite, skip or delete
+        #[arg(long)]
+        dry_run: bool,
+        /// Files copied at once out of a backup folder, 0 for one per CPU
+        #[arg(long, default_value_t = 0)]
+        threads: usize,
+        #[command(flatten)]
+        throttle: SThrottleArgs,
+    },
+    /// Re-hash a backup folder against its manifest and report missing, corrupted and extra files
+    Verify {
+        /// Backup folder containing manifest.toml, a backup archive, or DEST/NAME for a repository snapshot
+        #[arg(long)]
+        backup: String,
+        /// Key file of an encrypted backup, a passphrase is asked for otherwise
+        #[arg(long)]
+        key_file: Option<PathBuf>,
+    },
+    /// Delete the snapshots of a config's profile that its retention rules do not keep
+    Prune {
+        /// Path to backup_config.toml holding the retention rules
+        #[arg(long)]
+        config: String,
+        /// Folder the backups were created in
+        #[arg(long)]
+        dest: String,
+        /// Overrides the config's retention rules, e.g. "last=7, daily=7, weekly=4, monthly=12, size=50GiB"
+        #[arg(long)]
+        keep: Option<String>,
+        /// Only show which snapshots would be deleted
+        #[arg(long)]
+        dry_run: bool,
+        /// Key file of an encrypted destination, a passphrase is asked for otherwise
+        #[arg(long)]
+        key_file: Option<PathBuf>,
+    },
+    /// List the backups recorded in the catalog of a destination folder
+    Snapshots {
+        /// Folder the backups were created in
+        #[arg(long)]
+        dest: String,
+    },
+}
+
+/// How much a backup or restore may slow the machine down
+#[derive(Args)]
+pub struct SThrottleArgs {
+    /// Bytes per second read and written, e.g. 20MiB, overrides the config. 0 for no limit
+    #[arg(long, value_name = "SIZE", value_parser = parse_bandwidth)]
+    bwlimit: Option<u64>,
+    /// Read and write only while no other program uses the disk, Linux only
+    #[arg(long)]
+    idle_io: bool,
+    /// Added to the CPU nice level, overrides the config
+    #[arg(long, allow_negative_numbers = true)]
+    nice: Option<i32>,
+}
+
+fn parse_bandwidth(text: &str) -> Result<u64, String> {
+    parse_size(text).ok_or_else(|| "expected a size such as 512KiB or 20MiB".to_string())
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum EIncrementalArg {
+    Off,
+    Hardlink,
+    Skip,
+}
+
+impl From<EIncrementalArg> for EIncrementalMode {
+    fn from(mode: EIncrementalArg) -> EIncrementalMode {
+        match mode {
+            EIncrementalArg::Off => EIncrementalMode::Off,
+            EIncrementalArg::Hardlink => EIncrementalMode::HardLink,
+            EIncrementalArg::Skip => EIncrementalMode::Skip,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, ValueEnum)]
+pub enum EFormatArg {
+    Folder,
+    Repository,
+    Tar,
+    #[value(name = "tar.zst")]
+    TarZst,
+    Zip,
+}
+
+impl From<EFormatArg> for EDestinationFormat {
+    fn from(format: EFormatArg) -> EDestinationFormat {
+        match format {
+            EFormatArg::Folder => EDestinationFormat::Folder,
+            EFormatArg::Repository => EDestinationFormat::Repository,
+            EFormatArg::Tar => EDestinationFormat::Archive(EArchiveFormat::Tar),
+            EFormatArg::TarZst => EDestinationFormat::Archive(EArchiveFormat::TarZst),
+            EFormatArg::Zip => EDestinationFormat::Archive(EArchiveFormat::Zip),
+        }
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum ERestoreMode {
+    Copy,
+    Move,
+}
+
+impl From<ERestoreMode> for EFileAction {
+    fn from(mode: ERestoreMode) -> EFileAction {
+        match mode {
+            ERestoreMode::Copy => EFileAction::Copied,
+            ERestoreMode::Move => EFileAction::Moved,
+        }
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum EConflictArg {
+    Replace,
+    Skip,
+    /// Replace only files older than the backup's copy
+    KeepNewer,
+    /// Restore next to the existing file as NAME.restored.EXT
+    Rename,
+}
+
+impl From<EConflictArg> for EConflictPolicy {
+    fn from(policy: EConflictArg) -> EConflictPolicy {
+        match policy {
+            EConflictArg::Replace => EConflictPolicy::Replace,
+            EConflictArg::Skip => EConflictPolicy::Skip,
+            EConflictArg::KeepNewer => EConflictPolicy::KeepNewer,
+            EConflictArg::Rename => EConflictPolicy::Rename,
+        }
+    }
+}
+
+pub fn run(command: ECommand) -> ExitCode {
+    match command {
+        ECommand::Backup {
+            config,
+            dest,
+            name,
+            incremental,
+            compare_hash,
+            threads,
+            format,
+            compression,
+            encrypt,
+            key_file,
+            dry_run,
+            no_resume,
+            throttle,
+        } => backup(
+            config,
+            dest,
+            name,
+            incremental,
+            compare_hash,
+            threads,
+            format,
+            compression,
+            encrypt,
+            key_file,
+            dry_run,
+            no_resume,
+            throttle,
+        ),
+        ECommand::Restore {
+            from,
+            mode,
+            to,
+            flat,
+            on_conflict,
+            paths,
+            key_file,
+            dry_run,
+            threads,
+            throttle,
+        } => restore(
+            from,
+            mode,
+            to,
+            flat,
+            on_conflict,
+            paths,
+            key_file,
+            dry_run,
+            threads,
+            throttle,
+        ),
+        ECommand::Verify { backup, key_file } => verify(backup, key_file),
+        ECommand::Prune {
+            config,
+            dest,
+            keep,
+            dry_run,
+            key_file,
+        } => prune(config, dest, keep, dry_run, key_file),
+        ECommand::Snapshots { dest } => snapshots(dest),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn backup(
+    config_path: String,
+    dest: String,
+    name: String,
+    incremental: Option<EIncrementalArg>,
+    compare_hash: bool,
+    threads: Option<usize>,
+    format: Option<EFormatArg>,
+    compression: String,
+    encrypt: bool,
+    key_file: Option<PathBuf>,
+    dry_run: bool,
+    no_resume: bool,
+    throttle: SThrottleArgs,
+) -> ExitCode {
+    if !Path::new(&config_path).is_file() {
+        return invalid_input(format!("Config file not found: {}", config_path));
+    }
+    if !Path::new(&dest).is_dir() {
+        return invalid_input(format!("Destination folder not found: {}", dest));
+    }
+    if name.is_empty() {
+        return invalid_input("Backup folder name is empty".to_string());
+    }
+    if format == Some(EFormatArg::Folder)
+        && SRepository::is_repository(&*storage::open(Path::new(&dest)))
+    {
+        return invalid_input(format!("{} is a repository", dest));
+    }
+
+    let compression = match ECompression::parse(&compression) {
+        Ok(compression) => compression,
+        Err(e) => return invalid_input(e.to_string()),
+    };
+
+    let mut config = match SBackupConfig::from_file(config_path) {
+        Ok(config) => config,
+        Err(e) => return invalid_input(e.to_string()),
+    };
+    if let Some(incremental) = incremental {
+        config.incremental = incremental.into();
+    }
+    config.compare_hash |= compare_hash;
+    if let Some(threads) = threads {
+        config.threads = threads;
+    }
+    config.idle_io |= throttle.idle_io;
+    if let Some(nice) = throttle.nice {
+        config.nice = nice;
+    }
+    if config.elements.is_empty() {
+        return invalid_input("Config has no elements".to_string());
+    }
+    let key = match key_source(Path::new(&dest), key_file, encrypt) {
+        Ok(key) => key,
+        Err(code) => return code,
+    };
+
+    let mut details = SBackupUI {
+        folder_name: name,
+        folder_path: dest,
+        format: format.map(Into::into).unwrap_or_default(),
+        compression,
+        key,
+        resume: None,
+        bandwidth_limit: throttle.bwlimit,
+    };
+    let resumable = match backup_service::resumable_backup(&config, &details) {
+        Ok(resumable) => resumable,
+        Err(e) => return backup_failure(e),
+    };
+    if let Some(resumable) = resumable {
+        if !no_resume {
+            println!(
+                "Resuming {}, {} files ({}) were copied before it was interrupted",
+                resumable.name,
+                resumable.files,
+                format_size(resumable.size)
+            );
+            details.resume = Some(resumable.name);
+        } else if !dry_run {
+            if let Err(e) = backup_service::discard_resumable(&details, &resumable.name) {
+                return failure(e);
+            }
+        }
+    }
+    if dry_run {
+        return match backup_service::backup_plan(&config, &details) {
+            Ok(plan) => print_plan(&plan),
+            Err(e) => backup_failure(e),
+        };
+    }
+    let mut summary = None;
+    let mut observer = |event: EProgressEvent, progress: &SProgress| {
+        if event == EProgressEvent::Finished {
+            summary = Some(format_summary(progress));
+        }
+    };
+    let snapshot = match backup_service::backup(&config, &details, &mut observer) {
+        Ok(snapshot) => snapshot,
+        Err(e) => return backup_failure(e),
+    };
+
+    println!(
+        "Backup created: {} ({})",
+        Path::new(&details.folder_path)
+            .join(&snapshot.name)
+            .display(),
+        summary.unwrap_or_default()
+    );
+    if !snapshot.skipped.is_empty() {
+        eprintln!(
+            "Skipped {} FIFOs, sockets or device nodes:",
+            snapshot.skipped.len()
+        );
+        for path in &snapshot.skipped {
+            eprintln!("  {}", path);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// A bad name template or key is the caller's fault, anything else failed the backup
+fn backup_failure(e: BackupError) -> ExitCode {
+    match e {
+        BackupError::InvalidTemplate { .. }
+        | BackupError::WrongKey { .. }
+        | BackupError::KeyRequired { .. } => invalid_input(e.to_string()),
+        e => failure(e),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restore(
+    from: String,
+    mode: ERestoreMode,
+    to: Option<PathBuf>,
+    flat: bool,
+    on_conflict: EConflictArg,
+    paths: Vec<String>,
+    key_file: Option<PathBuf>,
+    dry_run: bool,
+    threads: usize,
+    throttle: SThrottleArgs,
+) -> ExitCode {
+    if !Path::new(&from).join("backup_config.toml").is_file()
+        && !is_repository_snapshot(&from)
+        && !is_archive(&from)
+    {
+        return invalid_input(format!("No backup_config.toml in {}", from));
+    }
+    let key = match key_source(Path::new(&from), key_file, false) {
+        Ok(key) => key,
+        Err(code) => return code,
+    };
+
+    let mut recovery = SRecoveryPanel::new();
+    recovery.backup_folder = from;
+    recovery.file_action = mode.into();
+    recovery.restore_root = to;
+    if flat {
+        recovery.restore_layout = ERestoreLayout::Flat;
+    }
+    recovery.conflict_policy = on_conflict.into();
+    recovery.selection = paths;
+    recovery.key = key;
+    recovery.threads = threads;
+    recovery.bandwidth_limit = throttle.bwlimit.filter(|&limit| limit > 0);
+    recovery.idle_io = throttle.idle_io;
+    recovery.nice = throttle.nice.unwrap_or(0);
+    if dry_run {
+        return match backup_service::recovery_plan(&recovery) {
+            Ok(plan) => print_plan(&plan),
+            Err(
+                e @ (BackupError::WrongKey { .. }
+                | BackupError::KeyRequired { .. }
+                | BackupError::InvalidPattern { .. }),
+            ) => invalid_input(e.to_string()),
+            Err(e) => failure(e),
+        };
+    }
+    let mut summary = None;
+    let mut observer = |event: EProgressEvent, progress: &SProgress| {
+        if event == EProgressEvent::Finished {
+            summary = Some(format_summary(progress));
+        }
+    };
+    let report = match backup_service::recovery(&recovery, &mut observer) {
+        Ok(report) => report,
+        Err(
+            e @ (BackupError::WrongKey { .. }
+            | BackupError::KeyRequired { .. }
+            | BackupError::InvalidPattern { .. }),
+        ) => return invalid_input(e.to_string()),
+        Err(e) => return failure(e),
+    };
+
+    println!(
+        "Backup restored: {} ({})",
+        recovery.backup_folder,
+        summary.unwrap_or_default()
+    );
+    // The files are all there, so these are warnings and the restore still succeeds
+    if !report.metadata_failures.is_empty() {
+        eprintln!(
+            "Could not set {} attributes:",
+            report.metadata_failures.len()
+        );
+        for failure in &report.metadata_failures {
+            eprintln!(
+                "  {}: {} ({})",
+                failure.path.display(),
+                failure.attribute,
+                failure.error
+            );
+        }
+    }
+    // Nothing is lost, but the move did not finish
+    if !report.left_behind.is_empty() {
+        eprintln!(
+            "Kept the backup, {} files stayed in it:",
+            report.left_behind.len()
+        );
+        for file in &report.left_behind {
+            eprintln!("  {}: {}", file.path.display(), file.reason);
+        }
+        return ExitCode::from(EXIT_FAILURE);
+    }
+    ExitCode::SUCCESS
+}
+
+fn verify(backup: String, key_file: Option<PathBuf>) -> ExitCode {
+    if !Path::new(&backup).join(MANIFEST_FILE).is_file()
+        && !is_repository_snapshot(&backup)
+        && !is_archive(&backup)
+    {
+        return invalid_input(format!("No {} in {}", MANIFEST_FILE, backup));
+    }
+    let key = match key_source(Path::new(&backup), key_file, false) {
+        Ok(key) => key,
+        Err(code) => return code,
+    };
+
+    let mut summary = None;
+    let mut observer = |event: EProgressEvent, progress: &SProgress| {
+        if event == EProgressEvent::Finished {
+            summary = Some(format_summary(progress));
+        }
+    };
+    let report = match backup_service::verify(Path::new(&backup), key.as_ref(), &mut observer) {
+        Ok(report) => report,
+        Err(e @ (BackupError::WrongKey { .. } | BackupError::KeyRequired { .. })) => {
+            return invalid_input(e.to_string())
+        }
+        Err(e) => return failure(e),
+    };
+
+    for path in &report.missing {
+        println!("missing    {}", path);
+    }
+    for path in &report.corrupted {
+        println!("corrupted  {}", path);
+    }
+    for path in &report.extra {
+        println!("extra      {}", path);
+    }
+    println!(
+        "Checked {} files: {} missing, {} corrupted, {} extra ({})",
+        report.checked,
+        report.missing.len(),
+        report.corrupted.len(),
+        report.extra.len(),
+        summary.unwrap_or_default()
+    );
+
+    if report.is_ok() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_FAILURE)
+    }
+}
+
+fn prune(
+    config_path: String,
+    dest: String,
+    keep: Option<String>,
+    dry_run: bool,
+    key_file: Option<PathBuf>,
+) -> ExitCode {
+    if !Path::new(&config_path).is_file() {
+        return invalid_input(format!("Config file not found: {}", config_path));
+    }
+    if !Path::new(&dest).is_dir() {
+        return invalid_input(format!("Destination folder not found: {}", dest));
+    }
+
+    let mut config = match SBackupConfig::from_file(config_path) {
+        Ok(config) => config,
+        Err(e) => return invalid_input(e.to_string()),
+    };
+    if let Some(keep) = keep {
+        config.retention = match SRetention::parse(&keep) {
+            Ok(retention) => retention,
+            Err(e) => return invalid_input(e.to_string()),
+        };
+    }
+    let key = match key_source(Path::new(&dest), key_file, false) {
+        Ok(key) => key,
+        Err(code) => return code,
+    };
+    let plan = match retention::plan(Path::new(&dest), &config, key.as_ref()) {
+        Ok(plan) => plan,
+        Err(e @ (BackupError::WrongKey { .. } | BackupError::KeyRequired { .. })) => {
+            return invalid_input(e.to_string())
+        }
+        Err(e) => return failure(e),
+    };
+
+    for entry in &plan.entries {
+        println!(
+            "{}  {}  {}  {}",
+            if entry.keep { "keep  " } else { "delete" },
+            entry.snapshot.name,
+            format_size(entry.snapshot.size),
+            entry.reason
+        );
+    }
+    let pruned = plan.pruned().count();
+    if dry_run {
+        println!(
+            "Would delete {} snapshots, {}",
+            pruned,
+            format_size(plan.pruned_size())
+        );
+        return ExitCode::SUCCESS;
+    }
+
+    match retention::apply(&plan) {
+        Ok(removed) => {
+            println!(
+                "Deleted {} snapshots, {}",
+                removed,
+                format_size(plan.pruned_size())
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => failure(e),
+    }
+}
+
+fn snapshots(dest: String) -> ExitCode {
+    if !Path::new(&dest).is_dir() {
+        return invalid_input(format!("Destination folder not found: {}", dest));
+    }
+    let catalog = match SCatalog::load(Path::new(&dest)) {
+        Ok(catalog) => catalog,
+        Err(e) => return invalid_input(e.to_string()),
+    };
+
+    for snapshot in catalog.snapshots.iter().rev() {
+        let status = match snapshot.status {
+            ESnapshotStatus::Complete => "complete",
+            ESnapshotStatus::Incomplete => "incomplete",
+        };
+        println!(
+            "{}  {}  {} files  {}  {}  {}",
+            snapshot.name,
+            snapshot.time.format("%Y-%m-%d %H:%M:%S"),
+            snapshot.files,
+            format_size(snapshot.size),
+            status,
+            snapshot.source_config
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+/// What unlocks `folder`: the key file when one is given, else a passphrase when `encrypt`
+/// asks for a new one or `folder` is encrypted with one
+fn key_source(
+    folder: &Path,
+    key_file: Option<PathBuf>,
+    encrypt: bool,
+) -> Result<Option<EKeySource>, ExitCode> {
+    if let Some(key_file) = key_file {
+        if !key_file.is_file() {
+            return Err(invalid_input(format!(
+                "Key file not found: {}",
+                key_file.display()
+            )));
+        }
+        return Ok(Some(EKeySource::KeyFile(key_file)));
+    }
+
+    let header = match encryption::header_folder(folder).map(SEncryptionHeader::load) {
+        Some(Ok(header)) => header,
+        Some(Err(e)) => return Err(invalid_input(e.to_string())),
+        None => None,
+    };
+    let passphrase = match header {
+        Some(header) if header.derivation == EKeyDerivation::Argon2id => {
+            read_passphrase("Passphrase: ")
+        }
+        // The backend explains that a key file is needed
+        Some(_) => return Ok(None),
+        None if encrypt => read_passphrase("New passphrase: ").and_then(|passphrase| {
+            if env::var_os(PASSPHRASE_ENV).is_some()
+                || read_passphrase("Repeat passphrase: ")? == passphrase
+            {
+                Ok(passphrase)
+            } else {
+                Err(io::Error::other("the passphrases do not match"))
+            }
+        }),
+        None => return Ok(None),
+    };
+    match passphrase {
+        Ok(passphrase) if passphrase.is_empty() => {
+            Err(invalid_input("The passphrase is empty".to_string()))
+        }
+        Ok(passphrase) => Ok(Some(EKeySource::Passphrase(passphrase))),
+        Err(e) => Err(invalid_input(format!("Cannot read passphrase: {}", e))),
+    }
+}
+
+/// Taken from `PASSPHRASE_ENV` when it is set, else read from the terminal without echo
+fn read_passphrase(prompt: &str) -> io::Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    if !io::stdin().is_terminal() {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    terminal::enable_raw_mode()?;
+    let passphrase = read_hidden_line();
+    terminal::disable_raw_mode()?;
+    eprintln!();
+    passphrase
+}
+
+fn read_hidden_line() -> io::Result<String> {
+    let mut line = String::new();
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => return Ok(line),
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"))
+            }
+            KeyCode::Char(c) => line.push(c),
+            _ => {}
+        }
+    }
+}
+
+fn is_repository_snapshot(backup_folder: &str) -> bool {
+    SRepository::locate_snapshot(Path::new(backup_folder)).is_some()
+}
+
+fn is_archive(backup: &str) -> bool {
+    EArchiveFormat::of_file(Path::new(backup)).is_some()
+}
+
+fn format_summary(progress: &SProgress) -> String {
+    let mut summary = format!(
+        "{} files, {} in {}",
+        progress.files_done,
+        format_size(progress.bytes_done),
+        format_duration(progress.elapsed())
+    );
+    if progress.files_reused > 0 {
+        summary.push_str(&format!(
+            ", {} new, {} reused from {} unchanged files",
+            format_size(progress.bytes_done - progress.bytes_reused),
+            format_size(progress.bytes_reused),
+            progress.files_reused
+        ));
+    }
+    summary
+}
+
+/// One line per file and the totals of each action
+fn print_plan(plan: &SPlan) -> ExitCode {
+    println!("{}", plan.title);
+    for entry in &plan.entries {
+        let mut line = format!(
+            "{:<9}  {:>10}  {}",
+            entry.action.label(),
+            format_size(entry.size),
+            entry.path.display()
+        );
+        if !entry.note.is_empty() {
+            line.push_str(&format!("  ({})", entry.note));
+        }
+        println!("{}", line);
+    }
+    let totals: Vec<String> = plan
+        .totals()
+        .iter()
+        .map(|(action, files, bytes)| {
+            format!(
+                "{} {} files, {}",
+                action.label(),
+                files,
+                format_size(*bytes)
+            )
+        })
+        .collect();
+    if totals.is_empty() {
+        println!("Nothing to do");
+    } else {
+        println!("Would {}", totals.join("; "));
+    }
+    ExitCode::SUCCESS
+}
+
+fn invalid_input(message: String) -> ExitCode {
+    eprintln!("{}", message);
+    ExitCode::from(EXIT_INVALID_INPUT)
+}
+
+fn failure(error: BackupError) -> ExitCode {
+    eprintln!("Error: {}", error);
+    ExitCode::from(EXIT_FAILURE)
+}
@@ -0,0 +1,179 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+
+use crate::config::ECompression;
+
+/// How much of a file is test-compressed to decide whether compressing it is worth it
+const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Extensions of formats that are compressed already
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "avi", "br", "bz2", "docx", "flac", "gif", "gz", "heic", "jpeg", "jpg", "lz4",
+    "m4a", "mkv", "mov", "mp3", "mp4", "odt", "ogg", "png", "pptx", "rar", "tgz", "webm", "webp",
+    "xlsx", "xz", "zip", "zst",
+];
+
+/// Wraps a writer so everything written to it is compressed
+pub enum EEncoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Lz4(FrameEncoder<W>),
+}
+
+impl<W: Write> EEncoder<W> {
+    pub fn new(compression: ECompression, inner: W) -> io::Result<EEncoder<W>> {
+        Ok(match compression {
+            ECompression::None => EEncoder::Plain(inner),
+            ECompression::Gzip => EEncoder::Gzip(GzEncoder::new(inner, Compression::default())),
+            ECompression::Zstd(level) => EEncoder::Zstd(zstd::Encoder::new(inner, level)?),
+            ECompression::Lz4 => EEncoder::Lz4(FrameEncoder::new(inner)),
+        })
+    }
+
+    /// Writes what is still buffered and returns the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            EEncoder::Plain(inner) => Ok(inner),
+            EEncoder::Gzip(encoder) => encoder.finish(),
+            EEncoder::Zstd(encoder) => encoder.finish(),
+            EEncoder::Lz4(encoder) => encoder.finish().map_err(io::Error::other),
+        }
+    }
+}
+
+impl<W: Write> Write for EEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EEncoder::Plain(inner) => inner.write(buf),
+            EEncoder::Gzip(encoder) => encoder.write(buf),
+            EEncoder::Zstd(encoder) => encoder.write(buf),
+            EEncoder::Lz4(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EEncoder::Plain(inner) => inner.flush(),
+            EEncoder::Gzip(encoder) => encoder.flush(),
+            EEncoder::Zstd(encoder) => encoder.flush(),
+            EEncoder::Lz4(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Wraps a reader so what is read from it is decompressed
+pub fn decoder<'a, R: Read + 'a>(
+    compression: ECompression,
+    inner: R,
+) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        ECompression::None => Box::new(inner),
+        ECompression::Gzip => Box::new(GzDecoder::new(inner)),
+        ECompression::Zstd(_) => Box::new(zstd::Decoder::new(inner)?),
+        ECompression::Lz4 => Box::new(FrameDecoder::new(inner)),
+    })
+}
+
+pub fn compress(compression: ECompression, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = EEncoder::new(compression, Vec::new())?;
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+pub fn decompress(compression: ECompression, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    decoder(compression, data)?.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// `compression` unless the file is empty, has the extension of a compressed format,
+/// or its first bytes barely shrink
+pub fn choose(compression: ECompression, path: &Path) -> io::Result<ECompression> {
+    if compression.is_none() {
+        return Ok(compression);
+    }
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if COMPRESSED_EXTENSIONS.contains(&extension.as_str()) {
+        return Ok(ECompression::None);
+    }
+
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+    File::open(path)?
+        .take(SAMPLE_SIZE as u64)
+        .read_to_end(&mut sample)?;
+    if sample.is_empty() || lz4_flex::compress(&sample).len() > sample.len() * 9 / 10 {
+        return Ok(ECompression::None);
+    }
+    Ok(compression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::STestFolder;
+
+    const CODECS: [ECompression; 4] = [
+        ECompression::None,
+        ECompression::Gzip,
+        ECompression::Zstd(ECompression::DEFAULT_ZSTD_LEVEL),
+        ECompression::Lz4,
+    ];
+
+    /// Bytes that do not compress
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_codec_gives_back_what_it_compressed() {
+        let text = b"the same line over and over\n".repeat(10_000);
+        for compression in CODECS {
+            for data in [&text[..], &noise(100_000), b""] {
+                let compressed = compress(compression, data).unwrap();
+                assert_eq!(decompress(compression, &compressed).unwrap(), data);
+            }
+            if !compression.is_none() {
+                assert!(compress(compression, &text).unwrap().len() < text.len() / 10);
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_empty_and_incompressible_files_are_stored_as_they_are() {
+        let folder = STestFolder::new("compression-choose");
+        let zstd = ECompression::Zstd(ECompression::DEFAULT_ZSTD_LEVEL);
+        let text = b"the same line over and over\n".repeat(10_000);
+        assert!(choose(zstd, &folder.write("notes.txt", &text)).unwrap() == zstd);
+        assert!(
+            choose(ECompression::None, &folder.write("plain.txt", &text))
+                .unwrap()
+                .is_none()
+        );
+        assert!(choose(zstd, &folder.write("photo.JPG", &text))
+            .unwrap()
+            .is_none());
+        assert!(choose(zstd, &folder.write("empty.txt", b""))
+            .unwrap()
+            .is_none());
+        let noise = noise(100_000);
+        assert!(choose(zstd, &folder.write("noise.bin", &noise))
+            .unwrap()
+            .is_none());
+    }
+}
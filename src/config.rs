@@ -1,24 +1,267 @@
+use std::fmt;
 use std::fs::{self, File};
 use std::io::Write;
-use toml;
+use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Serialize, Deserialize)]
+use crate::encryption::{self, SKey};
+use crate::error::BackupError;
+use crate::progress::parse_size;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub enum EElementType {
     File,
     Folder,
     Anything,
 }
 
-#[derive(Serialize, Deserialize)]
+/// How files that did not change since the previous backup in the same destination are handled
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum EIncrementalMode {
+    /// Every file is copied
+    #[default]
+    Off,
+    /// Unchanged files are hard-linked from the previous backup, so every backup is complete
+    HardLink,
+    /// Unchanged files are left out, the backup only holds what changed
+    Skip,
+}
+
+/// Codec files are stored with. Written as `none`, `gzip`, `zstd:<level>` or `lz4`.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ECompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd(i32),
+    Lz4,
+}
+
+impl ECompression {
+    pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+    pub fn is_none(&self) -> bool {
+        *self == ECompression::None
+    }
+
+    pub fn parse(text: &str) -> Result<ECompression, BackupError> {
+        let invalid = || BackupError::InvalidCompression {
+            text: text.to_string(),
+        };
+        match text.trim().split_once(':') {
+            None => match text.trim() {
+                "none" => Ok(ECompression::None),
+                "gzip" => Ok(ECompression::Gzip),
+                "zstd" => Ok(ECompression::Zstd(ECompression::DEFAULT_ZSTD_LEVEL)),
+                "lz4" => Ok(ECompression::Lz4),
+                _ => Err(invalid()),
+            },
+            Some(("zstd", level)) => match level.trim().parse() {
+                Ok(level) if zstd::compression_level_range().contains(&level) => {
+                    Ok(ECompression::Zstd(level))
+                }
+                _ => Err(invalid()),
+            },
+            Some(_) => Err(invalid()),
+        }
+    }
+
+    /// Appended to the name of a file stored with this codec in a backup folder
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ECompression::None => "",
+            ECompression::Gzip => ".gz",
+            ECompression::Zstd(_) => ".zst",
+            ECompression::Lz4 => ".lz4",
+        }
+    }
+}
+
+impl fmt::Display for ECompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ECompression::None => write!(f, "none"),
+            ECompression::Gzip => write!(f, "gzip"),
+            ECompression::Zstd(level) => write!(f, "zstd:{}", level),
+            ECompression::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+impl TryFrom<String> for ECompression {
+    type Error = BackupError;
+
+    fn try_from(text: String) -> Result<ECompression, BackupError> {
+        ECompression::parse(&text)
+    }
+}
+
+impl From<ECompression> for String {
+    fn from(compression: ECompression) -> String {
+        compression.to_string()
+    }
+}
+
+/// Patterns are globs. One without a `/` matches a file or folder name at any depth,
+/// one with a `/` matches the path relative to the backed up folder.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SConfigElement {
     pub path: String,
     pub content_type: EElementType,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// When not empty only matching files are copied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Overrides the compression chosen for the destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<ECompression>,
+}
+
+impl SConfigElement {
+    pub fn new(path: String, content_type: EElementType) -> SConfigElement {
+        SConfigElement {
+            path,
+            content_type,
+            exclude: Vec::new(),
+            include: Vec::new(),
+            compression: None,
+        }
+    }
+}
+
+/// Which snapshots of this profile `prune` keeps. A snapshot is kept when any rule keeps it,
+/// with no rules set nothing is pruned.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SRetention {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_last: Option<u32>,
+    /// The newest snapshot of each of the last N days that have one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_daily: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_weekly: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_monthly: Option<u32>,
+    /// Oldest kept snapshots are pruned too while the sizes recorded in the catalog add up to more
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_size: Option<u64>,
+}
+
+impl SRetention {
+    pub fn is_empty(&self) -> bool {
+        *self == SRetention::default()
+    }
+
+    /// Parses the `last=7, daily=7, weekly=4, monthly=12, size=50GiB` form shown by `Display`
+    pub fn parse(text: &str) -> Result<SRetention, BackupError> {
+        let invalid = || BackupError::InvalidRetention {
+            text: text.to_string(),
+        };
+
+        let mut retention = SRetention::default();
+        for rule in text
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+        {
+            let (key, value) = rule.split_once('=').ok_or_else(invalid)?;
+            let value = value.trim();
+            let count = || value.parse::<u32>().map_err(|_| invalid());
+            match key.trim() {
+                "last" => retention.keep_last = Some(count()?),
+                "daily" => retention.keep_daily = Some(count()?),
+                "weekly" => retention.keep_weekly = Some(count()?),
+                "monthly" => retention.keep_monthly = Some(count()?),
+                "size" => retention.max_total_size = Some(parse_size(value).ok_or_else(invalid)?),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(retention)
+    }
+}
+
+impl fmt::Display for SRetention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rules = Vec::new();
+        let counts = [
+            ("last", self.keep_last),
+            ("daily", self.keep_daily),
+            ("weekly", self.keep_weekly),
+            ("monthly", self.keep_monthly),
+        ];
+        for (key, count) in counts {
+            if let Some(count) = count {
+                rules.push(format!("{}={}", key, count));
+            }
+        }
+        if let Some(size) = self.max_total_size {
+            // Exact, so that editing the rules does not round the limit
+            let units = [
+                ("TiB", 1u64 << 40),
+                ("GiB", 1 << 30),
+                ("MiB", 1 << 20),
+                ("KiB", 1 << 10),
+            ];
+            let size = units
+                .iter()
+                .find(|(_, unit)| size >= *unit && size % unit == 0)
+                .map(|(name, unit)| format!("{}{}", size / unit, name))
+                .unwrap_or_else(|| size.to_string());
+            rules.push(format!("size={}", size));
+        }
+        write!(f, "{}", rules.join(", "))
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+/// Settings for backups written to one destination folder
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SDestination {
+    pub path: String,
+    /// Bytes per second read and written, overrides `SBackupConfig::bandwidth_limit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth_limit: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SBackupConfig {
+    /// Profile name used for `{profile}` in backup names
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    /// Applied to every element in addition to its own patterns
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Skip what `.gitignore` files inside the backed up folders ignore
+    #[serde(default)]
+    pub use_gitignore: bool,
+    /// Back up what symlinks point to instead of the links themselves
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub incremental: EIncrementalMode,
+    /// Compare file contents too, not only size and modification time
+    #[serde(default)]
+    pub compare_hash: bool,
+    /// Files read and copied at once, 0 for one per CPU
+    #[serde(default)]
+    pub threads: usize,
+    /// Bytes per second read and written, for destinations `destinations` sets no limit for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth_limit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub destinations: Vec<SDestination>,
+    /// Read and write only while no other program uses the disk, Linux only
+    #[serde(default)]
+    pub idle_io: bool,
+    /// Added to the CPU nice level while the backup runs
+    #[serde(default)]
+    pub nice: i32,
+    #[serde(default, skip_serializing_if = "SRetention::is_empty")]
+    pub retention: SRetention,
     #[serde(default)]
     pub elements: Vec<SConfigElement>,
     #[serde(skip)]
@@ -26,40 +269,129 @@ pub struct SBackupConfig {
 }
 
 impl SBackupConfig {
-    pub fn new(config_path: String) -> SBackupConfig {
-        let mut config = SBackupConfig {
+    pub fn new() -> SBackupConfig {
+        SBackupConfig {
+            name: String::new(),
+            exclude: Vec::new(),
+            include: Vec::new(),
+            use_gitignore: false,
+            follow_symlinks: false,
+            incremental: EIncrementalMode::Off,
+            compare_hash: false,
+            threads: 0,
+            bandwidth_limit: None,
+            destinations: Vec::new(),
+            idle_io: false,
+            nice: 0,
+            retention: SRetention::default(),
             elements: Vec::new(),
             path: String::new(),
-        };
+        }
+    }
+
+    pub fn from_file(config_path: String) -> Result<SBackupConfig, BackupError> {
+        let mut config = SBackupConfig::new();
+        config.load_config(config_path.clone())?;
+        config.path = config_path;
+        Ok(config)
+    }
 
-        if !config_path.is_empty() {
-            config.load_config(config_path);
+    /// The configured name, else the config file name without extension
+    pub fn profile_name(&self) -> String {
+        if !self.name.is_empty() {
+            return self.name.clone();
         }
 
-        config
+        let path = Path::new(&self.path);
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy());
+        match stem {
+            // Configs saved from the TUI are all called backup_config.toml
+            Some(stem) if stem == "backup_config" => path
+                .parent()
+                .and_then(|parent| parent.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "backup".to_string()),
+            Some(stem) => stem.to_string(),
+            None => "backup".to_string(),
+        }
     }
 
-    pub fn load_config(&mut self, config_path: String) {
-        let contents = fs::read_to_string(config_path).expect("Failed to read config file");
+    /// `run_limit` when one is given for this run, 0 for none, else the limit `destinations`
+    /// sets for `destination`, else `bandwidth_limit`
+    pub fn bandwidth_limit_for(&self, destination: &Path, run_limit: Option<u64>) -> Option<u64> {
+        if let Some(limit) = run_limit {
+            return Some(limit).filter(|&limit| limit > 0);
+        }
+        let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let destination = canonical(destination);
+        self.destinations
+            .iter()
+            .find(|settings| canonical(Path::new(&settings.path)) == destination)
+            .and_then(|settings| settings.bandwidth_limit)
+            .or(self.bandwidth_limit)
+    }
+
+    pub fn load_config(&mut self, config_path: String) -> Result<(), BackupError> {
+        let contents = fs::read_to_string(&config_path)
+            .map_err(BackupError::io("read config", &config_path))?;
         let config_des: SBackupConfig =
-            toml::from_str(&contents).expect("Failde to deserialize config file");
-        self.elements = config_des.elements;
+            toml::from_str(&contents).map_err(|source| BackupError::ParseToml {
+                path: Path::new(&config_path).to_path_buf(),
+                source,
+            })?;
+        *self = SBackupConfig {
+            path: self.path.clone(),
+            ..config_des
+        };
+        Ok(())
     }
 
-    pub fn auto_save(&mut self, mut config_path: String) {
+    pub fn auto_save(&mut self, mut config_path: String) -> Result<(), BackupError> {
         if self.path.is_empty() {
             config_path.push_str("/backup_config.toml");
             self.path = config_path;
         }
 
-        self.save(self.path.clone())
+        self.write_file(&self.path)
     }
-    pub fn save(&self, mut config_path: String) {
-        config_path.push_str("/backup_config.toml");
-        let toml_string = toml::to_string(&self).expect("Failed to serialize to TOML");
+    /// Saves the config a backup is made with into its folder, encrypted with the backup
+    pub fn save_to_backup(
+        &self,
+        backup_folder: &Path,
+        key: Option<&SKey>,
+    ) -> Result<(), BackupError> {
+        let path = backup_folder.join("backup_config.toml");
+        let toml_string = toml::to_string(&self).map_err(|source| BackupError::SerializeToml {
+            path: path.clone(),
+            source,
+        })?;
+        encryption::write_file(&path, toml_string.as_bytes(), key)
+    }
+
+    pub fn load_from_backup(
+        backup_folder: &Path,
+        key: Option<&SKey>,
+    ) -> Result<SBackupConfig, BackupError> {
+        let path = backup_folder.join("backup_config.toml");
+        let contents = encryption::read_file(&path, key)?;
+        let mut config: SBackupConfig = toml::from_str(&String::from_utf8_lossy(&contents))
+            .map_err(|source| BackupError::ParseToml {
+                path: path.clone(),
+                source,
+            })?;
+        config.path = path.to_string_lossy().to_string();
+        Ok(config)
+    }
+
+    fn write_file(&self, file_path: &str) -> Result<(), BackupError> {
+        let toml_string = toml::to_string(&self).map_err(|source| BackupError::SerializeToml {
+            path: Path::new(file_path).to_path_buf(),
+            source,
+        })?;
 
-        let mut file = File::create(config_path).expect("Failed to create file");
+        let mut file =
+            File::create(file_path).map_err(BackupError::io("create config", file_path))?;
         file.write_all(toml_string.as_bytes())
-            .expect("Failed to write to file");
+            .map_err(BackupError::io("write config", file_path))
     }
 }
@@ -0,0 +1,596 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+
+use crate::error::BackupError;
+use crate::storage::{SLocalStorage, StorageBackend};
+
+/// How the key of an encrypted destination is derived. Holds no secret and is copied
+/// into every backup folder, so each one can be restored on its own.
+pub const ENCRYPTION_FILE: &str = "encryption.toml";
+
+/// Streams are encrypted in segments of this size, each with its own tag
+const SEGMENT_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+/// Random start of every nonce of a stream, the rest is the segment counter
+const NONCE_PREFIX_SIZE: usize = 16;
+/// Set in the counter of the final segment, so a truncated stream does not decrypt
+const LAST_SEGMENT: u64 = 1 << 63;
+/// Sealed with the key, a wrong passphrase fails to open it before anything else is read
+const KEY_CHECK: &[u8] = b"backup-nf key check";
+
+const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+const ARGON2_ITERATIONS: u32 = 3;
+const ARGON2_PARALLELISM: u32 = 1;
+/// What a header may ask for, so a damaged one can not make unlocking take all memory or forever
+const ARGON2_MEMORY_KIB_RANGE: RangeInclusive<u32> = 8 * 1024..=1024 * 1024;
+const ARGON2_ITERATIONS_RANGE: RangeInclusive<u32> = 1..=64;
+const ARGON2_PARALLELISM_RANGE: RangeInclusive<u32> = 1..=16;
+
+/// Where the key comes from
+#[derive(Clone)]
+pub enum EKeySource {
+    Passphrase(String),
+    KeyFile(PathBuf),
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EKeyDerivation {
+    Argon2id,
+    /// Any file, its contents are hashed into the key
+    KeyFile,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SEncryptionHeader {
+    pub derivation: EKeyDerivation,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    salt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    memory_kib: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iterations: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parallelism: Option<u32>,
+    /// `KEY_CHECK` sealed with the key, hex
+    check: String,
+}
+
+impl SEncryptionHeader {
+    /// A new header for `source` with a fresh salt, and the key it describes
+    pub fn create(source: &EKeySource) -> Result<(SEncryptionHeader, SKey), BackupError> {
+        let mut header = match source {
+            EKeySource::Passphrase(_) => {
+                let mut salt = [0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+                SEncryptionHeader {
+                    derivation: EKeyDerivation::Argon2id,
+                    salt: to_hex(&salt),
+                    memory_kib: Some(ARGON2_MEMORY_KIB),
+                    iterations: Some(ARGON2_ITERATIONS),
+                    parallelism: Some(ARGON2_PARALLELISM),
+                    check: String::new(),
+                }
+            }
+            EKeySource::KeyFile(_) => SEncryptionHeader {
+                derivation: EKeyDerivation::KeyFile,
+                salt: String::new(),
+                memory_kib: None,
+                iterations: None,
+                parallelism: None,
+                check: String::new(),
+            },
+        };
+        let key = header.derive_key(source, Path::new(ENCRYPTION_FILE))?;
+        header.check = to_hex(&key.seal(KEY_CHECK));
+        Ok((header, key))
+    }
+
+    /// `None` when `folder` is not encrypted
+    pub fn load(folder: &Path) -> Result<Option<SEncryptionHeader>, BackupError> {
+        SEncryptionHeader::load_from(&SLocalStorage::new(folder))
+    }
+
+    /// `None` when the folder in `storage` is not encrypted
+    pub fn load_from(
+        storage: &dyn StorageBackend,
+    ) -> Result<Option<SEncryptionHeader>, BackupError> {
+        if storage.stat(ENCRYPTION_FILE)?.is_none() {
+            return Ok(None);
+        }
+        let contents = storage.get(ENCRYPTION_FILE)?;
+        toml::from_str(&String::from_utf8_lossy(&contents))
+            .map(Some)
+            .map_err(|source| BackupError::ParseToml {
+                path: storage.location(ENCRYPTION_FILE),
+                source,
+            })
+    }
+
+    pub fn save(&self, storage: &dyn StorageBackend) -> Result<(), BackupError> {
+        let contents = toml::to_string(self).map_err(|source| BackupError::SerializeToml {
+            path: storage.location(ENCRYPTION_FILE),
+            source,
+        })?;
+        storage.put(ENCRYPTION_FILE, contents.as_bytes())
+    }
+
+    pub fn unlock(&self, source: &EKeySource, folder: &Path) -> Result<SKey, BackupError> {
+        let wrong_key = || BackupError::WrongKey {
+            path: folder.to_path_buf(),
+        };
+        let key = self.derive_key(source, folder)?;
+        let check = from_hex(&self.check).ok_or_else(wrong_key)?;
+        match key.open(&check) {
+            Some(check) if check == KEY_CHECK => Ok(key),
+            _ => Err(wrong_key()),
+        }
+    }
+
+    fn derive_key(&self, source: &EKeySource, folder: &Path) -> Result<SKey, BackupError> {
+        let wrong_key = || BackupError::WrongKey {
+            path: folder.to_path_buf(),
+        };
+        let mut master = [0u8; 32];
+        match (self.derivation, source) {
+            (EKeyDerivation::Argon2id, EKeySource::Passphrase(passphrase)) => {
+                let salt = from_hex(&self.salt).ok_or_else(wrong_key)?;
+                let memory_kib = self.memory_kib.unwrap_or(ARGON2_MEMORY_KIB);
+                let iterations = self.iterations.unwrap_or(ARGON2_ITERATIONS);
+                let parallelism = self.parallelism.unwrap_or(ARGON2_PARALLELISM);
+                if !ARGON2_MEMORY_KIB_RANGE.contains(&memory_kib)
+                    || !ARGON2_ITERATIONS_RANGE.contains(&iterations)
+                    || !ARGON2_PARALLELISM_RANGE.contains(&parallelism)
+                {
+                    return Err(BackupError::KeySettings {
+                        path: folder.to_path_buf(),
+                    });
+                }
+                let params = Params::new(memory_kib, iterations, parallelism, Some(master.len()))
+                    .map_err(|_| wrong_key())?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(passphrase.as_bytes(), &salt, &mut master)
+                    .map_err(|_| wrong_key())?;
+            }
+            (EKeyDerivation::KeyFile, EKeySource::KeyFile(path)) => {
+                let contents = fs::read(path).map_err(BackupError::io("read key file", path))?;
+                master = blake3::derive_key("backup-nf key file", &contents);
+            }
+            // A passphrase for a destination encrypted with a key file or the other way round
+            _ => return Err(wrong_key()),
+        }
+        Ok(SKey::new(&master))
+    }
+}
+
+/// The key of the folder in `storage`, `None` when it is not encrypted
+pub fn unlock(
+    storage: &dyn StorageBackend,
+    source: Option<&EKeySource>,
+) -> Result<Option<SKey>, BackupError> {
+    let Some(header) = SEncryptionHeader::load_from(storage)? else {
+        return Ok(None);
+    };
+    let folder = storage.location("");
+    let source = source.ok_or_else(|| BackupError::KeyRequired {
+        path: folder.clone(),
+    })?;
+    header.unlock(source, &folder).map(Some)
+}
+
+/// The folder holding the encryption header of a backup folder or of a repository snapshot
+pub fn header_folder(backup_folder: &Path) -> Option<&Path> {
+    [Some(backup_folder), backup_folder.parent()]
+        .into_iter()
+        .flatten()
+        .find(|folder| folder.join(ENCRYPTION_FILE).is_file())
+}
+
+#[derive(Clone)]
+pub struct SKey {
+    cipher: XChaCha20Poly1305,
+    id_key: [u8; 32],
+}
+
+impl SKey {
+    fn new(master: &[u8; 32]) -> SKey {
+        let cipher_key = blake3::derive_key("backup-nf contents", master);
+        SKey {
+            cipher: XChaCha20Poly1305::new(&cipher_key.into()),
+            id_key: blake3::derive_key("backup-nf object ids", master),
+        }
+    }
+
+    /// A fixed key for the unit tests, without the slow key derivation
+    #[cfg(test)]
+    pub fn for_tests() -> SKey {
+        SKey::new(&[7; 32])
+    }
+
+    /// Random nonce followed by the ciphertext
+    pub fn seal(&self, data: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher
+                .encrypt(&nonce, data)
+                .expect("encrypting into memory does not fail"),
+        );
+        sealed
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_SIZE {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+
+    /// Keyed hash, so ids of encrypted objects say nothing about their contents
+    pub fn object_id(&self, data: &[u8]) -> String {
+        blake3::keyed_hash(&self.id_key, data).to_hex().to_string()
+    }
+
+    fn segment_nonce(prefix: &[u8; NONCE_PREFIX_SIZE], counter: u64, last: bool) -> XNonce {
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+        let counter = if last {
+            counter | LAST_SEGMENT
+        } else {
+            counter
+        };
+        nonce[NONCE_PREFIX_SIZE..].copy_from_slice(&counter.to_be_bytes());
+        nonce.into()
+    }
+}
+
+/// Reads a whole file, decrypting it when there is a key
+pub fn read_file(path: &Path, key: Option<&SKey>) -> Result<Vec<u8>, BackupError> {
+    let contents = fs::read(path).map_err(BackupError::io("read", path))?;
+    match key {
+        Some(key) => key
+            .open(&contents)
+            .ok_or_else(|| BackupError::Undecryptable {
+                path: path.to_path_buf(),
+            }),
+        None => Ok(contents),
+    }
+}
+
+pub fn write_file(path: &Path, contents: &[u8], key: Option<&SKey>) -> Result<(), BackupError> {
+    let contents = match key {
+        Some(key) => key.seal(contents),
+        None => contents.to_vec(),
+    };
+    fs::write(path, contents).map_err(BackupError::io("write", path))
+}
+
+/// Wraps a writer so everything written to it is encrypted
+pub enum EEncryptor<W: Write> {
+    Plain(W),
+    Encrypted(SEncryptWriter<W>),
+}
+
+impl<W: Write> EEncryptor<W> {
+    pub fn new(key: Option<&SKey>, inner: W) -> io::Result<EEncryptor<W>> {
+        Ok(match key {
+            Some(key) => EEncryptor::Encrypted(SEncryptWriter::new(key, inner)?),
+            None => EEncryptor::Plain(inner),
+        })
+    }
+
+    /// Writes the final segment and returns the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            EEncryptor::Plain(inner) => Ok(inner),
+            EEncryptor::Encrypted(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for EEncryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EEncryptor::Plain(inner) => inner.write(buf),
+            EEncryptor::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EEncryptor::Plain(inner) => inner.flush(),
+            EEncryptor::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Writes the nonce prefix, then every `SEGMENT_SIZE` bytes as a sealed segment
+pub struct SEncryptWriter<W: Write> {
+    inner: W,
+    key: SKey,
+    prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> SEncryptWriter<W> {
+    fn new(key: &SKey, mut inner: W) -> io::Result<SEncryptWriter<W>> {
+        let mut prefix = [0u8; NONCE_PREFIX_SIZE];
+        OsRng.fill_bytes(&mut prefix);
+        inner.write_all(&prefix)?;
+        Ok(SEncryptWriter {
+            inner,
+            key: key.clone(),
+            prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(SEGMENT_SIZE),
+        })
+    }
+
+    fn write_segment(&mut self, last: bool) -> io::Result<()> {
+        let nonce = SKey::segment_nonce(&self.prefix, self.counter, last);
+        let sealed = self
+            .key
+            .cipher
+            .encrypt(&nonce, self.buffer.as_slice())
+            .map_err(|_| io::Error::other("cannot encrypt"))?;
+        self.inner.write_all(&sealed)?;
+        self.buffer.clear();
+        self.counter += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.write_segment(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SEncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A full segment is only written once more data shows it is not the last one
+        if self.buffer.len() == SEGMENT_SIZE {
+            self.write_segment(false)?;
+        }
+        let taken = buf.len().min(SEGMENT_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..taken]);
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wraps a reader so what is read from it is decrypted. A segment that fails
+/// to authenticate is an `InvalidData` error.
+pub fn decryptor<'a, R: Read + 'a>(key: Option<&SKey>, inner: R) -> Box<dyn Read + 'a> {
+    match key {
+        Some(key) => Box::new(SDecryptReader {
+            inner,
+            key: key.clone(),
+            prefix: None,
+            counter: 0,
+            pending: Vec::new(),
+            plain: Vec::new(),
+            position: 0,
+            finished: false,
+        }),
+        None => Box::new(inner),
+    }
+}
+
+struct SDecryptReader<R: Read> {
+    inner: R,
+    key: SKey,
+    prefix: Option<[u8; NONCE_PREFIX_SIZE]>,
+    counter: u64,
+    /// Ciphertext read ahead, one byte past a segment tells whether it is the last
+    pending: Vec<u8>,
+    plain: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> SDecryptReader<R> {
+    fn next_segment(&mut self) -> io::Result<()> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "cannot decrypt");
+        let prefix = match self.prefix {
+            Some(prefix) => prefix,
+            None => {
+                let mut prefix = [0u8; NONCE_PREFIX_SIZE];
+                self.inner.read_exact(&mut prefix).map_err(|_| invalid())?;
+                *self.prefix.insert(prefix)
+            }
+        };
+
+        let segment = SEGMENT_SIZE + TAG_SIZE;
+        let mut chunk = [0u8; 8192];
+        while self.pending.len() <= segment {
+            let read = self.inner.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            self.pending.extend_from_slice(&chunk[..read]);
+        }
+
+        let last = self.pending.len() <= segment;
+        let length = self.pending.len().min(segment);
+        let nonce = SKey::segment_nonce(&prefix, self.counter, last);
+        self.plain = self
+            .key
+            .cipher
+            .decrypt(&nonce, &self.pending[..length])
+            .map_err(|_| invalid())?;
+        self.pending.drain(..length);
+        self.position = 0;
+        self.counter += 1;
+        self.finished = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for SDecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plain.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.next_segment()?;
+        }
+        let read = buf.len().min(self.plain.len() - self.position);
+        buf[..read].copy_from_slice(&self.plain[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEALED_SEGMENT_SIZE: usize = SEGMENT_SIZE + TAG_SIZE;
+
+    fn encrypt(key: &SKey, data: &[u8]) -> Vec<u8> {
+        let mut writer = EEncryptor::new(Some(key), Vec::new()).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(key: &SKey, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut plain = Vec::new();
+        decryptor(Some(key), sealed).read_to_end(&mut plain)?;
+        Ok(plain)
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn stream_round_trips_at_segment_boundaries() {
+        let key = SKey::for_tests();
+        for len in [
+            0,
+            1,
+            SEGMENT_SIZE - 1,
+            SEGMENT_SIZE,
+            SEGMENT_SIZE + 1,
+            3 * SEGMENT_SIZE + 5,
+        ] {
+            let data = data(len);
+            let sealed = encrypt(&key, &data);
+            assert_eq!(decrypt(&key, &sealed).unwrap(), data, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn truncated_stream_is_rejected() {
+        let key = SKey::for_tests();
+        let sealed = encrypt(&key, &data(2 * SEGMENT_SIZE + 100));
+        // Cut at a segment boundary, inside a segment and inside the nonce prefix
+        for len in [
+            NONCE_PREFIX_SIZE + SEALED_SEGMENT_SIZE,
+            NONCE_PREFIX_SIZE + 2 * SEALED_SEGMENT_SIZE,
+            sealed.len() - 1,
+            NONCE_PREFIX_SIZE / 2,
+        ] {
+            let error = decrypt(&key, &sealed[..len]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn reordered_segments_are_rejected() {
+        let key = SKey::for_tests();
+        let sealed = encrypt(&key, &data(2 * SEGMENT_SIZE + 100));
+        let first = NONCE_PREFIX_SIZE..NONCE_PREFIX_SIZE + SEALED_SEGMENT_SIZE;
+        let second = first.end..first.end + SEALED_SEGMENT_SIZE;
+        let mut reordered = sealed[..first.start].to_vec();
+        reordered.extend_from_slice(&sealed[second.clone()]);
+        reordered.extend_from_slice(&sealed[first]);
+        reordered.extend_from_slice(&sealed[second.end..]);
+        let error = decrypt(&key, &reordered).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn stream_of_another_key_is_rejected() {
+        let sealed = encrypt(&SKey::new(&[1; 32]), &data(100));
+        let error = decrypt(&SKey::for_tests(), &sealed).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    fn argon2_header(memory_kib: u32, iterations: u32, parallelism: u32) -> SEncryptionHeader {
+        SEncryptionHeader {
+            derivation: EKeyDerivation::Argon2id,
+            salt: to_hex(&[3; 16]),
+            memory_kib: Some(memory_kib),
+            iterations: Some(iterations),
+            parallelism: Some(parallelism),
+            check: String::new(),
+        }
+    }
+
+    #[test]
+    fn argon2_settings_out_of_range_are_rejected() {
+        let passphrase = EKeySource::Passphrase("secret".to_string());
+        for header in [
+            argon2_header(u32::MAX, 1, 1),
+            argon2_header(1, 1, 1),
+            argon2_header(8 * 1024, u32::MAX, 1),
+            argon2_header(8 * 1024, 0, 1),
+            argon2_header(8 * 1024, 1, 255),
+        ] {
+            assert!(matches!(
+                header.unlock(&passphrase, Path::new("dest")),
+                Err(BackupError::KeySettings { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn argon2_key_unlocks_with_its_passphrase_only() {
+        let mut header = argon2_header(8 * 1024, 1, 1);
+        let passphrase = EKeySource::Passphrase("secret".to_string());
+        let key = header.derive_key(&passphrase, Path::new("dest")).unwrap();
+        header.check = to_hex(&key.seal(KEY_CHECK));
+
+        let unlocked = header.unlock(&passphrase, Path::new("dest")).unwrap();
+        assert_eq!(unlocked.object_id(b"data"), key.object_id(b"data"));
+        let wrong = EKeySource::Passphrase("guess".to_string());
+        assert!(matches!(
+            header.unlock(&wrong, Path::new("dest")),
+            Err(BackupError::WrongKey { .. })
+        ));
+    }
+}
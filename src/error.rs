@@ -0,0 +1,175 @@
+use std::{
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum BackupError {
+    Io {
+        operation: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    InvalidPath {
+        operation: &'static str,
+        path: PathBuf,
+    },
+    ParseToml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    SerializeToml {
+        path: PathBuf,
+        source: toml::ser::Error,
+    },
+    InvalidTemplate {
+        template: String,
+    },
+    InvalidRetention {
+        text: String,
+    },
+    InvalidCompression {
+        text: String,
+    },
+    InvalidPattern {
+        pattern: String,
+        source: globset::Error,
+    },
+    /// A repository object no longer matches the hash it is stored under
+    CorruptedObject {
+        kind: &'static str,
+        hash: String,
+    },
+    MissingChunk {
+        hash: String,
+    },
+    WrongKey {
+        path: PathBuf,
+    },
+    KeyRequired {
+        path: PathBuf,
+    },
+    /// The key derivation settings of an `encryption.toml` are outside the supported range
+    KeySettings {
+        path: PathBuf,
+    },
+    /// An encrypted file fails to authenticate although the key is right
+    Undecryptable {
+        path: PathBuf,
+    },
+    /// The I/O priority or the nice level could not be changed
+    Priority {
+        operation: &'static str,
+        source: io::Error,
+    },
+    Cancelled,
+    WorkerStopped,
+}
+
+impl BackupError {
+    /// For `map_err`: wraps an io error with the operation and the path it failed on
+    pub fn io(operation: &'static str, path: impl AsRef<Path>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        move |source| BackupError::Io {
+            operation,
+            path,
+            source,
+        }
+    }
+
+    pub fn invalid_path(operation: &'static str, path: impl AsRef<Path>) -> Self {
+        BackupError::InvalidPath {
+            operation,
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::Io {
+                operation,
+                path,
+                source,
+            } => write!(f, "Cannot {} {}: {}", operation, path.display(), source),
+            BackupError::InvalidPath { operation, path } => {
+                write!(f, "Cannot {}: invalid path {}", operation, path.display())
+            }
+            BackupError::ParseToml { path, source } => {
+                write!(f, "Cannot parse {}: {}", path.display(), source.message())
+            }
+            BackupError::SerializeToml { path, source } => {
+                write!(f, "Cannot serialize {}: {}", path.display(), source)
+            }
+            BackupError::InvalidTemplate { template } => {
+                write!(f, "Invalid backup name template \"{}\"", template)
+            }
+            BackupError::InvalidRetention { text } => write!(
+                f,
+                "Invalid retention rules \"{}\", expected e.g. last=7, daily=7, weekly=4, monthly=12, size=50GiB",
+                text
+            ),
+            BackupError::InvalidCompression { text } => write!(
+                f,
+                "Invalid compression \"{}\", expected none, gzip, zstd:<level> or lz4",
+                text
+            ),
+            BackupError::InvalidPattern { pattern, source } => {
+                write!(f, "Invalid pattern \"{}\": {}", pattern, source.kind())
+            }
+            BackupError::CorruptedObject { kind, hash } => {
+                write!(f, "Repository {} {} is corrupted", kind, hash)
+            }
+            BackupError::MissingChunk { hash } => {
+                write!(f, "Repository chunk {} is missing", hash)
+            }
+            BackupError::WrongKey { path } => {
+                write!(f, "Wrong passphrase or key file for {}", path.display())
+            }
+            BackupError::KeyRequired { path } => write!(
+                f,
+                "{} is encrypted, a passphrase or key file is needed",
+                path.display()
+            ),
+            BackupError::KeySettings { path } => write!(
+                f,
+                "Cannot unlock {}: its key derivation settings are out of range",
+                path.display()
+            ),
+            BackupError::Undecryptable { path } => {
+                write!(f, "Cannot decrypt {}: it is damaged", path.display())
+            }
+            BackupError::Priority { operation, source } => {
+                write!(f, "Cannot {}: {}", operation, source)
+            }
+            BackupError::Cancelled => write!(f, "Cancelled by user"),
+            BackupError::WorkerStopped => write!(f, "The worker thread stopped unexpectedly"),
+        }
+    }
+}
+
+impl Error for BackupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BackupError::Io { source, .. } => Some(source),
+            BackupError::ParseToml { source, .. } => Some(source),
+            BackupError::SerializeToml { source, .. } => Some(source),
+            BackupError::InvalidPattern { source, .. } => Some(source),
+            BackupError::Priority { source, .. } => Some(source),
+            BackupError::InvalidPath { .. }
+            | BackupError::InvalidTemplate { .. }
+            | BackupError::InvalidRetention { .. }
+            | BackupError::InvalidCompression { .. }
+            | BackupError::CorruptedObject { .. }
+            | BackupError::MissingChunk { .. }
+            | BackupError::WrongKey { .. }
+            | BackupError::KeyRequired { .. }
+            | BackupError::KeySettings { .. }
+            | BackupError::Undecryptable { .. }
+            | BackupError::Cancelled
+            | BackupError::WorkerStopped => None,
+        }
+    }
+}
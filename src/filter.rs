@@ -0,0 +1,285 @@
+use std::path::{Path, PathBuf};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+use crate::config::{SBackupConfig, SConfigElement};
+use crate::error::BackupError;
+
+/// Decides which entries of a backed up folder are copied.
+/// Relative patterns are resolved against the element folder `root`.
+pub struct SPathFilter {
+    root: PathBuf,
+    exclude: SPatternSet,
+    include: SPatternSet,
+    use_gitignore: bool,
+    /// `.gitignore` files of the folders currently being walked, outermost first
+    gitignores: Vec<Gitignore>,
+}
+
+/// Name patterns match the last path component, path patterns the whole relative path
+struct SPatternSet {
+    names: GlobSet,
+    paths: GlobSet,
+    len: usize,
+}
+
+impl SPatternSet {
+    fn new<'a>(patterns: impl Iterator<Item = &'a String>) -> Result<SPatternSet, BackupError> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        let mut len = 0;
+
+        for pattern in patterns {
+            let trimmed = pattern.trim().trim_end_matches('/');
+            if trimmed.is_empty() {
+                continue;
+            }
+            let glob = GlobBuilder::new(trimmed.trim_start_matches('/'))
+                .literal_separator(true)
+                .build()
+                .map_err(|source| BackupError::InvalidPattern {
+                    pattern: pattern.clone(),
+                    source,
+                })?;
+            if trimmed.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+            len += 1;
+        }
+
+        let build = |builder: GlobSetBuilder| {
+            builder
+                .build()
+                .map_err(|source| BackupError::InvalidPattern {
+                    pattern: String::new(),
+                    source,
+                })
+        };
+        Ok(SPatternSet {
+            names: build(names)?,
+            paths: build(paths)?,
+            len,
+        })
+    }
+
+    fn is_match(&self, relative: &Path) -> bool {
+        let name_match = relative
+            .file_name()
+            .is_some_and(|name| self.names.is_match(name));
+        name_match || self.paths.is_match(relative)
+    }
+}
+
+impl SPathFilter {
+    pub fn new(
+        config: &SBackupConfig,
+        element: &SConfigElement,
+    ) -> Result<SPathFilter, BackupError> {
+        Ok(SPathFilter {
+            root: PathBuf::from(&element.path),
+            exclude: SPatternSet::new(config.exclude.iter().chain(&element.exclude))?,
+            include: SPatternSet::new(config.include.iter().chain(&element.include))?,
+            use_gitignore: config.use_gitignore,
+            gitignores: Vec::new(),
+        })
+    }
+
+    /// Excluded folders are not walked into
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if self.exclude.is_match(relative) || self.is_gitignored(path, is_dir) {
+            return true;
+        }
+        if is_dir {
+            return false;
+        }
+        self.include.len > 0 && !self.include.is_match(relative)
+    }
+
+    /// Must be paired with `leave_dir` once the folder is walked
+    pub fn enter_dir(&mut self, dir: &Path) -> bool {
+        if !self.use_gitignore {
+            return false;
+        }
+        let file = dir.join(".gitignore");
+        if !file.is_file() {
+            return false;
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        builder.add(file);
+        match builder.build() {
+            Ok(gitignore) => {
+                self.gitignores.push(gitignore);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn leave_dir(&mut self, entered: bool) {
+        if entered {
+            self.gitignores.pop();
+        }
+    }
+
+    /// The innermost `.gitignore` with an opinion about the path wins
+    fn is_gitignored(&self, path: &Path, is_dir: bool) -> bool {
+        for gitignore in self.gitignores.iter().rev() {
+            match gitignore.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+/// Picks what a restore takes from a backup. Patterns match `/` separated paths inside the
+/// backup and a matched folder brings everything below it. Without patterns all is picked.
+pub struct SRestoreSelection {
+    globs: Option<GlobSet>,
+}
+
+impl SRestoreSelection {
+    pub fn new(patterns: &[String]) -> Result<SRestoreSelection, BackupError> {
+        let mut builder = GlobSetBuilder::new();
+        let mut len = 0;
+        for pattern in patterns {
+            let trimmed = pattern.trim().trim_matches('/');
+            if trimmed.is_empty() {
+                continue;
+            }
+            let glob = GlobBuilder::new(trimmed)
+                .literal_separator(true)
+                .build()
+                .map_err(|source| BackupError::InvalidPattern {
+                    pattern: pattern.clone(),
+                    source,
+                })?;
+            builder.add(glob);
+            len += 1;
+        }
+        if len == 0 {
+            return Ok(SRestoreSelection { globs: None });
+        }
+        let globs = builder
+            .build()
+            .map_err(|source| BackupError::InvalidPattern {
+                pattern: String::new(),
+                source,
+            })?;
+        Ok(SRestoreSelection { globs: Some(globs) })
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.globs.is_none()
+    }
+
+    /// Whether `path` or one of the folders above it is picked
+    pub fn contains(&self, path: &str) -> bool {
+        let Some(globs) = &self.globs else {
+            return true;
+        };
+        path.match_indices('/')
+            .map(|(end, _)| &path[..end])
+            .chain([path])
+            .any(|path| globs.is_match(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EElementType;
+    use crate::testing::STestFolder;
+
+    fn filter(root: &Path, exclude: &[&str], include: &[&str]) -> SPathFilter {
+        let mut config = SBackupConfig::new();
+        config.exclude = exclude.iter().map(|pattern| pattern.to_string()).collect();
+        config.include = include.iter().map(|pattern| pattern.to_string()).collect();
+        config.use_gitignore = true;
+        let element = SConfigElement::new(root.to_string_lossy().to_string(), EElementType::Folder);
+        SPathFilter::new(&config, &element).unwrap()
+    }
+
+    #[test]
+    fn name_patterns_match_at_any_depth_and_path_patterns_from_the_root() {
+        let root = Path::new("/data");
+        let filter = filter(root, &["*.log", "build/out"], &[]);
+        assert!(filter.is_excluded(&root.join("a.log"), false));
+        assert!(filter.is_excluded(&root.join("deep/er/a.log"), false));
+        assert!(filter.is_excluded(&root.join("build/out"), true));
+        assert!(!filter.is_excluded(&root.join("sub/build/out"), true));
+        assert!(!filter.is_excluded(&root.join("build"), true));
+        assert!(!filter.is_excluded(&root.join("a.txt"), false));
+    }
+
+    #[test]
+    fn anchored_patterns_only_match_right_under_the_root() {
+        let root = Path::new("/data");
+        let filter = filter(root, &["/tmp", "/cache/"], &[]);
+        assert!(filter.is_excluded(&root.join("tmp"), true));
+        assert!(filter.is_excluded(&root.join("cache"), true));
+        assert!(!filter.is_excluded(&root.join("sub/tmp"), true));
+        assert!(!filter.is_excluded(&root.join("sub/cache"), true));
+    }
+
+    #[test]
+    fn include_patterns_pick_files_and_every_folder_is_walked() {
+        let root = Path::new("/data");
+        let filter = filter(root, &["skip.txt"], &["*.txt"]);
+        assert!(!filter.is_excluded(&root.join("a.txt"), false));
+        assert!(!filter.is_excluded(&root.join("sub/b.txt"), false));
+        assert!(filter.is_excluded(&root.join("a.bin"), false));
+        assert!(!filter.is_excluded(&root.join("sub"), true));
+        // Excludes win over includes
+        assert!(filter.is_excluded(&root.join("skip.txt"), false));
+    }
+
+    #[test]
+    fn innermost_gitignore_wins() {
+        let folder = STestFolder::new("filter-gitignore");
+        folder.write(".gitignore", b"*.tmp\nlogs/\n");
+        folder.write("sub/.gitignore", b"!keep.tmp\n");
+        let root = folder.path();
+        let mut filter = filter(root, &[], &[]);
+
+        let entered_root = filter.enter_dir(root);
+        assert!(entered_root);
+        assert!(filter.is_excluded(&root.join("a.tmp"), false));
+        assert!(filter.is_excluded(&root.join("logs"), true));
+        assert!(!filter.is_excluded(&root.join("logs"), false));
+
+        let entered_sub = filter.enter_dir(&root.join("sub"));
+        assert!(entered_sub);
+        assert!(!filter.is_excluded(&root.join("sub/keep.tmp"), false));
+        assert!(filter.is_excluded(&root.join("sub/other.tmp"), false));
+        filter.leave_dir(entered_sub);
+
+        assert!(filter.is_excluded(&root.join("keep.tmp"), false));
+        filter.leave_dir(entered_root);
+        assert!(!filter.is_excluded(&root.join("a.tmp"), false));
+    }
+
+    #[test]
+    fn restore_selection_picks_paths_and_everything_below_them() {
+        let all = SRestoreSelection::new(&[" ".to_string()]).unwrap();
+        assert!(all.is_all() && all.contains("any/path"));
+
+        let patterns = ["/docs/".to_string(), "*.txt".to_string()];
+        let selection = SRestoreSelection::new(&patterns).unwrap();
+        assert!(!selection.is_all());
+        assert!(selection.contains("docs"));
+        assert!(selection.contains("docs/sub/report.pdf"));
+        assert!(!selection.contains("docsx/report.pdf"));
+        assert!(!selection.contains("other/docs"));
+        assert!(selection.contains("notes.txt"));
+        assert!(selection.contains("notes.txt/inside"));
+        assert!(!selection.contains("sub/notes.txt"));
+    }
+}
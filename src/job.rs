@@ -0,0 +1,79 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, Sender, TryRecvError},
+    Arc,
+};
+use std::thread;
+
+use crate::error::BackupError;
+use crate::progress::{EProgressEvent, ProgressObserver, SProgress};
+
+pub enum EJobMessage<T = ()> {
+    Progress(EProgressEvent, SProgress),
+    Done(Result<T, BackupError>),
+}
+
+/// A backup, restore or verify running on a worker thread.
+/// The TUI polls `try_recv` from its event loop and calls `cancel` on the cancel key.
+pub struct SJob<T = ()> {
+    receiver: Receiver<EJobMessage<T>>,
+    cancel: Arc<AtomicBool>,
+}
+
+struct SJobObserver<T> {
+    sender: Sender<EJobMessage<T>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl<T> ProgressObserver for SJobObserver<T> {
+    fn on_progress(&mut self, event: EProgressEvent, progress: &SProgress) {
+        let _ = self
+            .sender
+            .send(EJobMessage::Progress(event, progress.clone()));
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: Send + 'static> SJob<T> {
+    pub fn spawn<F>(work: F) -> SJob<T>
+    where
+        F: FnOnce(&mut dyn ProgressObserver) -> Result<T, BackupError> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let mut observer = SJobObserver {
+            sender: sender.clone(),
+            cancel: cancel.clone(),
+        };
+        thread::spawn(move || {
+            let result = work(&mut observer);
+            let _ = sender.send(EJobMessage::Done(result));
+        });
+
+        SJob { receiver, cancel }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelling(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Next message without blocking, `None` when there is nothing new
+    pub fn try_recv(&self) -> Option<EJobMessage<T>> {
+        match self.receiver.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            // The worker died without sending `Done`, e.g. on a panic
+            Err(TryRecvError::Disconnected) => {
+                Some(EJobMessage::Done(Err(BackupError::WorkerStopped)))
+            }
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::encryption::SKey;
+use crate::error::BackupError;
+
+/// Lists the files a folder backup copied so far, kept in its staging folder until it is
+/// complete so that an interrupted backup can be continued
+pub const JOURNAL_FILE: &str = ".journal";
+/// Copied files are flushed to the disk and written into the journal at least this often
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+const SYNC_FILES: usize = 256;
+
+/// Which backup a journal belongs to, its first record
+#[derive(Serialize, Deserialize)]
+pub struct SJournalHeader {
+    /// Of the backup folder the staging folder is committed as
+    pub name: String,
+    /// `SBackupConfig::profile_name` of the config
+    pub profile: String,
+    pub source_config: String,
+    pub time: DateTime<Local>,
+}
+
+/// A file copied into the staging folder, with what its manifest entry needs
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SJournalEntry {
+    /// Relative to the backup folder, `/` separated
+    pub path: String,
+    pub source: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Local>>,
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+}
+
+/// Each record is its length as 4 little endian bytes followed by its TOML, sealed when
+/// the backup is encrypted. A record cut off by a crash ends the journal.
+pub struct SJournal {
+    path: PathBuf,
+    file: File,
+    key: Option<SKey>,
+    /// Written by the interrupted run this one continues, by path
+    done: HashMap<String, SJournalEntry>,
+    /// Copied since the last sync with where they were copied to, only written into the
+    /// journal once they are on the disk
+    pending: Vec<(PathBuf, SJournalEntry)>,
+    synced: Instant,
+}
+
+impl SJournal {
+    pub fn create(
+        folder: &Path,
+        header: &SJournalHeader,
+        key: Option<&SKey>,
+    ) -> Result<SJournal, BackupError> {
+        let path = folder.join(JOURNAL_FILE);
+        let file = File::create(&path).map_err(BackupError::io("create journal", &path))?;
+        let mut journal = SJournal {
+            path,
+            file,
+            key: key.cloned(),
+            done: HashMap::new(),
+            pending: Vec::new(),
+            synced: Instant::now(),
+        };
+        let record = journal.record(header)?;
+        journal
+            .file
+            .write_all(&record)
+            .and_then(|()| journal.file.sync_data())
+            .map_err(BackupError::io("write journal", &journal.path))?;
+        Ok(journal)
+    }
+
+    /// The header and the files of the journal in `folder`, `None` when there is none
+    pub fn load(
+        folder: &Path,
+        key: Option<&SKey>,
+    ) -> Result<Option<(SJournalHeader, Vec<SJournalEntry>)>, BackupError> {
+        let path = folder.join(JOURNAL_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let mut file = File::open(&path).map_err(BackupError::io("read journal", &path))?;
+        Ok(read_records(&mut file, &path, key)?.map(|(header, entries, _)| (header, entries)))
+    }
+
+    /// Continues the journal in `folder`, the files it lists are looked up with `done`
+    pub fn resume(folder: &Path, key: Option<&SKey>) -> Result<Option<SJournal>, BackupError> {
+        let path = folder.join(JOURNAL_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(BackupError::io("open journal", &path))?;
+        let Some((_, entries, length)) = read_records(&mut file, &path, key)? else {
+            return Ok(None);
+        };
+        // A cut off record would hide everything appended after it
+        file.set_len(length)
+            .map_err(BackupError::io("write journal", &path))?;
+        Ok(Some(SJournal {
+            path,
+            file,
+            key: key.cloned(),
+            done: entries
+                .into_iter()
+                .map(|entry| (entry.path.clone(), entry))
+                .collect(),
+            pending: Vec::new(),
+            synced: Instant::now(),
+        }))
+    }
+
+    /// What the interrupted run recorded for `path`
+    pub fn done(&self, path: &str) -> Option<&SJournalEntry> {
+        self.done.get(path)
+    }
+
+    /// `stored` is where the file was copied to, files the interrupted run copied are not
+    /// recorded again
+    pub fn add(&mut self, stored: &Path, entry: SJournalEntry) {
+        if !self.done.contains_key(&entry.path) {
+            self.pending.push((stored.to_path_buf(), entry));
+        }
+    }
+
+    /// Flushes the files copied since the last sync and then records them. Unless `force`
+    /// is set that only happens once enough of them piled up.
+    pub fn sync(&mut self, force: bool) -> Result<(), BackupError> {
+        if self.pending.is_empty()
+            || !force && self.pending.len() < SYNC_FILES && self.synced.elapsed() < SYNC_INTERVAL
+        {
+            return Ok(());
+        }
+        let mut folders = HashSet::new();
+        for (stored, _) in &self.pending {
+            // Symlinks cannot be opened to be flushed, their folder holds them
+            if stored.is_file() && !stored.is_symlink() {
+                File::open(stored)
+                    .and_then(|file| file.sync_all())
+                    .map_err(BackupError::io("sync file", stored))?;
+            }
+            if let Some(folder) = stored.parent() {
+                folders.insert(folder.to_path_buf());
+            }
+        }
+        #[cfg(unix)]
+        for folder in folders {
+            File::open(&folder)
+                .and_then(|file| file.sync_all())
+                .map_err(BackupError::io("sync folder", &folder))?;
+        }
+
+        let mut records = Vec::new();
+        for (_, entry) in mem::take(&mut self.pending) {
+            records.extend(self.record(&entry)?);
+        }
+        self.file
+            .write_all(&records)
+            .and_then(|()| self.file.sync_data())
+            .map_err(BackupError::io("write journal", &self.path))?;
+        self.synced = Instant::now();
+        Ok(())
+    }
+
+    fn record(&self, value: &impl Serialize) -> Result<Vec<u8>, BackupError> {
+        let contents = toml::to_string(value).map_err(|source| BackupError::SerializeToml {
+            path: self.path.clone(),
+            source,
+        })?;
+        let contents = match &self.key {
+            Some(key) => key.seal(contents.as_bytes()),
+            None => contents.into_bytes(),
+        };
+        let mut record = (contents.len() as u32).to_le_bytes().to_vec();
+        record.extend(contents);
+        Ok(record)
+    }
+}
+
+/// The header, the entries and the length of the records read whole.
+/// `None` when not even the header can be read.
+fn read_records(
+    file: &mut File,
+    path: &Path,
+    key: Option<&SKey>,
+) -> Result<Option<(SJournalHeader, Vec<SJournalEntry>, u64)>, BackupError> {
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)
+        .map_err(BackupError::io("read journal", path))?;
+
+    let mut records = contents.as_slice();
+    let Some(header) = next_record::<SJournalHeader>(&mut records, key) else {
+        return Ok(None);
+    };
+    let mut length = contents.len() - records.len();
+    let mut entries = Vec::new();
+    while let Some(entry) = next_record::<SJournalEntry>(&mut records, key) {
+        entries.push(entry);
+        length = contents.len() - records.len();
+    }
+    Ok(Some((header, entries, length as u64)))
+}
+
+fn next_record<T: DeserializeOwned>(records: &mut &[u8], key: Option<&SKey>) -> Option<T> {
+    let (size, rest) = records.split_first_chunk::<4>()?;
+    let size = u32::from_le_bytes(*size) as usize;
+    if rest.len() < size {
+        return None;
+    }
+    let (contents, rest) = rest.split_at(size);
+    let contents = match key {
+        Some(key) => key.open(contents)?,
+        None => contents.to_vec(),
+    };
+    let value = toml::from_str(&String::from_utf8_lossy(&contents)).ok()?;
+    *records = rest;
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::STestFolder;
+
+    fn header() -> SJournalHeader {
+        SJournalHeader {
+            name: "one".to_string(),
+            profile: "test".to_string(),
+            source_config: "test.toml".to_string(),
+            time: Local::now(),
+        }
+    }
+
+    fn entry(path: &str) -> SJournalEntry {
+        SJournalEntry {
+            path: path.to_string(),
+            source: format!("/src/{}", path),
+            size: 1,
+            modified: None,
+            hash: "hash".to_string(),
+            snapshot: None,
+        }
+    }
+
+    fn paths(folder: &Path, key: Option<&SKey>) -> Vec<String> {
+        let (_, entries) = SJournal::load(folder, key).unwrap().unwrap();
+        entries.into_iter().map(|entry| entry.path).collect()
+    }
+
+    fn resume_after_cut_off_record(key: Option<SKey>) {
+        let folder = STestFolder::new(&format!("journal-{}", key.is_some()));
+        let mut journal = SJournal::create(folder.path(), &header(), key.as_ref()).unwrap();
+        for name in ["a", "b"] {
+            let stored = folder.write(name, b"x");
+            journal.add(&stored, entry(name));
+        }
+        journal.sync(true).unwrap();
+        // The record of c was being written when the backup was interrupted
+        let cut_off = journal.record(&entry("c")).unwrap();
+        journal
+            .file
+            .write_all(&cut_off[..cut_off.len() / 2])
+            .unwrap();
+        drop(journal);
+        assert_eq!(paths(folder.path(), key.as_ref()), ["a", "b"]);
+
+        let mut journal = SJournal::resume(folder.path(), key.as_ref())
+            .unwrap()
+            .unwrap();
+        assert!(journal.done("b").is_some());
+        assert!(journal.done("c").is_none());
+        for name in ["b", "c"] {
+            let stored = folder.write(name, b"x");
+            journal.add(&stored, entry(name));
+        }
+        journal.sync(true).unwrap();
+        drop(journal);
+        assert_eq!(paths(folder.path(), key.as_ref()), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn journal_resumes_after_cut_off_record() {
+        resume_after_cut_off_record(None);
+    }
+
+    #[test]
+    fn encrypted_journal_resumes_after_cut_off_record() {
+        resume_after_cut_off_record(Some(SKey::for_tests()));
+    }
+
+    #[test]
+    fn journal_with_cut_off_header_is_not_resumed() {
+        let folder = STestFolder::new("journal-header");
+        let journal = SJournal::create(folder.path(), &header(), None).unwrap();
+        let length = journal.file.metadata().unwrap().len();
+        journal.file.set_len(length - 1).unwrap();
+        drop(journal);
+        assert!(SJournal::load(folder.path(), None).unwrap().is_none());
+        assert!(SJournal::resume(folder.path(), None).unwrap().is_none());
+    }
+}
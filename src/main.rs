@@ -1,14 +1,36 @@
+mod archive;
 mod backup_service;
+mod catalog;
+mod cli;
+mod compression;
 mod config;
+mod encryption;
+mod error;
+mod filter;
+mod job;
+mod journal;
+mod manifest;
+mod metadata;
+mod parallel;
+mod plan;
+mod progress;
+mod repository;
+mod retention;
+mod sparse;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod throttle;
 mod tui;
 mod ui;
 
-use std::{
-    cell::RefCell,
-    io::{self, Stdout},
-};
+use std::{cell::RefCell, io::Stdout, path::PathBuf, process::ExitCode};
 
+use clap::Parser;
+use cli::SCli;
 use config::SBackupConfig;
+use encryption::EKeySource;
+use job::SJob;
 use ratatui::{backend::CrosstermBackend, terminal::Terminal};
 use ui::{backup::SBackupUI, menu::CurrentlyBtn, recovery::SRecoveryPanel};
 
@@ -24,15 +46,30 @@ impl App {
                 ui::backup_config::start(&self.terminal, callback)
             }
             CurrentlyBtn::Restore => {
-                let callback = |config: &SRecoveryPanel| backup_service::recovery(config);
+                let callback = |config: &SRecoveryPanel| {
+                    let config = config.clone();
+                    SJob::spawn(move |observer| backup_service::recovery(&config, observer))
+                };
                 ui::recovery::start(&self.terminal, callback)
             }
+            CurrentlyBtn::Verify => {
+                let callback = |backup_folder: &str, key: Option<EKeySource>| {
+                    let backup_folder = PathBuf::from(backup_folder);
+                    SJob::spawn(move |observer| {
+                        backup_service::verify(&backup_folder, key.as_ref(), observer)
+                    })
+                };
+                ui::verify::start(&self.terminal, callback)
+            }
         };
     }
 
     fn backup_panel(&self, config: &SBackupConfig) {
-        let callback =
-            |config: &SBackupConfig, details: &SBackupUI| backup_service::backup(config, details);
+        let callback = |config: &SBackupConfig, details: &SBackupUI| {
+            let config = config.clone();
+            let details = details.clone();
+            SJob::spawn(move |observer| backup_service::backup(&config, &details, observer))
+        };
         ui::backup::start(&self.terminal, config, callback).unwrap();
     }
 
@@ -54,8 +91,14 @@ impl Drop for App {
     }
 }
 
-fn main() -> io::Result<()> {
-    let app = App::new();
+fn main() -> ExitCode {
+    let cli = SCli::parse();
 
-    Ok(())
+    match cli.command {
+        Some(command) => cli::run(command),
+        None => {
+            let _app = App::new();
+            ExitCode::SUCCESS
+        }
+    }
 }
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::config::ECompression;
+use crate::encryption::{self, SKey};
+use crate::error::BackupError;
+use crate::metadata::SFileMetadata;
+
+/// Lists every file a backup holds, written next to its `backup_config.toml`
+pub const MANIFEST_FILE: &str = "manifest.toml";
+pub const HASH_ALGORITHM: &str = "blake3";
+
+/// `size` and `hash` are of the original contents, also when the file is stored compressed
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SManifestEntry {
+    /// Where the file is stored, relative to the backup folder, `/` separated.
+    /// Ends with the compression's extension when it is compressed.
+    pub path: String,
+    /// Where the file was backed up from
+    pub source: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Local>>,
+    pub hash: String,
+    /// Set when an incremental backup in skip mode left the file out,
+    /// names the snapshot in the same destination that holds it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    #[serde(default, skip_serializing_if = "ECompression::is_none")]
+    pub compression: ECompression,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<SFileMetadata>,
+    /// Where a symlink points, `hash` is of this target and the link is stored as a link
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlink: Option<String>,
+    /// `path` of the first file of a hard link group, which this one is restored as a link to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardlink: Option<String>,
+    /// The holes of the file are kept on restore
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sparse: bool,
+}
+
+/// A backed up folder, kept for its attributes
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SManifestFolder {
+    /// Relative to the backup folder, `/` separated
+    pub path: String,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Local>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<SFileMetadata>,
+}
+
+impl SManifestEntry {
+    /// Where the file of `backup_folder` is, in the earlier backup next to it for a skipped one
+    pub fn stored_path(&self, backup_folder: &Path) -> PathBuf {
+        match &self.snapshot {
+            Some(snapshot) => backup_folder
+                .parent()
+                .unwrap_or(backup_folder)
+                .join(snapshot)
+                .join(&self.path),
+            None => backup_folder.join(&self.path),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SManifest {
+    pub algorithm: String,
+    #[serde(default)]
+    pub files: Vec<SManifestEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub folders: Vec<SManifestFolder>,
+}
+
+impl SManifest {
+    pub fn new() -> SManifest {
+        SManifest {
+            algorithm: HASH_ALGORITHM.to_string(),
+            files: Vec::new(),
+            folders: Vec::new(),
+        }
+    }
+
+    /// `key` is the backup's, the manifest is encrypted with it
+    pub fn load(backup_folder: &Path, key: Option<&SKey>) -> Result<SManifest, BackupError> {
+        let path = backup_folder.join(MANIFEST_FILE);
+        let contents = encryption::read_file(&path, key)?;
+        toml::from_str(&String::from_utf8_lossy(&contents))
+            .map_err(|source| BackupError::ParseToml { path, source })
+    }
+
+    pub fn save(&self, backup_folder: &Path, key: Option<&SKey>) -> Result<(), BackupError> {
+        let path = backup_folder.join(MANIFEST_FILE);
+        let contents = toml::to_string(self).map_err(|source| BackupError::SerializeToml {
+            path: path.clone(),
+            source,
+        })?;
+        encryption::write_file(&path, contents.as_bytes(), key)
+    }
+
+    /// Entries by relative path
+    pub fn by_path(self) -> HashMap<String, SManifestEntry> {
+        self.files
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect()
+    }
+}
+
+/// `/` separated so manifests read the same on every platform
+pub fn relative_path(path: &Path, root: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<String> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect();
+    Some(parts.join("/"))
+}
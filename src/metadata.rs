@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, FileTimes};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+/// What a backup keeps of a file or folder besides its contents and modification time.
+/// ACLs are extended attributes on Linux and come along with them.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SFileMetadata {
+    /// Unix permission bits, setuid, setgid and sticky included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accessed: Option<DateTime<Local>>,
+    /// Hex encoded values by attribute name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+}
+
+/// An attribute a restore could not put back
+#[derive(Clone)]
+pub struct SMetadataFailure {
+    pub path: PathBuf,
+    /// Like `owner 1000:1000` or `xattr user.tag`
+    pub attribute: String,
+    pub error: String,
+}
+
+impl SMetadataFailure {
+    fn new(path: &Path, attribute: String, error: impl ToString) -> SMetadataFailure {
+        SMetadataFailure {
+            path: path.to_path_buf(),
+            attribute,
+            error: error.to_string(),
+        }
+    }
+}
+
+impl SFileMetadata {
+    /// What can be read of `path`, attributes the platform does not have stay empty
+    pub fn read(path: &Path) -> SFileMetadata {
+        let Ok(metadata) = fs::metadata(path) else {
+            return SFileMetadata::default();
+        };
+        #[allow(unused_mut)]
+        let mut result = SFileMetadata {
+            accessed: metadata.accessed().ok().map(DateTime::from),
+            ..SFileMetadata::default()
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            result.mode = Some(metadata.mode() & 0o7777);
+            result.uid = Some(metadata.uid());
+            result.gid = Some(metadata.gid());
+            for name in xattr::list(path).into_iter().flatten() {
+                if let Ok(Some(value)) = xattr::get(path, &name) {
+                    result
+                        .xattrs
+                        .insert(name.to_string_lossy().to_string(), to_hex(&value));
+                }
+            }
+        }
+        result
+    }
+
+    /// Puts the attributes and `modified` back on `path` and returns those that could not be
+    /// set. The owner goes before the mode since changing it clears setuid bits, and the
+    /// times before the mode since it can make the file read only.
+    pub fn apply(&self, path: &Path, modified: Option<SystemTime>) -> Vec<SMetadataFailure> {
+        let mut failures = Vec::new();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            for (name, value) in &self.xattrs {
+                let result = from_hex(value)
+                    .ok_or_else(|| "not hex encoded".to_string())
+                    .and_then(|value| xattr::set(path, name, &value).map_err(|e| e.to_string()));
+                if let Err(e) = result {
+                    failures.push(SMetadataFailure::new(path, format!("xattr {}", name), e));
+                }
+            }
+
+            // Only changed when it differs, which lets a user restore their own files
+            let current = fs::metadata(path).ok();
+            let uid = self
+                .uid
+                .filter(|uid| current.as_ref().is_some_and(|m| m.uid() != *uid));
+            let gid = self
+                .gid
+                .filter(|gid| current.as_ref().is_some_and(|m| m.gid() != *gid));
+            if uid.is_some() || gid.is_some() {
+                if let Err(e) = std::os::unix::fs::chown(path, uid, gid) {
+                    let owner = format!(
+                        "owner {}:{}",
+                        self.uid.map_or("-".to_string(), |uid| uid.to_string()),
+                        self.gid.map_or("-".to_string(), |gid| gid.to_string())
+                    );
+                    failures.push(SMetadataFailure::new(path, owner, e));
+                }
+            }
+        }
+
+        let accessed = self.accessed.map(SystemTime::from);
+        if accessed.is_some() || modified.is_some() {
+            let mut times = FileTimes::new();
+            if let Some(accessed) = accessed {
+                times = times.set_accessed(accessed);
+            }
+            if let Some(modified) = modified {
+                times = times.set_modified(modified);
+            }
+            if let Err(e) = File::open(path).and_then(|file| file.set_times(times)) {
+                failures.push(SMetadataFailure::new(path, "times".to_string(), e));
+            }
+        }
+
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+                failures.push(SMetadataFailure::new(path, format!("mode {:o}", mode), e));
+            }
+        }
+
+        failures
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Device and inode of a file, the same for all hard links to it
+pub fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Whether more than one path leads to the file
+pub fn is_hard_linked(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.is_file() && metadata.nlink() > 1
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+/// Whether the file takes less space on disk than its length, so it has holes
+pub fn is_sparse(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.is_file() && metadata.blocks() * 512 < metadata.len()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
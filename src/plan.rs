@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+
+/// What a backup or restore would do to one file
+#[derive(Clone, Copy, PartialEq)]
+pub enum EPlanAction {
+    Copy,
+    /// Written over a file that is already there
+    Overwrite,
+    Skip,
+    Delete,
+}
+
+impl EPlanAction {
+    pub const ALL: [EPlanAction; 4] = [
+        EPlanAction::Copy,
+        EPlanAction::Overwrite,
+        EPlanAction::Skip,
+        EPlanAction::Delete,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EPlanAction::Copy => "copy",
+            EPlanAction::Overwrite => "overwrite",
+            EPlanAction::Skip => "skip",
+            EPlanAction::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SPlanEntry {
+    pub action: EPlanAction,
+    /// The source of a backed up file, the target of a restored one
+    pub path: PathBuf,
+    pub size: u64,
+    /// Why, like `unchanged since the previous backup`, empty when there is nothing to add
+    pub note: String,
+}
+
+/// What a dry run found a backup or restore would do. Nothing is written to make it.
+#[derive(Clone)]
+pub struct SPlan {
+    /// Like `Backup to /backups/docs-20240101`
+    pub title: String,
+    pub entries: Vec<SPlanEntry>,
+}
+
+impl SPlan {
+    pub fn new(title: String) -> SPlan {
+        SPlan {
+            title,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        action: EPlanAction,
+        path: impl Into<PathBuf>,
+        size: u64,
+        note: impl Into<String>,
+    ) {
+        self.entries.push(SPlanEntry {
+            action,
+            path: path.into(),
+            size,
+            note: note.into(),
+        });
+    }
+
+    /// Files and bytes of each action that has any, in the order of `EPlanAction::ALL`
+    pub fn totals(&self) -> Vec<(EPlanAction, u64, u64)> {
+        EPlanAction::ALL
+            .into_iter()
+            .map(|action| {
+                let entries = self.entries.iter().filter(|entry| entry.action == action);
+                let (files, bytes) = entries.fold((0, 0), |(files, bytes), entry| {
+                    (files + 1, bytes + entry.size)
+                });
+                (action, files, bytes)
+            })
+            .filter(|(_, files, _)| *files > 0)
+            .collect()
+    }
+}
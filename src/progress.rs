@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::throttle::SThrottle;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EProgressEvent {
+    /// Totals are known, nothing is copied yet
+    Started,
+    /// A chunk of `current_path` was copied
+    Bytes,
+    /// `current_path` is fully processed
+    FileDone,
+    Finished,
+}
+
+#[derive(Clone)]
+pub struct SProgress {
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// Unchanged files taken over from the previous backup, counted in `*_done` too
+    pub files_reused: u64,
+    pub bytes_reused: u64,
+    pub current_path: PathBuf,
+    pub started: Instant,
+    /// Run time frozen when the job finishes
+    pub finished: Option<Duration>,
+}
+
+impl SProgress {
+    pub fn new(files_total: u64, bytes_total: u64) -> SProgress {
+        SProgress {
+            files_done: 0,
+            files_total,
+            bytes_done: 0,
+            bytes_total,
+            files_reused: 0,
+            bytes_reused: 0,
+            current_path: PathBuf::new(),
+            started: Instant::now(),
+            finished: None,
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = Some(self.started.elapsed());
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.finished.unwrap_or_else(|| self.started.elapsed())
+    }
+
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_total == 0 {
+            if self.files_total == 0 {
+                return 1.0;
+            }
+            return self.files_done as f64 / self.files_total as f64;
+        }
+        (self.bytes_done as f64 / self.bytes_total as f64).min(1.0)
+    }
+
+    /// Bytes per second since the start
+    pub fn throughput(&self) -> f64 {
+        let elapsed = self.elapsed().as_secs_f64();
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+        self.bytes_done as f64 / elapsed
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        let throughput = self.throughput();
+        if throughput <= 0.0 {
+            return None;
+        }
+        let bytes_left = self.bytes_total.saturating_sub(self.bytes_done);
+        Some(Duration::from_secs_f64(bytes_left as f64 / throughput))
+    }
+}
+
+pub trait ProgressObserver {
+    fn on_progress(&mut self, event: EProgressEvent, progress: &SProgress);
+
+    /// Checked between files, a cancelled job stops before the next one
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+
+    /// The bandwidth limit of the job, shared by the threads it copies on
+    fn throttle(&self) -> Option<&Arc<SThrottle>> {
+        None
+    }
+
+    /// Called with the bytes read or written since the last call, sleeps while the job is
+    /// over its bandwidth limit
+    fn on_transfer(&self, bytes: u64) {
+        if let Some(throttle) = self.throttle() {
+            throttle.pass(bytes, || self.is_cancelled());
+        }
+    }
+}
+
+impl<F: FnMut(EProgressEvent, &SProgress)> ProgressObserver for F {
+    fn on_progress(&mut self, event: EProgressEvent, progress: &SProgress) {
+        self(event, progress)
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Reads sizes like `512`, `1.5GiB` or `20 MB`, units are powers of 1024
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let unit = unit.trim().to_ascii_lowercase();
+    let unit = unit.trim_end_matches('b').trim_end_matches('i');
+    let exponent = match unit {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        _ => return None,
+    };
+    Some((number * 1024f64.powi(exponent)) as u64)
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,573 @@
+mod pack;
+mod tree;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+use fastcdc::v2020::StreamCDC;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::EArchiveFormat;
+use crate::config::{ECompression, SBackupConfig};
+use crate::encryption::SKey;
+use crate::error::BackupError;
+use crate::progress::{EProgressEvent, ProgressObserver, SProgress};
+use crate::storage::{self, StorageBackend};
+
+use pack::{SChunkLocation, SPackWriter};
+pub use tree::{ETreeEntryKind, STree, STreeBuilder, STreeEntry};
+
+/// Marks a destination as a repository instead of a folder of plain copies
+pub const REPOSITORY_FILE: &str = "repository.toml";
+const REPOSITORY_VERSION: u32 = 1;
+const SNAPSHOTS_FOLDER: &str = "snapshots";
+const TREES_FOLDER: &str = "trees";
+/// A pack whose used chunks take less of it than this is repacked by `collect_garbage`
+const MIN_USED_PACK_FRACTION: f64 = 0.5;
+
+/// How a backup is written into its destination
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum EDestinationFormat {
+    /// A plain copy of the files in a folder per snapshot
+    #[default]
+    Folder,
+    /// Deduplicated chunks in pack files, see `SRepository`
+    Repository,
+    /// One archive file per snapshot
+    Archive(EArchiveFormat),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SRepositorySettings {
+    version: u32,
+    min_chunk_size: usize,
+    avg_chunk_size: usize,
+    max_chunk_size: usize,
+    /// A pack is closed once it grows past this size
+    pack_size: u64,
+}
+
+impl Default for SRepositorySettings {
+    fn default() -> Self {
+        SRepositorySettings {
+            version: REPOSITORY_VERSION,
+            min_chunk_size: 256 * 1024,
+            avg_chunk_size: 1024 * 1024,
+            max_chunk_size: 8 * 1024 * 1024,
+            pack_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A backup stored in a repository, `snapshots/<name>.toml`
+#[derive(Serialize, Deserialize)]
+pub struct SSnapshotObject {
+    pub name: String,
+    pub time: DateTime<Local>,
+    /// Hash of the root tree, its entries are the backed up elements
+    pub tree: String,
+    pub config: SBackupConfig,
+}
+
+/// Files are split into content-defined chunks, every chunk is stored once under
+/// its BLAKE3 hash in a pack file, and a snapshot is a tree of folders that lists
+/// the chunks of each file. In an encrypted repository every object is sealed with
+/// `key` and hashes are keyed with it. Everything is read and written through `storage`.
+pub struct SRepository {
+    storage: Arc<dyn StorageBackend>,
+    settings: SRepositorySettings,
+    index: HashMap<String, SChunkLocation>,
+    writer: Option<SPackWriter>,
+    key: Option<SKey>,
+}
+
+impl SRepository {
+    pub fn is_repository(storage: &dyn StorageBackend) -> bool {
+        storage.exists(REPOSITORY_FILE)
+    }
+
+    /// The storage of the repository and the snapshot name when `backup_folder` names
+    /// a snapshot inside a repository
+    pub fn locate_snapshot(backup_folder: &Path) -> Option<(Arc<dyn StorageBackend>, String)> {
+        let root = backup_folder.parent()?;
+        let name = backup_folder.file_name()?.to_string_lossy().to_string();
+        let storage = storage::open(root);
+        let exists = storage.exists(&snapshot_path(&name));
+        (exists && SRepository::is_repository(&*storage)).then_some((storage, name))
+    }
+
+    /// Folders are made as objects are written into them
+    pub fn init(
+        storage: Arc<dyn StorageBackend>,
+        key: Option<SKey>,
+    ) -> Result<SRepository, BackupError> {
+        write_toml(
+            &*storage,
+            REPOSITORY_FILE,
+            &SRepositorySettings::default(),
+            None,
+        )?;
+        SRepository::open(storage, key)
+    }
+
+    /// `key` is the one of the destination's `encryption.toml`
+    pub fn open(
+        storage: Arc<dyn StorageBackend>,
+        key: Option<SKey>,
+    ) -> Result<SRepository, BackupError> {
+        let settings: SRepositorySettings = read_toml(&*storage, REPOSITORY_FILE, None)?;
+        if settings.version != REPOSITORY_VERSION {
+            return Err(BackupError::invalid_path(
+                "open repository of another version",
+                storage.location(REPOSITORY_FILE),
+            ));
+        }
+        Ok(SRepository {
+            index: pack::load_index(&*storage, key.as_ref())?,
+            storage,
+            settings,
+            writer: None,
+            key,
+        })
+    }
+
+    pub fn has_chunk(&self, hash: &str) -> bool {
+        self.index.contains_key(hash)
+    }
+
+    /// Splits a file into chunks and stores the new ones, returns the hashes of all of them
+    pub fn store_file(
+        &mut self,
+        path: &Path,
+        compression: ECompression,
+        progress: &mut SProgress,
+        observer: &mut dyn ProgressObserver,
+    ) -> Result<Vec<String>, BackupError> {
+        let file = File::open(path).map_err(BackupError::io("open file", path))?;
+        let chunker = StreamCDC::new(
+            file,
+            self.settings.min_chunk_size,
+            self.settings.avg_chunk_size,
+            self.settings.max_chunk_size,
+        );
+
+        let mut chunks = Vec::new();
+        for chunk in chunker {
+            let chunk = chunk.map_err(|e| BackupError::Io {
+                operation: "read file",
+                path: path.to_path_buf(),
+                source: e.into(),
+            })?;
+            let hash = object_id(self.key.as_ref(), &chunk.data);
+            self.add_chunk(&hash, &chunk.data, compression)?;
+            chunks.push(hash);
+            observer.on_transfer(chunk.length as u64);
+            progress.bytes_done += chunk.length as u64;
+            observer.on_progress(EProgressEvent::Bytes, progress);
+        }
+        Ok(chunks)
+    }
+
+    fn add_chunk(
+        &mut self,
+        hash: &str,
+        data: &[u8],
+        compression: ECompression,
+    ) -> Result<(), BackupError> {
+        if self.index.contains_key(hash) {
+            return Ok(());
+        }
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => self.writer.insert(SPackWriter::new(self.key.clone())),
+        };
+        if writer.contains(hash) {
+            return Ok(());
+        }
+        writer.add(hash, data, compression)?;
+        if writer.len() >= self.settings.pack_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the open pack so its chunks can be read
+    pub fn flush(&mut self) -> Result<(), BackupError> {
+        if let Some(writer) = self.writer.take() {
+            self.index.extend(writer.finish(&*self.storage)?);
+        }
+        Ok(())
+    }
+
+    pub fn read_chunk(&self, hash: &str) -> Result<Vec<u8>, BackupError> {
+        let location = self
+            .index
+            .get(hash)
+            .ok_or_else(|| BackupError::MissingChunk {
+                hash: hash.to_string(),
+            })?;
+        pack::read_chunk(&*self.storage, hash, location, self.key.as_ref())
+    }
+
+    /// Returns the hash the tree is stored under
+    pub fn save_tree(&self, tree: &STree) -> Result<String, BackupError> {
+        let contents = toml::to_string(tree).map_err(|source| BackupError::SerializeToml {
+            path: self.storage.location(TREES_FOLDER),
+            source,
+        })?;
+        let hash = object_id(self.key.as_ref(), contents.as_bytes());
+        let path = tree_path(&hash);
+        if !self.storage.exists(&path) {
+            write_object(
+                &*self.storage,
+                &path,
+                contents.as_bytes(),
+                self.key.as_ref(),
+            )?;
+        }
+        Ok(hash)
+    }
+
+    pub fn load_tree(&self, hash: &str) -> Result<STree, BackupError> {
+        let path = tree_path(hash);
+        let corrupted = || BackupError::CorruptedObject {
+            kind: "tree",
+            hash: hash.to_string(),
+        };
+        let contents = match read_object(&*self.storage, &path, self.key.as_ref()) {
+            Err(BackupError::Undecryptable { .. }) => return Err(corrupted()),
+            contents => contents?,
+        };
+        if object_id(self.key.as_ref(), &contents) != hash {
+            return Err(corrupted());
+        }
+        toml::from_str(&String::from_utf8_lossy(&contents)).map_err(|source| {
+            BackupError::ParseToml {
+                path: self.storage.location(&path),
+                source,
+            }
+        })
+    }
+
+    /// `entry` and everything below it with the paths they get under `path`, parents first.
+    /// Names that are not a plain file name, like `..`, are refused.
+    pub fn flatten(
+        &self,
+        entry: &STreeEntry,
+        path: &Path,
+        entries: &mut Vec<(PathBuf, STreeEntry)>,
+    ) -> Result<(), BackupError> {
+        let mut components = Path::new(&entry.name).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(BackupError::invalid_path("read snapshot tree", path));
+        }
+        entries.push((path.to_path_buf(), entry.clone()));
+        if let Some(hash) = &entry.tree {
+            for child in self.load_tree(hash)?.entries {
+                self.flatten(&child, &path.join(&child.name), entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Files of a snapshot by their `/` separated path below the root tree
+    pub fn snapshot_files(&self, name: &str) -> Result<HashMap<String, STreeEntry>, BackupError> {
+        let snapshot = self.load_snapshot(name)?;
+        let mut files = HashMap::new();
+        for entry in self.load_tree(&snapshot.tree)?.entries {
+            let mut entries = Vec::new();
+            self.flatten(&entry, Path::new(&entry.name), &mut entries)?;
+            for (path, entry) in entries {
+                if entry.kind == ETreeEntryKind::File {
+                    let parts: Vec<String> = path
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy().to_string())
+                        .collect();
+                    files.insert(parts.join("/"), entry);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    pub fn has_snapshot(&self, name: &str) -> bool {
+        self.storage.exists(&snapshot_path(name))
+    }
+
+    pub fn save_snapshot(&self, snapshot: &SSnapshotObject) -> Result<(), BackupError> {
+        write_toml(
+            &*self.storage,
+            &snapshot_path(&snapshot.name),
+            snapshot,
+            self.key.as_ref(),
+        )
+    }
+
+    pub fn load_snapshot(&self, name: &str) -> Result<SSnapshotObject, BackupError> {
+        read_toml(&*self.storage, &snapshot_path(name), self.key.as_ref())
+    }
+
+    /// Only the snapshot itself, `collect_garbage` frees what no other snapshot uses
+    pub fn remove_snapshot(&self, name: &str) -> Result<(), BackupError> {
+        self.storage.delete(&snapshot_path(name))
+    }
+
+    /// Removes trees no snapshot refers to and packs none of whose chunks are used. Packs
+    /// mostly holding unused chunks are repacked with only the used ones.
+    /// Returns how many bytes were freed.
+    pub fn collect_garbage(&mut self) -> Result<u64, BackupError> {
+        self.flush()?;
+
+        let mut trees = HashSet::new();
+        let mut chunks = HashSet::new();
+        for name in self.storage.list(SNAPSHOTS_FOLDER)? {
+            let Some(name) = name.strip_suffix(".toml") else {
+                continue;
+            };
+            let snapshot = self.load_snapshot(name)?;
+            self.mark_tree(&snapshot.tree, &mut trees, &mut chunks)?;
+        }
+
+        let mut freed = 0;
+        for name in self.storage.list(TREES_FOLDER)? {
+            let Some(hash) = name.strip_suffix(".toml") else {
+                continue;
+            };
+            if !trees.contains(hash) {
+                let path = tree_path(hash);
+                freed += self.object_size(&path);
+                self.storage.delete(&path)?;
+            }
+        }
+
+        // The used chunks of each pack
+        let mut packs: HashMap<String, Vec<(String, SChunkLocation)>> = HashMap::new();
+        for (hash, location) in &self.index {
+            let used = packs.entry(location.pack.clone()).or_default();
+            if chunks.contains(hash) {
+                used.push((hash.clone(), location.clone()));
+            }
+        }
+        let mut writer = SPackWriter::new(self.key.clone());
+        let mut emptied = Vec::new();
+        for (pack, mut used) in packs {
+            let size = self.object_size(&pack::pack_path(&pack));
+            let used_size: u64 = used.iter().map(|(_, location)| location.length).sum();
+            if !used.is_empty() && used_size as f64 >= size as f64 * MIN_USED_PACK_FRACTION {
+                continue;
+            }
+            used.sort_by_key(|(_, location)| location.offset);
+            for (hash, location) in used {
+                let stored = self.storage.get_range(
+                    &pack::pack_path(&pack),
+                    location.offset,
+                    location.length,
+                )?;
+                writer.add_stored(&hash, &stored, location.compression);
+                if writer.len() >= self.settings.pack_size {
+                    let full = mem::replace(&mut writer, SPackWriter::new(self.key.clone()));
+                    self.index.extend(full.finish(&*self.storage)?);
+                }
+            }
+            freed += size.saturating_sub(used_size);
+            emptied.push(pack);
+        }
+        // The old packs go once the chunks they still had are in the new ones
+        self.index.extend(writer.finish(&*self.storage)?);
+        for pack in emptied {
+            pack::remove_pack(&*self.storage, &pack)?;
+            self.index.retain(|_, location| location.pack != pack);
+        }
+        Ok(freed)
+    }
+
+    fn mark_tree(
+        &self,
+        hash: &str,
+        trees: &mut HashSet<String>,
+        chunks: &mut HashSet<String>,
+    ) -> Result<(), BackupError> {
+        if !trees.insert(hash.to_string()) {
+            return Ok(());
+        }
+        for entry in self.load_tree(hash)?.entries {
+            chunks.extend(entry.chunks);
+            if let Some(tree) = &entry.tree {
+                self.mark_tree(tree, trees, chunks)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn object_size(&self, path: &str) -> u64 {
+        match self.storage.stat(path) {
+            Ok(Some(stat)) => stat.size,
+            _ => 0,
+        }
+    }
+}
+
+fn tree_path(hash: &str) -> String {
+    format!("{}/{}.toml", TREES_FOLDER, hash)
+}
+
+fn snapshot_path(name: &str) -> String {
+    format!("{}/{}.toml", SNAPSHOTS_FOLDER, name)
+}
+
+/// Plain BLAKE3 hash, keyed in an encrypted repository
+fn object_id(key: Option<&SKey>, data: &[u8]) -> String {
+    match key {
+        Some(key) => key.object_id(data),
+        None => blake3::hash(data).to_hex().to_string(),
+    }
+}
+
+/// Reads a whole object, decrypting it when there is a key
+fn read_object(
+    storage: &dyn StorageBackend,
+    path: &str,
+    key: Option<&SKey>,
+) -> Result<Vec<u8>, BackupError> {
+    let contents = storage.get(path)?;
+    match key {
+        Some(key) => key
+            .open(&contents)
+            .ok_or_else(|| BackupError::Undecryptable {
+                path: storage.location(path),
+            }),
+        None => Ok(contents),
+    }
+}
+
+fn write_object(
+    storage: &dyn StorageBackend,
+    path: &str,
+    contents: &[u8],
+    key: Option<&SKey>,
+) -> Result<(), BackupError> {
+    match key {
+        Some(key) => storage.put(path, &key.seal(contents)),
+        None => storage.put(path, contents),
+    }
+}
+
+fn read_toml<T: for<'de> Deserialize<'de>>(
+    storage: &dyn StorageBackend,
+    path: &str,
+    key: Option<&SKey>,
+) -> Result<T, BackupError> {
+    let contents = read_object(storage, path, key)?;
+    toml::from_str(&String::from_utf8_lossy(&contents)).map_err(|source| BackupError::ParseToml {
+        path: storage.location(path),
+        source,
+    })
+}
+
+fn write_toml<T: Serialize>(
+    storage: &dyn StorageBackend,
+    path: &str,
+    value: &T,
+    key: Option<&SKey>,
+) -> Result<(), BackupError> {
+    let contents = toml::to_string(value).map_err(|source| BackupError::SerializeToml {
+        path: storage.location(path),
+        source,
+    })?;
+    write_object(storage, path, contents.as_bytes(), key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::SLocalStorage;
+    use crate::testing::STestFolder;
+
+    /// A snapshot of one file made of `chunks`
+    fn save_file_snapshot(repository: &SRepository, name: &str, chunks: &[String]) {
+        let tree = STree {
+            entries: vec![STreeEntry {
+                name: "file".to_string(),
+                kind: ETreeEntryKind::File,
+                size: 0,
+                modified: None,
+                chunks: chunks.to_vec(),
+                tree: None,
+                metadata: None,
+                target: None,
+                hardlink: None,
+                sparse: false,
+            }],
+        };
+        let tree = repository.save_tree(&tree).unwrap();
+        repository
+            .save_snapshot(&SSnapshotObject {
+                name: name.to_string(),
+                time: Local::now(),
+                tree,
+                config: SBackupConfig::new(),
+            })
+            .unwrap();
+    }
+
+    fn add_chunks(repository: &mut SRepository, chunks: &[Vec<u8>]) -> Vec<String> {
+        let hashes = chunks
+            .iter()
+            .map(|data| {
+                let hash = object_id(repository.key.as_ref(), data);
+                repository
+                    .add_chunk(&hash, data, ECompression::None)
+                    .unwrap();
+                hash
+            })
+            .collect();
+        repository.flush().unwrap();
+        hashes
+    }
+
+    #[test]
+    fn garbage_collection_repacks_mostly_unused_packs() {
+        let folder = STestFolder::new("repository-repack");
+        let storage: Arc<dyn StorageBackend> = Arc::new(SLocalStorage::new(folder.path()));
+        let mut repository = SRepository::init(storage.clone(), None).unwrap();
+        let chunks: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i; 1000]).collect();
+        let hashes = add_chunks(&mut repository, &chunks);
+        save_file_snapshot(&repository, "one", &hashes[..1]);
+
+        let freed = repository.collect_garbage().unwrap();
+        assert_eq!(freed, 3000);
+        assert_eq!(storage.list("packs").unwrap().len(), 1);
+        let repository = SRepository::open(storage, None).unwrap();
+        assert_eq!(repository.read_chunk(&hashes[0]).unwrap(), chunks[0]);
+        assert!(!repository.has_chunk(&hashes[1]));
+    }
+
+    #[test]
+    fn garbage_collection_keeps_mostly_used_packs_and_removes_unused_ones() {
+        let folder = STestFolder::new("repository-keep");
+        let storage: Arc<dyn StorageBackend> = Arc::new(SLocalStorage::new(folder.path()));
+        let mut repository = SRepository::init(storage.clone(), None).unwrap();
+        let used = add_chunks(
+            &mut repository,
+            &[vec![1; 1000], vec![2; 1000], vec![3; 1000]],
+        );
+        let unused = add_chunks(&mut repository, &[vec![4; 1000]]);
+        save_file_snapshot(&repository, "one", &used[..2]);
+        let packs = storage.list("packs").unwrap();
+        assert_eq!(packs.len(), 2);
+
+        assert_eq!(repository.collect_garbage().unwrap(), 1000);
+        let left = storage.list("packs").unwrap();
+        assert_eq!(left.len(), 1);
+        assert!(packs.contains(&left[0]));
+        assert!(repository.has_chunk(&used[2]));
+        assert!(!repository.has_chunk(&unused[0]));
+    }
+}
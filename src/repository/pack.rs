@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{read_object, write_object};
+use crate::compression;
+use crate::config::ECompression;
+use crate::encryption::SKey;
+use crate::error::BackupError;
+use crate::storage::StorageBackend;
+
+const PACKS_FOLDER: &str = "packs";
+const INDEX_FOLDER: &str = "index";
+
+/// Where a chunk is stored
+#[derive(Clone)]
+pub struct SChunkLocation {
+    pub pack: String,
+    pub offset: u64,
+    pub length: u64,
+    pub compression: ECompression,
+}
+
+/// `length` is what the chunk takes in the pack, `hash` is the id of the original data
+#[derive(Serialize, Deserialize)]
+struct SIndexEntry {
+    hash: String,
+    offset: u64,
+    length: u64,
+    #[serde(default, skip_serializing_if = "ECompression::is_none")]
+    compression: ECompression,
+}
+
+/// Chunks of one pack file. Written after the pack, so a pack without an index
+/// is left over from an interrupted backup and is never read.
+#[derive(Default, Serialize, Deserialize)]
+struct SPackIndex {
+    #[serde(default)]
+    chunks: Vec<SIndexEntry>,
+}
+
+/// Every indexed chunk of the repository in `storage`, by hash
+pub fn load_index(
+    storage: &dyn StorageBackend,
+    key: Option<&SKey>,
+) -> Result<HashMap<String, SChunkLocation>, BackupError> {
+    let mut index = HashMap::new();
+    for name in storage.list(INDEX_FOLDER)? {
+        // Anything else is left over from an interrupted write
+        let Some(pack) = name.strip_suffix(".toml") else {
+            continue;
+        };
+        let path = index_path(pack);
+        let contents = read_object(storage, &path, key)?;
+        let pack_index: SPackIndex =
+            toml::from_str(&String::from_utf8_lossy(&contents)).map_err(|source| {
+                BackupError::ParseToml {
+                    path: storage.location(&path),
+                    source,
+                }
+            })?;
+        for chunk in pack_index.chunks {
+            index.insert(
+                chunk.hash,
+                SChunkLocation {
+                    pack: pack.to_string(),
+                    offset: chunk.offset,
+                    length: chunk.length,
+                    compression: chunk.compression,
+                },
+            );
+        }
+    }
+    Ok(index)
+}
+
+/// Reads a chunk and checks it still has the hash it is stored under
+pub fn read_chunk(
+    storage: &dyn StorageBackend,
+    hash: &str,
+    location: &SChunkLocation,
+    key: Option<&SKey>,
+) -> Result<Vec<u8>, BackupError> {
+    let stored = storage.get_range(&pack_path(&location.pack), location.offset, location.length)?;
+
+    let corrupted = || BackupError::CorruptedObject {
+        kind: "chunk",
+        hash: hash.to_string(),
+    };
+    let stored = match key {
+        Some(key) => key.open(&stored).ok_or_else(corrupted)?,
+        None => stored,
+    };
+    let data = match location.compression {
+        ECompression::None => stored,
+        codec => compression::decompress(codec, &stored).map_err(|_| corrupted())?,
+    };
+    if super::object_id(key, &data) != hash {
+        return Err(corrupted());
+    }
+    Ok(data)
+}
+
+/// Removes a pack and its index
+pub fn remove_pack(storage: &dyn StorageBackend, pack: &str) -> Result<(), BackupError> {
+    // The index goes first, a pack without one is never read
+    storage.delete(&index_path(pack))?;
+    storage.delete(&pack_path(pack))
+}
+
+pub fn pack_path(pack: &str) -> String {
+    format!("{}/{}.pack", PACKS_FOLDER, pack)
+}
+
+fn index_path(pack: &str) -> String {
+    format!("{}/{}.toml", INDEX_FOLDER, pack)
+}
+
+/// Collects new chunks in memory until the pack is finished and stored as a whole
+pub struct SPackWriter {
+    contents: Vec<u8>,
+    index: SPackIndex,
+    key: Option<SKey>,
+}
+
+impl SPackWriter {
+    pub fn new(key: Option<SKey>) -> SPackWriter {
+        SPackWriter {
+            contents: Vec::new(),
+            index: SPackIndex::default(),
+            key,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.contents.len() as u64
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.index.chunks.iter().any(|chunk| chunk.hash == hash)
+    }
+
+    /// A chunk that does not get smaller is stored uncompressed. Compressed or not,
+    /// it is sealed on its own when the repository is encrypted.
+    pub fn add(
+        &mut self,
+        hash: &str,
+        data: &[u8],
+        compression: ECompression,
+    ) -> Result<(), BackupError> {
+        let compressed = match compression {
+            ECompression::None => None,
+            codec => Some(
+                compression::compress(codec, data)
+                    .map_err(BackupError::io("compress chunk", hash))?,
+            ),
+        };
+        let (stored, compression) = match &compressed {
+            Some(compressed) if compressed.len() < data.len() => (&compressed[..], compression),
+            _ => (data, ECompression::None),
+        };
+        let sealed = self.key.as_ref().map(|key| key.seal(stored));
+        self.add_stored(hash, sealed.as_deref().unwrap_or(stored), compression);
+        Ok(())
+    }
+
+    /// A chunk as another pack stores it, compressed and sealed already
+    pub fn add_stored(&mut self, hash: &str, stored: &[u8], compression: ECompression) {
+        self.index.chunks.push(SIndexEntry {
+            hash: hash.to_string(),
+            offset: self.contents.len() as u64,
+            length: stored.len() as u64,
+            compression,
+        });
+        self.contents.extend_from_slice(stored);
+    }
+
+    /// Names the pack after its contents and writes its index.
+    /// Returns the locations of the chunks it holds.
+    pub fn finish(
+        self,
+        storage: &dyn StorageBackend,
+    ) -> Result<Vec<(String, SChunkLocation)>, BackupError> {
+        if self.index.chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let pack = blake3::hash(&self.contents).to_hex().to_string();
+        storage.put(&pack_path(&pack), &self.contents)?;
+
+        let index_path = index_path(&pack);
+        let contents =
+            toml::to_string(&self.index).map_err(|source| BackupError::SerializeToml {
+                path: storage.location(&index_path),
+                source,
+            })?;
+        write_object(storage, &index_path, contents.as_bytes(), self.key.as_ref())?;
+
+        Ok(self
+            .index
+            .chunks
+            .into_iter()
+            .map(|chunk| {
+                let location = SChunkLocation {
+                    pack: pack.clone(),
+                    offset: chunk.offset,
+                    length: chunk.length,
+                    compression: chunk.compression,
+                };
+                (chunk.hash, location)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::object_id;
+    use crate::storage::SLocalStorage;
+    use crate::testing::STestFolder;
+
+    fn write_then_read(key: Option<SKey>) {
+        let folder = STestFolder::new(&format!("pack-{}", key.is_some()));
+        let storage = SLocalStorage::new(folder.path());
+        let chunks: Vec<(Vec<u8>, ECompression)> = vec![
+            (b"plain chunk".to_vec(), ECompression::None),
+            (vec![b'z'; 10_000], ECompression::Zstd(3)),
+            (vec![b'l'; 5_000], ECompression::Lz4),
+        ];
+        let mut writer = SPackWriter::new(key.clone());
+        for (data, compression) in &chunks {
+            let hash = object_id(key.as_ref(), data);
+            writer.add(&hash, data, *compression).unwrap();
+            assert!(writer.contains(&hash));
+        }
+        let written = writer.finish(&storage).unwrap();
+        assert_eq!(written.len(), chunks.len());
+
+        let index = load_index(&storage, key.as_ref()).unwrap();
+        assert_eq!(index.len(), chunks.len());
+        for (data, _) in &chunks {
+            let hash = object_id(key.as_ref(), data);
+            let read = read_chunk(&storage, &hash, &index[&hash], key.as_ref()).unwrap();
+            assert_eq!(&read, data);
+        }
+        // Compressible chunks are stored compressed, the short one is not
+        let stored: u64 = index.values().map(|location| location.length).sum();
+        assert!(stored < 15_000);
+    }
+
+    #[test]
+    fn pack_reads_back_what_was_written() {
+        write_then_read(None);
+    }
+
+    #[test]
+    fn encrypted_pack_reads_back_what_was_written() {
+        write_then_read(Some(SKey::for_tests()));
+    }
+
+    #[test]
+    fn damaged_chunk_is_rejected() {
+        let folder = STestFolder::new("pack-damaged");
+        let storage = SLocalStorage::new(folder.path());
+        let data = b"some chunk".to_vec();
+        let hash = object_id(None, &data);
+        let mut writer = SPackWriter::new(None);
+        writer.add(&hash, &data, ECompression::None).unwrap();
+        let (_, location) = writer.finish(&storage).unwrap().remove(0);
+
+        let pack = storage.location(&pack_path(&location.pack));
+        let mut contents = std::fs::read(&pack).unwrap();
+        contents[0] ^= 1;
+        std::fs::write(&pack, contents).unwrap();
+        assert!(matches!(
+            read_chunk(&storage, &hash, &location, None),
+            Err(BackupError::CorruptedObject { .. })
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::error::BackupError;
+use crate::metadata::SFileMetadata;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ETreeEntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct STreeEntry {
+    pub name: String,
+    pub kind: ETreeEntryKind,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Local>>,
+    /// Hashes of the file's chunks in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<String>,
+    /// Hash of the folder's tree object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<SFileMetadata>,
+    /// Where a symlink points
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Path in the snapshot of the first file of a hard link group,
+    /// which this one is restored as a link to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardlink: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sparse: bool,
+}
+
+/// One folder of a snapshot. Stored under the hash of its contents,
+/// so a folder that did not change is shared by every snapshot.
+#[derive(Default, Serialize, Deserialize)]
+pub struct STree {
+    #[serde(default)]
+    pub entries: Vec<STreeEntry>,
+}
+
+/// Collects the files of a backup into nested folders, then saves the trees bottom up
+#[derive(Default)]
+pub struct STreeBuilder {
+    files: Vec<STreeEntry>,
+    dirs: BTreeMap<String, STreeBuilder>,
+    /// Of the folder itself
+    pub modified: Option<DateTime<Local>>,
+    pub metadata: Option<SFileMetadata>,
+}
+
+impl STreeBuilder {
+    pub fn add_dir(&mut self, components: &[String]) -> &mut STreeBuilder {
+        let mut node = self;
+        for component in components {
+            node = node.dirs.entry(component.clone()).or_default();
+        }
+        node
+    }
+
+    pub fn add_file(&mut self, components: &[String], entry: STreeEntry) {
+        let Some((_, parents)) = components.split_last() else {
+            return;
+        };
+        self.add_dir(parents).files.push(entry);
+    }
+
+    /// `save_tree` stores a tree and returns its hash
+    pub fn save(
+        self,
+        save_tree: &mut impl FnMut(&STree) -> Result<String, BackupError>,
+    ) -> Result<String, BackupError> {
+        let mut tree = STree::default();
+        for (name, dir) in self.dirs {
+            let modified = dir.modified;
+            let metadata = dir.metadata.clone();
+            tree.entries.push(STreeEntry {
+                name,
+                kind: ETreeEntryKind::Dir,
+                size: 0,
+                modified,
+                chunks: Vec::new(),
+                tree: Some(dir.save(save_tree)?),
+                metadata,
+                target: None,
+                hardlink: None,
+                sparse: false,
+            });
+        }
+        tree.entries.extend(self.files);
+        tree.entries.sort_by(|a, b| a.name.cmp(&b.name));
+        save_tree(&tree)
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::archive::EArchiveFormat;
+use crate::backup_service;
+use crate::catalog::{ESnapshotStatus, SCatalog, SSnapshotInfo};
+use crate::config::{EIncrementalMode, SBackupConfig, SRetention};
+use crate::encryption::{self, EKeySource, SKey};
+use crate::error::BackupError;
+use crate::manifest::SManifest;
+use crate::repository::SRepository;
+use crate::storage::{self, StorageBackend};
+
+pub struct SPruneEntry {
+    pub snapshot: SSnapshotInfo,
+    pub keep: bool,
+    /// Rule that keeps the snapshot, or why it goes
+    pub reason: String,
+}
+
+/// What `prune` would do to the snapshots of one profile, newest first
+pub struct SPrunePlan {
+    pub destination: PathBuf,
+    pub entries: Vec<SPruneEntry>,
+    storage: Arc<dyn StorageBackend>,
+    /// Of an encrypted destination, its backups' configs and repository are read with it
+    key: Option<SKey>,
+}
+
+impl SPrunePlan {
+    pub fn pruned(&self) -> impl Iterator<Item = &SPruneEntry> {
+        self.entries.iter().filter(|entry| !entry.keep)
+    }
+
+    pub fn pruned_size(&self) -> u64 {
+        self.pruned().map(|entry| entry.snapshot.size).sum()
+    }
+}
+
+/// Applies the retention rules of `config` to its profile's snapshots in `destination`.
+/// Nothing is deleted here.
+pub fn plan(
+    destination: &Path,
+    config: &SBackupConfig,
+    key: Option<&EKeySource>,
+) -> Result<SPrunePlan, BackupError> {
+    let storage = storage::open(destination);
+    let key = encryption::unlock(&*storage, key)?;
+    let catalog = SCatalog::load_from(&*storage)?;
+    let profile = config.profile_name();
+    let snapshots: Vec<SSnapshotInfo> = catalog
+        .snapshots
+        .into_iter()
+        .rev()
+        .filter(|snapshot| snapshot.profile == profile)
+        .collect();
+
+    let reasons = keep_reasons(destination, &snapshots, &config.retention, key.as_ref())?;
+    let entries = snapshots
+        .into_iter()
+        .zip(reasons)
+        .map(|(snapshot, (keep, reason))| SPruneEntry {
+            snapshot,
+            keep,
+            reason,
+        })
+        .collect();
+
+    Ok(SPrunePlan {
+        destination: destination.to_path_buf(),
+        entries,
+        storage,
+        key,
+    })
+}
+
+/// Whether each snapshot is kept and why, `snapshots` are newest first
+fn keep_reasons(
+    destination: &Path,
+    snapshots: &[SSnapshotInfo],
+    retention: &SRetention,
+    key: Option<&SKey>,
+) -> Result<Vec<(bool, String)>, BackupError> {
+    if retention.is_empty() {
+        return Ok(vec![
+            (true, "no retention rules".to_string());
+            snapshots.len()
+        ]);
+    }
+
+    let mut over_size = vec![false; snapshots.len()];
+    let mut reasons: Vec<Option<String>> = vec![None; snapshots.len()];
+    let complete: Vec<usize> = (0..snapshots.len())
+        .filter(|&i| snapshots[i].status == ESnapshotStatus::Complete)
+        .collect();
+
+    if let Some(&newest) = complete.first() {
+        reasons[newest] = Some("newest".to_string());
+    }
+
+    for &i in complete
+        .iter()
+        .take(retention.keep_last.unwrap_or(0) as usize)
+    {
+        reasons[i].get_or_insert_with(|| "last".to_string());
+    }
+
+    // The newest snapshot of each period is kept, for the N newest periods
+    let periods = [
+        (retention.keep_daily, "daily", "%Y-%m-%d"),
+        (retention.keep_weekly, "weekly", "%G-W%V"),
+        (retention.keep_monthly, "monthly", "%Y-%m"),
+    ];
+    for (count, rule, format) in periods {
+        let Some(count) = count else {
+            continue;
+        };
+        let mut last_period = None;
+        let mut kept = 0;
+        for &i in &complete {
+            let period = snapshots[i].time.format(format).to_string();
+            if last_period.as_ref() == Some(&period) {
+                continue;
+            }
+            if kept >= count {
+                break;
+            }
+            reasons[i].get_or_insert_with(|| format!("{} {}", rule, period));
+            last_period = Some(period);
+            kept += 1;
+        }
+    }
+
+    // A skip mode backup only holds changed files, the rest stay in the older snapshots
+    // its manifest names, so those can not go while it is kept
+    let mut needs: Vec<Vec<usize>> = vec![Vec::new(); snapshots.len()];
+    let mut depending: Vec<usize> = (0..snapshots.len())
+        .filter(|&i| reasons[i].is_some())
+        .collect();
+    while let Some(kept) = depending.pop() {
+        let backup_folder = destination.join(&snapshots[kept].name);
+        for name in needed_snapshots(&backup_folder, key)? {
+            let Some(i) = snapshots.iter().position(|snapshot| snapshot.name == name) else {
+                continue;
+            };
+            needs[kept].push(i);
+            if reasons[i].is_none() {
+                reasons[i] = Some(format!("needed by {}", snapshots[kept].name));
+                depending.push(i);
+            }
+        }
+    }
+
+    // The oldest snapshots go first, except the newest one and those a kept snapshot
+    // needs. One that goes can free the ones it needed, so this repeats until nothing
+    // more can go.
+    if let Some(max_total_size) = retention.max_total_size {
+        let mut total: u64 = (0..snapshots.len())
+            .filter(|&i| reasons[i].is_some())
+            .map(|i| snapshots[i].size)
+            .sum();
+        let mut dropped = true;
+        while total > max_total_size && dropped {
+            dropped = false;
+            for i in (0..snapshots.len()).rev() {
+                if total <= max_total_size {
+                    break;
+                }
+                let needed =
+                    (0..snapshots.len()).any(|j| reasons[j].is_some() && needs[j].contains(&i));
+                if reasons[i].is_some() && Some(&i) != complete.first() && !needed {
+                    reasons[i] = None;
+                    over_size[i] = true;
+                    total -= snapshots[i].size;
+                    dropped = true;
+                }
+            }
+        }
+        // What is still over the limit is needed by a kept snapshot
+        if total > max_total_size {
+            for i in (0..snapshots.len()).filter(|&i| Some(&i) != complete.first()) {
+                if let Some(reason) = &mut reasons[i] {
+                    reason.push_str(", over the size limit");
+                }
+            }
+        }
+    }
+
+    Ok(reasons
+        .into_iter()
+        .enumerate()
+        .map(|(i, reason)| match reason {
+            Some(reason) => (true, reason),
+            None if over_size[i] => (false, "over the size limit".to_string()),
+            None if snapshots[i].status == ESnapshotStatus::Incomplete => {
+                (false, "incomplete".to_string())
+            }
+            None => (false, "not kept by any rule".to_string()),
+        })
+        .collect())
+}
+
+/// Names of the snapshots a skip mode backup keeps some of its files in, none for other backups
+pub fn needed_snapshots(
+    backup_folder: &Path,
+    key: Option<&SKey>,
+) -> Result<BTreeSet<String>, BackupError> {
+    let made_in_skip_mode = SBackupConfig::load_from_backup(backup_folder, key)
+        .is_ok_and(|config| config.incremental == EIncrementalMode::Skip);
+    if !made_in_skip_mode {
+        return Ok(BTreeSet::new());
+    }
+    Ok(SManifest::load(backup_folder, key)?
+        .files
+        .into_iter()
+        .filter_map(|entry| entry.snapshot)
+        .collect())
+}
+
+/// Deletes the pruned snapshots of `plan`. Each one leaves the catalog before its folder
+/// is removed, so a failed removal never leaves a half deleted snapshot that later
+/// incremental backups would take files from.
+pub fn apply(plan: &SPrunePlan) -> Result<usize, BackupError> {
+    let mut catalog = SCatalog::load_from(&*plan.storage)?;
+    let mut repository = if SRepository::is_repository(&*plan.storage) {
+        Some(SRepository::open(plan.storage.clone(), plan.key.clone())?)
+    } else {
+        None
+    };
+    let mut removed = 0;
+
+    for entry in plan.pruned() {
+        let name = &entry.snapshot.name;
+        let mut components = Path::new(name).components();
+        let is_plain_name =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        let folder = plan.destination.join(name);
+        if !is_plain_name {
+            return Err(BackupError::invalid_path("prune snapshot", &folder));
+        }
+
+        if let Some(repository) = &repository {
+            catalog.remove(name);
+            catalog.save(&*plan.storage)?;
+            if repository.has_snapshot(name) {
+                repository.remove_snapshot(name)?;
+            }
+            removed += 1;
+            continue;
+        }
+
+        // Only folders that look like a backup are deleted
+        let is_archive = EArchiveFormat::of_file(&folder).is_some();
+        if folder.exists() && !is_archive && !folder.join("backup_config.toml").is_file() {
+            return Err(BackupError::invalid_path("prune snapshot", &folder));
+        }
+
+        catalog.remove(name);
+        catalog.save(&*plan.storage)?;
+        // What a failed backup wrote stays in the staging folder
+        let staging = backup_service::staging_path(&plan.destination, name);
+        if staging.is_dir() {
+            fs::remove_dir_all(&staging).map_err(BackupError::io("remove snapshot", &staging))?;
+        } else if staging.is_file() {
+            fs::remove_file(&staging).map_err(BackupError::io("remove snapshot", &staging))?;
+        }
+        if is_archive {
+            fs::remove_file(&folder).map_err(BackupError::io("remove snapshot", &folder))?;
+        } else if folder.exists() {
+            fs::remove_dir_all(&folder).map_err(BackupError::io("remove snapshot", &folder))?;
+        }
+        removed += 1;
+    }
+
+    // Chunks and trees only the removed snapshots used
+    if let Some(repository) = &mut repository {
+        repository.collect_garbage()?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{backup_details, folder_config, SNoProgress, STestFolder};
+
+    #[test]
+    fn skip_mode_snapshot_keeps_only_the_snapshots_its_manifest_names() {
+        let folder = STestFolder::new("retention-skip");
+        folder.write("src/data/a.txt", b"a");
+        folder.write("src/data/b.txt", b"b");
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let mut config = folder_config(&folder.path().join("src/data"), EIncrementalMode::Skip);
+        for (name, contents) in [("one", "a1"), ("two", "a22"), ("three", "a333")] {
+            backup_service::backup(
+                &config,
+                &backup_details(&destination, name),
+                &mut SNoProgress,
+            )
+            .unwrap();
+            folder.write("src/data/a.txt", contents.as_bytes());
+        }
+
+        config.retention.keep_last = Some(1);
+        let plan = plan(&destination, &config, None).unwrap();
+        let kept: Vec<(&str, bool, &str)> = plan
+            .entries
+            .iter()
+            .map(|entry| {
+                let name = entry.snapshot.name.as_str();
+                (name, entry.keep, entry.reason.as_str())
+            })
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                ("three", true, "newest"),
+                ("two", false, "not kept by any rule"),
+                ("one", true, "needed by three"),
+            ]
+        );
+
+        assert_eq!(apply(&plan).unwrap(), 1);
+        assert!(!destination.join("two").exists());
+        assert!(destination.join("one/data/b.txt").is_file());
+    }
+
+    #[test]
+    fn size_limit_keeps_what_kept_snapshots_need() {
+        let folder = STestFolder::new("retention-size");
+        folder.write("src/data/a.txt", b"a");
+        folder.write("src/data/b.txt", b"b");
+        let destination = folder.path().join("dest");
+        fs::create_dir(&destination).unwrap();
+        let mut config = folder_config(&folder.path().join("src/data"), EIncrementalMode::Skip);
+        for (name, contents) in [("one", "a1"), ("two", "a22"), ("three", "a333")] {
+            backup_service::backup(
+                &config,
+                &backup_details(&destination, name),
+                &mut SNoProgress,
+            )
+            .unwrap();
+            folder.write("src/data/a.txt", contents.as_bytes());
+        }
+        config.retention.keep_last = Some(3);
+        let kept = |config: &SBackupConfig| -> Vec<(String, bool, String)> {
+            plan(&destination, config, None)
+                .unwrap()
+                .entries
+                .into_iter()
+                .map(|entry| (entry.snapshot.name, entry.keep, entry.reason))
+                .collect()
+        };
+        let sizes: Vec<u64> = plan(&destination, &config, None)
+            .unwrap()
+            .entries
+            .iter()
+            .map(|entry| entry.snapshot.size)
+            .collect();
+
+        // "one" is older, but "three" needs it, so "two" goes instead
+        config.retention.max_total_size = Some(sizes[0] + sizes[2]);
+        assert_eq!(
+            kept(&config),
+            [
+                ("three".to_string(), true, "newest".to_string()),
+                ("two".to_string(), false, "over the size limit".to_string()),
+                ("one".to_string(), true, "last".to_string()),
+            ]
+        );
+
+        config.retention.max_total_size = Some(1);
+        assert_eq!(
+            kept(&config),
+            [
+                ("three".to_string(), true, "newest".to_string()),
+                ("two".to_string(), false, "over the size limit".to_string()),
+                (
+                    "one".to_string(),
+                    true,
+                    "last, over the size limit".to_string()
+                ),
+            ]
+        );
+    }
+}
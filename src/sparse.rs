@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Blocks of zeros this long become holes, smaller ones take a file system block anyway
+const BLOCK_SIZE: u64 = 4096;
+
+/// Writes a file that keeps the holes of a sparse one. Whole blocks of zeros are seeked over
+/// instead of written, everything is written as is when `sparse` is off.
+pub struct SSparseWriter {
+    file: File,
+    sparse: bool,
+    position: u64,
+    /// Zeros seen since the last written byte
+    hole: u64,
+}
+
+impl SSparseWriter {
+    pub fn new(file: File, sparse: bool) -> SSparseWriter {
+        SSparseWriter {
+            file,
+            sparse,
+            position: 0,
+            hole: 0,
+        }
+    }
+
+    /// Sets the length of a file that ends in a hole and gives the file back
+    pub fn finish(self) -> io::Result<File> {
+        if self.hole > 0 {
+            self.file.set_len(self.position)?;
+        }
+        Ok(self.file)
+    }
+}
+
+impl Write for SSparseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.sparse {
+            return self.file.write(buf);
+        }
+        // One block at a time, so a block of zeros is never half written
+        let block_left = (BLOCK_SIZE - self.position % BLOCK_SIZE) as usize;
+        let block = &buf[..buf.len().min(block_left)];
+        if block.iter().all(|byte| *byte == 0) {
+            self.hole += block.len() as u64;
+        } else {
+            if self.hole > 0 {
+                self.file.seek(SeekFrom::Current(self.hole as i64))?;
+                self.hole = 0;
+            }
+            self.file.write_all(block)?;
+        }
+        self.position += block.len() as u64;
+        Ok(block.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,159 @@
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{SObjectStat, StorageBackend};
+use crate::error::BackupError;
+
+/// A destination folder on a local file system
+pub struct SLocalStorage {
+    root: PathBuf,
+}
+
+impl SLocalStorage {
+    pub fn new(root: &Path) -> SLocalStorage {
+        SLocalStorage {
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+impl StorageBackend for SLocalStorage {
+    /// Written to a temporary file next to `path` and flushed to the disk before it is renamed
+    fn put(&self, path: &str, contents: &[u8]) -> Result<(), BackupError> {
+        let target = self.location(path);
+        let folder = target.parent().unwrap_or(&self.root);
+        fs::create_dir_all(folder).map_err(BackupError::io("create folder", folder))?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or_default();
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        let temp_path = folder.join(format!(".{}.{}-{}.tmp", name, std::process::id(), nanos));
+
+        let written = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(contents)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp_path, &target));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(BackupError::io("write", &target)(e));
+        }
+        Ok(())
+    }
+
+    fn get(&self, path: &str) -> Result<Vec<u8>, BackupError> {
+        let path = self.location(path);
+        fs::read(&path).map_err(BackupError::io("read", &path))
+    }
+
+    fn get_range(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>, BackupError> {
+        let path = self.location(path);
+        let mut contents = vec![0; length as usize];
+        File::open(&path)
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut contents)
+            })
+            .map_err(BackupError::io("read", &path))?;
+        Ok(contents)
+    }
+
+    fn list(&self, folder: &str) -> Result<Vec<String>, BackupError> {
+        let folder = self.location(folder);
+        let entries = match fs::read_dir(&folder) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(BackupError::io("list", &folder)(e)),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(BackupError::io("list", &folder))?;
+            if entry.file_type().is_ok_and(|file_type| file_type.is_file()) {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    fn delete(&self, path: &str) -> Result<(), BackupError> {
+        let path = self.location(path);
+        fs::remove_file(&path).map_err(BackupError::io("remove", &path))
+    }
+
+    fn stat(&self, path: &str) -> Result<Option<SObjectStat>, BackupError> {
+        let path = self.location(path);
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => Ok(Some(SObjectStat {
+                size: metadata.len(),
+            })),
+            Ok(_) => Ok(None),
+            // Below a file, e.g. in a backup archive, nothing can be found either
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                Ok(None)
+            }
+            Err(e) => Err(BackupError::io("read", &path)(e)),
+        }
+    }
+
+    fn location(&self, path: &str) -> PathBuf {
+        match path {
+            "" => self.root.clone(),
+            _ => self.root.join(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::STestFolder;
+
+    fn io_kind(result: Result<Vec<u8>, BackupError>) -> Option<ErrorKind> {
+        match result {
+            Err(BackupError::Io { source, .. }) => Some(source.kind()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn objects_are_written_read_and_removed_by_their_relative_path() {
+        let folder = STestFolder::new("local-storage");
+        let storage = SLocalStorage::new(folder.path());
+
+        storage.put("data/a/object", b"0123456789").unwrap();
+        storage.put("data/a/object", b"abcdefghij").unwrap();
+        assert_eq!(storage.get("data/a/object").unwrap(), b"abcdefghij");
+        assert_eq!(storage.get_range("data/a/object", 3, 4).unwrap(), b"defg");
+        assert_eq!(storage.stat("data/a/object").unwrap().unwrap().size, 10);
+        // Nothing but the object is left next to it
+        assert_eq!(storage.list("data/a").unwrap(), ["object"]);
+        assert!(storage.list("data").unwrap().is_empty());
+        assert!(storage.list("missing").unwrap().is_empty());
+
+        assert_eq!(
+            io_kind(storage.get_range("data/a/object", 8, 4)),
+            Some(ErrorKind::UnexpectedEof)
+        );
+        storage.delete("data/a/object").unwrap();
+        assert!(!storage.exists("data/a/object"));
+        assert!(storage.stat("data/a/object").unwrap().is_none());
+        assert_eq!(
+            io_kind(storage.get("data/a/object")),
+            Some(ErrorKind::NotFound)
+        );
+    }
+
+    #[test]
+    fn nothing_is_found_below_a_file() {
+        let folder = STestFolder::new("local-storage-below-file");
+        folder.write("archive.tar", b"not a folder");
+        let storage = SLocalStorage::new(folder.path());
+
+        assert!(storage.stat("archive.tar/object").unwrap().is_none());
+        assert!(storage.stat("").unwrap().is_none());
+    }
+}
@@ -0,0 +1,48 @@
+mod local;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::BackupError;
+
+pub use local::SLocalStorage;
+
+pub struct SObjectStat {
+    pub size: u64,
+}
+
+/// Where the catalog, encryption header and repository of a destination are kept. Folder
+/// backups and archives are written with the file system, only these go through a backend.
+/// Objects are named by `/` separated paths relative to the destination, folders are created
+/// as needed.
+pub trait StorageBackend: Send + Sync {
+    /// Writes `contents` under `path` as a whole, a reader sees the old object or the new one
+    fn put(&self, path: &str, contents: &[u8]) -> Result<(), BackupError>;
+
+    /// A missing object is an io error of kind `NotFound`
+    fn get(&self, path: &str) -> Result<Vec<u8>, BackupError>;
+
+    /// `length` bytes from `offset` on, an object that ends before is an io error of kind
+    /// `UnexpectedEof`
+    fn get_range(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>, BackupError>;
+
+    /// Names of the objects right inside `folder`, empty when there is no such folder
+    fn list(&self, folder: &str) -> Result<Vec<String>, BackupError>;
+
+    fn delete(&self, path: &str) -> Result<(), BackupError>;
+
+    /// `None` when there is no such object
+    fn stat(&self, path: &str) -> Result<Option<SObjectStat>, BackupError>;
+
+    /// How `path` is shown in messages, `""` is the destination itself
+    fn location(&self, path: &str) -> PathBuf;
+
+    fn exists(&self, path: &str) -> bool {
+        self.stat(path).is_ok_and(|stat| stat.is_some())
+    }
+}
+
+/// The storage of a destination folder
+pub fn open(location: &Path) -> Arc<dyn StorageBackend> {
+    Arc::new(SLocalStorage::new(location))
+}
@@ -0,0 +1,107 @@
+//! Helpers for the unit tests
+
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+use crate::backup_service::backup;
+use crate::config::{ECompression, EElementType, EIncrementalMode, SBackupConfig, SConfigElement};
+use crate::progress::{EProgressEvent, ProgressObserver, SProgress};
+use crate::repository::EDestinationFormat;
+use crate::ui::backup::SBackupUI;
+use crate::ui::recovery::SRecoveryPanel;
+
+/// An empty folder of its own under the temporary folder, removed when dropped
+pub struct STestFolder {
+    path: PathBuf,
+}
+
+impl STestFolder {
+    pub fn new(name: &str) -> STestFolder {
+        let path = env::temp_dir().join(format!("backup-nf-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        STestFolder { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `contents` to `relative`, creating the folders above it
+    pub fn write(&self, relative: &str, contents: &[u8]) -> PathBuf {
+        let path = self.path.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for STestFolder {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+pub struct SNoProgress;
+
+impl ProgressObserver for SNoProgress {
+    fn on_progress(&mut self, _event: EProgressEvent, _progress: &SProgress) {}
+}
+
+/// A config backing up the folder `source`
+pub fn folder_config(source: &Path, incremental: EIncrementalMode) -> SBackupConfig {
+    let mut config = SBackupConfig::new();
+    config.name = "test".to_string();
+    config.incremental = incremental;
+    config.elements.push(SConfigElement::new(
+        source.to_string_lossy().to_string(),
+        EElementType::Folder,
+    ));
+    config
+}
+
+/// An uncompressed folder backup named `name` into `destination`
+pub fn backup_details(destination: &Path, name: &str) -> SBackupUI {
+    SBackupUI {
+        folder_name: name.to_string(),
+        folder_path: destination.to_string_lossy().to_string(),
+        format: EDestinationFormat::Folder,
+        compression: ECompression::None,
+        key: None,
+        resume: None,
+        bandwidth_limit: None,
+    }
+}
+
+/// A restore of `backup_folder` under `restore_root`, keeping the full paths
+pub fn restore_panel(backup_folder: &Path, restore_root: &Path) -> SRecoveryPanel {
+    let mut panel = SRecoveryPanel::new();
+    panel.backup_folder = backup_folder.to_string_lossy().to_string();
+    panel.restore_root = Some(restore_root.to_path_buf());
+    panel
+}
+
+/// Two skip mode backups of `src/data` into `dest`, "one" of `a.txt` and `sub/b.txt`,
+/// then "two" once `a.txt` changed. Returns the source and the destination.
+pub fn skip_backups(folder: &STestFolder) -> (PathBuf, PathBuf) {
+    folder.write("src/data/a.txt", b"a");
+    folder.write("src/data/sub/b.txt", b"b");
+    let source = folder.path().join("src/data");
+    let destination = folder.path().join("dest");
+    fs::create_dir(&destination).unwrap();
+    let config = folder_config(&source, EIncrementalMode::Skip);
+    backup(
+        &config,
+        &backup_details(&destination, "one"),
+        &mut SNoProgress,
+    )
+    .unwrap();
+    folder.write("src/data/a.txt", b"a2");
+    backup(
+        &config,
+        &backup_details(&destination, "two"),
+        &mut SNoProgress,
+    )
+    .unwrap();
+    (source, destination)
+}
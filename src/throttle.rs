@@ -0,0 +1,180 @@
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::BackupError;
+use crate::progress::{EProgressEvent, ProgressObserver, SProgress};
+
+/// Bandwidth left unused is saved up for at most this long, so that a pause, e.g. for
+/// unchanged files, does not let the next files through at full speed
+const BURST: Duration = Duration::from_secs(1);
+/// Longest sleep before a throttled job checks whether it was cancelled
+const CANCEL_CHECK: Duration = Duration::from_millis(100);
+
+/// Bytes per second shared by every thread of a job
+pub struct SThrottle {
+    limit: u64,
+    /// When the bytes passed so far are within the limit
+    until: Mutex<Instant>,
+}
+
+impl SThrottle {
+    pub fn new(limit: u64) -> SThrottle {
+        SThrottle {
+            limit: limit.max(1),
+            until: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Sleeps until `bytes` more are within the limit or `cancelled` returns true
+    pub fn pass(&self, bytes: u64, cancelled: impl Fn() -> bool) {
+        let until = {
+            let mut until = self.until.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            let saved_up = now.checked_sub(BURST).unwrap_or(now);
+            *until =
+                (*until).max(saved_up) + Duration::from_secs_f64(bytes as f64 / self.limit as f64);
+            *until
+        };
+        loop {
+            let now = Instant::now();
+            if until <= now || cancelled() {
+                return;
+            }
+            thread::sleep((until - now).min(CANCEL_CHECK));
+        }
+    }
+}
+
+/// Passes progress on to the observer of a job and holds the job to its bandwidth limit
+pub struct SThrottledObserver<'a> {
+    inner: &'a mut dyn ProgressObserver,
+    throttle: Option<Arc<SThrottle>>,
+}
+
+impl<'a> SThrottledObserver<'a> {
+    /// Bytes per second, no limit when `limit` is `None`
+    pub fn new(inner: &'a mut dyn ProgressObserver, limit: Option<u64>) -> SThrottledObserver<'a> {
+        SThrottledObserver {
+            inner,
+            throttle: limit.map(|limit| Arc::new(SThrottle::new(limit))),
+        }
+    }
+}
+
+impl ProgressObserver for SThrottledObserver<'_> {
+    fn on_progress(&mut self, event: EProgressEvent, progress: &SProgress) {
+        self.inner.on_progress(event, progress)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+
+    fn throttle(&self) -> Option<&Arc<SThrottle>> {
+        self.throttle.as_ref()
+    }
+}
+
+/// Runs the calling thread, and the threads it starts afterwards, at idle I/O priority
+/// and with `nice` added to its CPU nice level. Idle I/O priority needs Linux, elsewhere
+/// the nice level is that of the whole process.
+pub fn lower_priority(idle_io: bool, nice: i32) -> Result<(), BackupError> {
+    let failed = |operation| move |source| BackupError::Priority { operation, source };
+    if idle_io {
+        set_idle_io().map_err(failed("set idle I/O priority"))?;
+    }
+    if nice != 0 {
+        add_nice(nice).map_err(failed("change nice level"))?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_idle_io() -> io::Result<()> {
+    // From linux/ioprio.h, which libc does not have
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    // SAFETY: ioprio_set only takes integers, 0 is the calling thread
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        )
+    };
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_idle_io() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "only supported on Linux",
+    ))
+}
+
+#[cfg(unix)]
+fn add_nice(nice: i32) -> io::Result<()> {
+    // SAFETY: both only take integers, 0 is the calling thread on Linux and the
+    // process elsewhere. The current level of the caller cannot fail to be read.
+    let result = unsafe {
+        let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+        libc::setpriority(libc::PRIO_PROCESS, 0, current.saturating_add(nice))
+    };
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn add_nice(_nice: i32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "only supported on Unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_pass_at_the_limit() {
+        let throttle = SThrottle::new(10_000);
+        let start = Instant::now();
+        for _ in 0..4 {
+            throttle.pass(1_000, || false);
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(900), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn unused_bandwidth_is_saved_up_for_at_most_a_burst() {
+        let throttle = SThrottle::new(10_000);
+        *throttle.until.lock().unwrap() = Instant::now() - 10 * BURST;
+        let start = Instant::now();
+        // A second's worth goes through at once, the rest at the limit
+        throttle.pass(10_000, || false);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        throttle.pass(2_000, || false);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn cancelled_job_stops_waiting() {
+        let throttle = SThrottle::new(1);
+        let start = Instant::now();
+        throttle.pass(1_000, || true);
+        assert!(start.elapsed() < CANCEL_CHECK);
+    }
+}
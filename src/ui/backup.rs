@@ -14,20 +14,48 @@ use ratatui::{
 
 use tui_textarea::TextArea;
 
+use crate::archive::EArchiveFormat;
+use crate::backup_service::{self, SResumableBackup};
+use crate::catalog::{SSnapshotInfo, DEFAULT_NAME_TEMPLATE};
 use crate::config::*;
+use crate::encryption::EKeySource;
+use crate::job::SJob;
+use crate::parallel;
+use crate::progress::format_size;
+use crate::repository::EDestinationFormat;
 
 use super::file_picker;
+use super::passphrase;
+use super::plan;
+use super::progress::{self, SProgressView};
+use super::prune;
 
+#[derive(Clone)]
 pub struct SBackupUI {
     pub folder_name: String,
     pub folder_path: String,
+    /// Ignored when the destination already is a repository
+    pub format: EDestinationFormat,
+    /// For elements that do not set their own
+    pub compression: ECompression,
+    /// Encrypts a new destination, unlocks one that is encrypted already
+    pub key: Option<EKeySource>,
+    /// Name of the interrupted backup to continue, see `backup_service::resumable_backup`
+    pub resume: Option<String>,
+    /// Bytes per second of this run, overrides the config. 0 for no limit
+    pub bandwidth_limit: Option<u64>,
 }
 
 impl SBackupUI {
     fn new() -> SBackupUI {
         SBackupUI {
-            folder_name: "backup".to_string(),
+            folder_name: DEFAULT_NAME_TEMPLATE.to_string(),
             folder_path: "".to_string(),
+            format: EDestinationFormat::Folder,
+            compression: ECompression::None,
+            key: None,
+            resume: None,
+            bandwidth_limit: None,
         }
     }
 }
@@ -35,7 +63,7 @@ impl SBackupUI {
 pub fn start(
     terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
     config: &SBackupConfig,
-    mut start_backup: impl FnMut(&SBackupConfig, &SBackupUI),
+    mut start_backup: impl FnMut(&SBackupConfig, &SBackupUI) -> SJob<SSnapshotInfo>,
 ) -> Result<(), Error> {
     let mut textarea = TextArea::default();
     textarea.set_block(
@@ -44,23 +72,30 @@ pub fn start(
             .border_style(Style::default().fg(Color::White))
             .title_bottom("CANCEL(ESC) SELECT(ENTER)")
             .title_alignment(Alignment::Center)
-            .title("Enter backup folder name: "),
+            .title("Enter backup folder name, {profile} and {date} are filled in: "),
     );
 
     textarea.set_style(Style::default().fg(Color::Yellow));
     textarea.set_placeholder_style(Style::default());
-    textarea.set_placeholder_text("backup");
+    textarea.set_placeholder_text(DEFAULT_NAME_TEMPLATE);
 
     let mut working = true;
     let mut enter_text = false;
 
-    let mut show_error = false;
+    let mut error: Option<String> = None;
+    // Shown after a backup that went through but left something out
+    let mut warning: Option<String> = None;
+    // An interrupted backup found when starting one, the user picks whether to continue it
+    let mut resumable: Option<SResumableBackup> = None;
+    let mut run_backup = false;
 
     let mut backup = SBackupUI::new();
+    // Options toggled here apply to this run only
+    let mut config = config.clone();
 
-    Ok(while working {
+    while working {
         terminal.borrow_mut().draw(|f| {
-            ui(f, &backup);
+            ui(f, &backup, &config);
             if enter_text {
                 let (_end, _save) = enter_folder_name(f, &mut textarea);
                 if _end {
@@ -70,19 +105,59 @@ pub fn start(
                 if _save {
                     backup.folder_name = textarea.lines()[0].clone();
                 }
-            } else {
-                if show_error {
-                    if ui_error(
-                        f,
-                        "Не указано имя папки, папка или в конфиге нету элементов".to_string(),
-                        "Close(ESC)".to_string(),
-                    ) {
-                        show_error = false
-                    }
-                }
+            } else if let Some(message) = &error {
+                ui_modal(f, " ERROR! ", Color::Red, message.clone(), "Close(ESC)");
+            } else if let Some(message) = &warning {
+                ui_modal(f, " WARNING ", Color::Yellow, message.clone(), "Close(ESC)");
+            } else if let Some(found) = &resumable {
+                let text = format!(
+                    "Resume previous incomplete backup {} from {}? {} files, {} are copied already",
+                    found.name,
+                    found.time.format("%Y-%m-%d %H:%M"),
+                    found.files,
+                    format_size(found.size)
+                );
+                ui_modal(
+                    f,
+                    " RESUME ",
+                    Color::Yellow,
+                    text,
+                    "Resume(Y) Start over(N) Cancel(ESC)",
+                );
             }
         })?;
-        if !enter_text && !show_error {
+        if !enter_text && (error.is_some() || warning.is_some()) {
+            handle_evnets(|key: KeyCode| {
+                if key == KeyCode::Esc {
+                    error = None;
+                    warning = None;
+                }
+            });
+        } else if let Some(name) = resumable.as_ref().map(|found| found.name.clone()) {
+            handle_evnets(|key: KeyCode| {
+                if key == KeyCode::Char('y')
+                    || key == KeyCode::Char('Y')
+                    || key == KeyCode::Char('н')
+                    || key == KeyCode::Char('Н')
+                {
+                    backup.resume = Some(name.clone());
+                    run_backup = true;
+                }
+                if key == KeyCode::Char('n')
+                    || key == KeyCode::Char('N')
+                    || key == KeyCode::Char('т')
+                    || key == KeyCode::Char('Т')
+                {
+                    match backup_service::discard_resumable(&backup, &name) {
+                        Ok(()) => run_backup = true,
+                        Err(e) => error = Some(e.to_string()),
+                    }
+                }
+                if run_backup || error.is_some() || key == KeyCode::Esc {
+                    resumable = None;
+                }
+            });
+        } else if !enter_text {
             let callback = |key: KeyCode| {
                 if key == KeyCode::Char('q')
                     || key == KeyCode::Char('Q')
@@ -109,6 +184,152 @@ pub fn start(
                     file_picker::start(terminal, callback, EElementType::Folder).unwrap();
                 }
 
+                if key == KeyCode::Char('I')
+                    || key == KeyCode::Char('i')
+                    || key == KeyCode::Char('ш')
+                    || key == KeyCode::Char('Ш')
+                {
+                    config.incremental = match config.incremental {
+                        EIncrementalMode::Off => EIncrementalMode::HardLink,
+                        EIncrementalMode::HardLink => EIncrementalMode::Skip,
+                        EIncrementalMode::Skip => EIncrementalMode::Off,
+                    };
+                }
+
+                if key == KeyCode::Char('R')
+                    || key == KeyCode::Char('r')
+                    || key == KeyCode::Char('к')
+                    || key == KeyCode::Char('К')
+                {
+                    backup.format = match backup.format {
+                        EDestinationFormat::Folder => EDestinationFormat::Repository,
+                        EDestinationFormat::Repository => {
+                            EDestinationFormat::Archive(EArchiveFormat::Tar)
+                        }
+                        EDestinationFormat::Archive(EArchiveFormat::Tar) => {
+                            EDestinationFormat::Archive(EArchiveFormat::TarZst)
+                        }
+                        EDestinationFormat::Archive(EArchiveFormat::TarZst) => {
+                            EDestinationFormat::Archive(EArchiveFormat::Zip)
+                        }
+                        EDestinationFormat::Archive(EArchiveFormat::Zip) => {
+                            EDestinationFormat::Folder
+                        }
+                    };
+                }
+
+                if key == KeyCode::Char('C')
+                    || key == KeyCode::Char('c')
+                    || key == KeyCode::Char('с')
+                    || key == KeyCode::Char('С')
+                {
+                    backup.compression = match backup.compression {
+                        ECompression::None => ECompression::Gzip,
+                        ECompression::Gzip => ECompression::Zstd(ECompression::DEFAULT_ZSTD_LEVEL),
+                        ECompression::Zstd(_) => ECompression::Lz4,
+                        ECompression::Lz4 => ECompression::None,
+                    };
+                }
+
+                if let ECompression::Zstd(level) = &mut backup.compression {
+                    let levels = zstd::compression_level_range();
+                    if key == KeyCode::Char('+') && *level < *levels.end() {
+                        *level += 1;
+                    }
+                    if key == KeyCode::Char('-') && *level > 1 {
+                        *level -= 1;
+                    }
+                }
+
+                if key == KeyCode::Char('E')
+                    || key == KeyCode::Char('e')
+                    || key == KeyCode::Char('у')
+                    || key == KeyCode::Char('У')
+                {
+                    backup.key = match backup.key {
+                        None => {
+                            let title = "Enter passphrase:";
+                            let first =
+                                passphrase::ask(terminal, title, |f| ui(f, &backup, &config))
+                                    .unwrap();
+                            let title = "Repeat passphrase:";
+                            let second = first.as_ref().and_then(|_| {
+                                passphrase::ask(terminal, title, |f| ui(f, &backup, &config))
+                                    .unwrap()
+                            });
+                            match (first, second) {
+                                (Some(first), Some(second)) if first != second => {
+                                    error = Some("Пароли не совпадают".to_string());
+                                    None
+                                }
+                                (Some(first), Some(_)) if first.is_empty() => {
+                                    error = Some("Пароль не может быть пустым".to_string());
+                                    None
+                                }
+                                (Some(first), Some(_)) => Some(EKeySource::Passphrase(first)),
+                                _ => None,
+                            }
+                        }
+                        Some(EKeySource::Passphrase(_)) => passphrase::pick_key_file(terminal)
+                            .unwrap()
+                            .map(EKeySource::KeyFile),
+                        Some(EKeySource::KeyFile(_)) => None,
+                    };
+                }
+
+                if key == KeyCode::Char('H')
+                    || key == KeyCode::Char('h')
+                    || key == KeyCode::Char('р')
+                    || key == KeyCode::Char('Р')
+                {
+                    config.compare_hash = !config.compare_hash;
+                }
+
+                if key == KeyCode::Char('T')
+                    || key == KeyCode::Char('t')
+                    || key == KeyCode::Char('е')
+                    || key == KeyCode::Char('Е')
+                {
+                    config.threads = match config.threads {
+                        0 => 1,
+                        threads if threads >= 16 => 0,
+                        threads => threads * 2,
+                    };
+                }
+
+                if key == KeyCode::Char('P')
+                    || key == KeyCode::Char('p')
+                    || key == KeyCode::Char('з')
+                    || key == KeyCode::Char('З')
+                {
+                    if backup.folder_path.is_empty() {
+                        error = Some("Не указана папка с бэкапами".to_string());
+                    } else {
+                        prune::start(terminal, &config, &backup.folder_path, backup.key.as_ref())
+                            .unwrap();
+                    }
+                }
+
+                if key == KeyCode::Char('В')
+                    || key == KeyCode::Char('в')
+                    || key == KeyCode::Char('D')
+                    || key == KeyCode::Char('d')
+                {
+                    if backup.folder_name.is_empty()
+                        || backup.folder_path.is_empty()
+                        || config.elements.is_empty()
+                    {
+                        error = Some(
+                            "Не указано имя папки, папка или в конфиге нету элементов".to_string(),
+                        );
+                    } else {
+                        match backup_service::backup_plan(&config, &backup) {
+                            Ok(backup_plan) => plan::start(terminal, &backup_plan).unwrap(),
+                            Err(e) => error = Some(e.to_string()),
+                        }
+                    }
+                }
+
                 if key == KeyCode::Char('Ы')
                     || key == KeyCode::Char('ы')
                     || key == KeyCode::Char('S')
@@ -118,19 +339,45 @@ pub fn start(
                         || backup.folder_path.is_empty()
                         || config.elements.is_empty()
                     {
-                        show_error = true;
+                        error = Some(
+                            "Не указано имя папки, папка или в конфиге нету элементов".to_string(),
+                        );
                     } else {
-                        start_backup(&config, &backup);
+                        match backup_service::resumable_backup(&config, &backup) {
+                            Ok(Some(found)) => resumable = Some(found),
+                            Ok(None) => run_backup = true,
+                            Err(e) => error = Some(e.to_string()),
+                        }
                     }
                 }
             };
 
             handle_evnets(callback);
         }
-    })
+
+        if run_backup {
+            run_backup = false;
+            let mut view = SProgressView::new("Backup");
+            let job = start_backup(&config, &backup);
+            match progress::run_job(terminal, &mut view, job).unwrap() {
+                Ok(snapshot) if !snapshot.skipped.is_empty() => {
+                    warning = Some(format!(
+                        "Skipped {} FIFOs, sockets or device nodes: {}",
+                        snapshot.skipped.len(),
+                        snapshot.skipped.join(", ")
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => error = Some(e.to_string()),
+            }
+            backup.resume = None;
+        }
+    }
+
+    Ok(())
 }
 
-fn ui(frame: &mut Frame, backup: &SBackupUI) {
+fn ui(frame: &mut Frame, backup: &SBackupUI, config: &SBackupConfig) {
     // Layouts ==========================
     let layout = Layout::new(
         Direction::Vertical,
@@ -138,8 +385,14 @@ fn ui(frame: &mut Frame, backup: &SBackupUI) {
             Constraint::Length(1), // 0 Header
             Constraint::Length(1), // 1 Folder name
             Constraint::Length(1), // 2 Backup folder
-            Constraint::Fill(1),   // 3 Spacer
-            Constraint::Length(1), // 4 Action menu
+            Constraint::Length(1), // 3 Incremental
+            Constraint::Length(1), // 4 Compare hash
+            Constraint::Length(1), // 5 Format
+            Constraint::Length(1), // 6 Compression
+            Constraint::Length(1), // 7 Encryption
+            Constraint::Length(1), // 8 Threads
+            Constraint::Fill(1),   // 9 Spacer
+            Constraint::Length(1), // 10 Action menu
         ],
     )
     .split(frame.size());
@@ -185,9 +438,115 @@ fn ui(frame: &mut Frame, backup: &SBackupUI) {
         backup_folder_layout[1],
     );
 
-    frame.render_widget(Block::default().borders(Borders::NONE), layout[3]);
+    let incremental_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(32), Constraint::Fill(1)])
+        .split(layout[3]);
+
+    let compare_hash_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(32), Constraint::Fill(1)])
+        .split(layout[4]);
+
+    frame.render_widget(
+        Paragraph::new("Incremental(I): ").white(),
+        incremental_layout[0],
+    );
+
+    let incremental_text = match config.incremental {
+        EIncrementalMode::Off => "Off, copy every file",
+        EIncrementalMode::HardLink => "Hard-link unchanged files from the previous backup",
+        EIncrementalMode::Skip => "Skip unchanged files",
+    };
+    frame.render_widget(
+        Paragraph::new(incremental_text).gray(),
+        incremental_layout[1],
+    );
+
+    frame.render_widget(
+        Paragraph::new("Compare contents(H): ").white(),
+        compare_hash_layout[0],
+    );
+
+    let compare_hash_text = if config.compare_hash { "Yes" } else { "No" };
+    frame.render_widget(
+        Paragraph::new(compare_hash_text).gray(),
+        compare_hash_layout[1],
+    );
+
+    let format_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(32), Constraint::Fill(1)])
+        .split(layout[5]);
+
+    frame.render_widget(Paragraph::new("Format(R): ").white(), format_layout[0]);
+
+    let format_text = match backup.format {
+        EDestinationFormat::Folder => "Folder with a plain copy",
+        EDestinationFormat::Repository => "Deduplicating repository",
+        EDestinationFormat::Archive(EArchiveFormat::Tar) => "Single tar archive",
+        EDestinationFormat::Archive(EArchiveFormat::TarZst) => "Single tar.zst archive",
+        EDestinationFormat::Archive(EArchiveFormat::Zip) => "Single zip archive",
+    };
+    frame.render_widget(Paragraph::new(format_text).gray(), format_layout[1]);
+
+    let compression_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(32), Constraint::Fill(1)])
+        .split(layout[6]);
+
+    frame.render_widget(
+        Paragraph::new("Compression(C): ").white(),
+        compression_layout[0],
+    );
+
+    let compression_text = match backup.compression {
+        ECompression::None => "None".to_string(),
+        ECompression::Gzip => "gzip".to_string(),
+        ECompression::Zstd(level) => format!("zstd, level {} (+/-)", level),
+        ECompression::Lz4 => "lz4".to_string(),
+    };
+    frame.render_widget(
+        Paragraph::new(compression_text).gray(),
+        compression_layout[1],
+    );
+
+    let encryption_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(32), Constraint::Fill(1)])
+        .split(layout[7]);
+
+    frame.render_widget(
+        Paragraph::new("Encryption(E): ").white(),
+        encryption_layout[0],
+    );
+
+    let encryption_text = match &backup.key {
+        None => "Off".to_string(),
+        Some(EKeySource::Passphrase(_)) => "Passphrase".to_string(),
+        Some(EKeySource::KeyFile(path)) => format!("Key file {}", path.display()),
+    };
+    frame.render_widget(Paragraph::new(encryption_text).gray(), encryption_layout[1]);
+
+    let threads_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(32), Constraint::Fill(1)])
+        .split(layout[8]);
+
+    frame.render_widget(Paragraph::new("Threads(T): ").white(), threads_layout[0]);
 
-    frame.render_widget(Paragraph::new("START BACKUP(S)  QUIT(Q)").gray(), layout[4]);
+    let threads_text = match config.threads {
+        0 => format!("Auto, {}", parallel::threads(0)),
+        threads => threads.to_string(),
+    };
+    frame.render_widget(Paragraph::new(threads_text).gray(), threads_layout[1]);
+
+    frame.render_widget(Block::default().borders(Borders::NONE), layout[9]);
+
+    frame.render_widget(
+        Paragraph::new("START BACKUP(S)  DRY RUN(D)  PRUNE OLD SNAPSHOTS(P)  QUIT(Q)").gray(),
+        layout[10],
+    );
 }
 
 fn handle_evnets(mut callback: impl FnMut(KeyCode)) {
@@ -222,24 +581,24 @@ fn enter_folder_name(frame: &mut Frame, text_area: &mut TextArea<'_>) -> (bool,
         }
     }
 
-    return (false, false);
+    (false, false)
 }
 
-fn ui_error(frame: &mut Frame, text1: String, text2: String) -> bool {
+fn ui_modal(frame: &mut Frame, title: &str, color: Color, text1: String, text2: &str) {
     let width: u16 = 120;
     let height: u16 = 4;
     let area = Rect {
-        width: width,
-        height: height,
+        width,
+        height,
         x: (frame.size().width / 2) - width / 2,
         y: (frame.size().height / 2) - height / 2,
     };
 
     frame.render_widget(
         Block::new()
-            .title(" ERROR! ")
+            .title(title)
             .borders(Borders::all())
-            .border_style(Style::default().red()),
+            .border_style(Style::default().fg(color)),
         area,
     );
 
@@ -255,12 +614,4 @@ fn ui_error(frame: &mut Frame, text1: String, text2: String) -> bool {
         Paragraph::new(text2).white().alignment(Alignment::Right),
         layout[1],
     );
-
-    if let event::Event::Key(key) = event::read().unwrap() {
-        if key.kind == KeyEventKind::Press && key.code == KeyCode::Esc {
-            return true;
-        }
-    }
-
-    return false;
 }
@@ -12,26 +12,40 @@ use ratatui::{
     *,
 };
 
+use tui_textarea::TextArea;
+
 use super::file_picker;
 use crate::config::*;
 
+#[derive(Clone, Copy)]
+enum EEditedField {
+    Exclude,
+    Include,
+    Retention,
+    Compression,
+}
+
 struct SBackupConfigUI {
     backup_config: SBackupConfig,
     current_element: u16,
     top_element: u16,
+    error: Option<String>,
+    editing: Option<EEditedField>,
 }
 
 impl SBackupConfigUI {
     fn new() -> SBackupConfigUI {
         SBackupConfigUI {
-            backup_config: SBackupConfig::new(String::new()),
+            backup_config: SBackupConfig::new(),
             current_element: 0,
             top_element: 0,
+            error: None,
+            editing: None,
         }
     }
 
     fn next(&mut self) {
-        if self.current_element >= self.backup_config.elements.len() as u16 - 1 {
+        if self.current_element + 1 >= self.backup_config.elements.len() as u16 {
             return;
         }
         self.current_element += 1;
@@ -55,7 +69,7 @@ impl SBackupConfigUI {
             .elements
             .remove(self.current_element as usize);
 
-        if !(self.current_element == 0) {
+        if self.current_element != 0 {
             self.current_element -= 1;
         }
     }
@@ -67,6 +81,74 @@ impl SBackupConfigUI {
     fn clear_all(&mut self) {
         self.backup_config.elements.clear();
     }
+
+    fn current_patterns(&mut self, list: EEditedField) -> Option<&mut Vec<String>> {
+        let element = self
+            .backup_config
+            .elements
+            .get_mut(self.current_element as usize)?;
+        match list {
+            EEditedField::Exclude => Some(&mut element.exclude),
+            EEditedField::Include => Some(&mut element.include),
+            EEditedField::Retention | EEditedField::Compression => None,
+        }
+    }
+
+    fn start_editing(&mut self, field: EEditedField, textarea: &mut TextArea<'_>) {
+        let text = match field {
+            EEditedField::Retention => self.backup_config.retention.to_string(),
+            EEditedField::Compression => {
+                match self
+                    .backup_config
+                    .elements
+                    .get(self.current_element as usize)
+                {
+                    Some(element) => element
+                        .compression
+                        .map(|compression| compression.to_string())
+                        .unwrap_or_default(),
+                    None => return,
+                }
+            }
+            _ => match self.current_patterns(field) {
+                Some(patterns) => patterns.join(", "),
+                None => return,
+            },
+        };
+        *textarea = edit_textarea(field, text);
+        self.editing = Some(field);
+    }
+
+    /// Patterns and retention rules are entered comma separated.
+    /// An empty compression leaves it to the destination.
+    fn finish_editing(&mut self, text: &str) {
+        let Some(field) = self.editing.take() else {
+            return;
+        };
+        if let EEditedField::Retention = field {
+            match SRetention::parse(text) {
+                Ok(retention) => self.backup_config.retention = retention,
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        } else if let EEditedField::Compression = field {
+            let compression = match text.trim() {
+                "" => Ok(None),
+                text => ECompression::parse(text).map(Some),
+            };
+            let current = self.current_element as usize;
+            match (compression, self.backup_config.elements.get_mut(current)) {
+                (Ok(compression), Some(element)) => element.compression = compression,
+                (Err(e), _) => self.error = Some(e.to_string()),
+                (Ok(_), None) => {}
+            }
+        } else if let Some(patterns) = self.current_patterns(field) {
+            *patterns = text
+                .split(',')
+                .map(|pattern| pattern.trim().to_string())
+                .filter(|pattern| !pattern.is_empty())
+                .collect();
+        }
+    }
 }
 
 pub fn start(
@@ -74,15 +156,38 @@ pub fn start(
     mut start_backup: impl FnMut(&SBackupConfig),
 ) -> Result<(), Error> {
     let backup_config = RefCell::new(SBackupConfigUI::new());
+    let mut textarea = TextArea::default();
 
     let mut working = true;
 
-    Ok(while working {
+    while working {
         terminal
             .borrow_mut()
-            .draw(|f| ui(f, &backup_config))
+            .draw(|f| {
+                ui(f, &backup_config);
+                if backup_config.borrow().editing.is_some() {
+                    let (_end, _save) = enter_patterns(f, &mut textarea);
+                    if _save {
+                        backup_config
+                            .borrow_mut()
+                            .finish_editing(&textarea.lines()[0]);
+                    } else if _end {
+                        backup_config.borrow_mut().editing = None;
+                    }
+                }
+            })
             .unwrap();
+        if backup_config.borrow().editing.is_some() {
+            continue;
+        }
         let mut callback = |key: KeyCode| {
+            if backup_config.borrow().error.is_some() {
+                if key == KeyCode::Esc {
+                    backup_config.borrow_mut().error = None;
+                }
+                return;
+            }
+
             if key == KeyCode::Char('q')
                 || key == KeyCode::Char('Q')
                 || key == KeyCode::Char('й')
@@ -129,22 +234,82 @@ pub fn start(
                 || key == KeyCode::Char('a')
             {
                 let callback = |path: String, element_type: EElementType| {
-                    backup_config.borrow_mut().add_new(SConfigElement {
-                        path: path,
-                        content_type: element_type,
-                    })
+                    backup_config
+                        .borrow_mut()
+                        .add_new(SConfigElement::new(path, element_type))
                 };
                 let _ = file_picker::start(terminal, callback, EElementType::Anything);
             }
 
+            if key == KeyCode::Char('e')
+                || key == KeyCode::Char('E')
+                || key == KeyCode::Char('у')
+                || key == KeyCode::Char('У')
+            {
+                backup_config
+                    .borrow_mut()
+                    .start_editing(EEditedField::Exclude, &mut textarea);
+            }
+
+            if key == KeyCode::Char('i')
+                || key == KeyCode::Char('I')
+                || key == KeyCode::Char('ш')
+                || key == KeyCode::Char('Ш')
+            {
+                backup_config
+                    .borrow_mut()
+                    .start_editing(EEditedField::Include, &mut textarea);
+            }
+
+            if key == KeyCode::Char('z')
+                || key == KeyCode::Char('Z')
+                || key == KeyCode::Char('я')
+                || key == KeyCode::Char('Я')
+            {
+                backup_config
+                    .borrow_mut()
+                    .start_editing(EEditedField::Compression, &mut textarea);
+            }
+
+            if key == KeyCode::Char('k')
+                || key == KeyCode::Char('K')
+                || key == KeyCode::Char('л')
+                || key == KeyCode::Char('Л')
+            {
+                backup_config
+                    .borrow_mut()
+                    .start_editing(EEditedField::Retention, &mut textarea);
+            }
+
+            if key == KeyCode::Char('g')
+                || key == KeyCode::Char('G')
+                || key == KeyCode::Char('п')
+                || key == KeyCode::Char('П')
+            {
+                let mut config_ui = backup_config.borrow_mut();
+                config_ui.backup_config.use_gitignore = !config_ui.backup_config.use_gitignore;
+            }
+
+            if key == KeyCode::Char('y')
+                || key == KeyCode::Char('Y')
+                || key == KeyCode::Char('н')
+                || key == KeyCode::Char('Н')
+            {
+                let mut config_ui = backup_config.borrow_mut();
+                config_ui.backup_config.follow_symlinks = !config_ui.backup_config.follow_symlinks;
+            }
+
             if key == KeyCode::Char('L')
                 || key == KeyCode::Char('l')
                 || key == KeyCode::Char('д')
                 || key == KeyCode::Char('Д')
             {
                 let callback = |path: String, _element_type: EElementType| {
-                    backup_config.borrow_mut().backup_config.path = path.clone();
-                    backup_config.borrow_mut().backup_config.load_config(path);
+                    let mut config_ui = backup_config.borrow_mut();
+                    match config_ui.backup_config.load_config(path.clone()) {
+                        Ok(()) => config_ui.backup_config.path = path,
+                        Err(e) => config_ui.error = Some(e.to_string()),
+                    }
                 };
                 let _ = file_picker::start(terminal, callback, EElementType::File);
             }
@@ -156,19 +321,24 @@ pub fn start(
             {
                 if backup_config.borrow().backup_config.path.is_empty() {
                     let callback = |path: String, _element_type: EElementType| {
-                        backup_config.borrow_mut().backup_config.auto_save(path);
+                        let mut config_ui = backup_config.borrow_mut();
+                        if let Err(e) = config_ui.backup_config.auto_save(path) {
+                            config_ui.error = Some(e.to_string());
+                        }
                     };
                     let _ = file_picker::start(terminal, callback, EElementType::Folder);
                 } else {
-                    backup_config
-                        .borrow_mut()
-                        .backup_config
-                        .auto_save(String::new());
+                    let mut config_ui = backup_config.borrow_mut();
+                    if let Err(e) = config_ui.backup_config.auto_save(String::new()) {
+                        config_ui.error = Some(e.to_string());
+                    }
                 }
             }
         };
         handle_evnets(&mut callback);
-    })
+    }
+
+    Ok(())
 }
 
 fn ui(frame: &mut Frame, backup_config: &RefCell<SBackupConfigUI>) {
@@ -195,6 +365,14 @@ fn ui(frame: &mut Frame, backup_config: &RefCell<SBackupConfigUI>) {
         ])
         .split(layout[1]);
 
+    let options_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Fill(2), // Retention
+            Constraint::Fill(1), // Symlinks
+        ])
+        .split(layout[3]);
+
     let content = Layout::default()
         .direction(Direction::Horizontal)
         .margin(1)
@@ -215,7 +393,9 @@ fn ui(frame: &mut Frame, backup_config: &RefCell<SBackupConfigUI>) {
     // Content block
     frame.render_widget(
         Block::new()
-            .title_bottom("─── CLEAR ALL(C) ─── ADD FILE/FOLDER(A) ─── REMOVE(R) ")
+            .title_bottom(
+                "─── CLEAR ALL(C) ─── ADD FILE/FOLDER(A) ─── REMOVE(R) ─── EXCLUDE(E) ─── INCLUDE(I) ─── COMPRESSION(Z) ",
+            )
             .borders(Borders::ALL),
         layout[2],
     );
@@ -225,18 +405,41 @@ fn ui(frame: &mut Frame, backup_config: &RefCell<SBackupConfigUI>) {
         Paragraph::new("START BACKUP(B) QUIT(Q)")
             .gray()
             .alignment(Alignment::Center),
-        layout[3],
+        layout[4],
     );
 
     config_content_ui(frame, &content[0], backup_config);
 
+    let retention = &backup_config.borrow().backup_config.retention;
+    let retention_text = if retention.is_empty() {
+        "keep every snapshot".to_string()
+    } else {
+        retention.to_string()
+    };
+    frame.render_widget(
+        Paragraph::new(format!("Retention(K): {}", retention_text)).gray(),
+        options_layout[0],
+    );
+
+    let symlinks_text = if backup_config.borrow().backup_config.follow_symlinks {
+        "Follow symlinks(Y): yes"
+    } else {
+        "Follow symlinks(Y): no"
+    };
+    frame.render_widget(Paragraph::new(symlinks_text).gray(), options_layout[1]);
+
     frame.render_widget(Paragraph::new("Current config: ").red(), config_layout[0]);
     frame.render_widget(
         Paragraph::new(backup_config.borrow().backup_config.path.clone()).gray(),
         config_layout[1],
     );
 
-    frame.render_widget(Block::default().borders(Borders::NONE), config_layout[2]);
+    let gitignore_text = if backup_config.borrow().backup_config.use_gitignore {
+        "Use .gitignore(G): yes"
+    } else {
+        "Use .gitignore(G): no"
+    };
+    frame.render_widget(Paragraph::new(gitignore_text).gray(), config_layout[2]);
 
     frame.render_widget(
         Paragraph::new("LOAD CONFIG(L)  SAVE CONFIG(S)")
@@ -244,6 +447,10 @@ fn ui(frame: &mut Frame, backup_config: &RefCell<SBackupConfigUI>) {
             .alignment(Alignment::Right),
         config_layout[3],
     );
+
+    if let Some(message) = &backup_config.borrow().error {
+        ui_error(frame, message.clone(), "Close(ESC)".to_string());
+    }
 }
 
 fn handle_evnets(mut callback: impl FnMut(KeyCode)) {
@@ -278,7 +485,7 @@ fn config_content_ui(frame: &mut Frame, area: &Rect, backup_config: &RefCell<SBa
         content_unit_ui(
             frame,
             &layout[i as usize],
-            &config_element,
+            config_element,
             is_element_selectd,
         );
     }
@@ -287,7 +494,12 @@ fn config_content_ui(frame: &mut Frame, area: &Rect, backup_config: &RefCell<SBa
 fn content_unit_ui(frame: &mut Frame, area: &Rect, unit: &SConfigElement, selected: bool) {
     let layout = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Fill(1), Constraint::Length(6)])
+        .constraints([
+            Constraint::Fill(1),
+            Constraint::Length(40),
+            Constraint::Length(10),
+            Constraint::Length(6),
+        ])
         .split(*area);
 
     if selected {
@@ -300,16 +512,133 @@ fn content_unit_ui(frame: &mut Frame, area: &Rect, unit: &SConfigElement, select
     }
     frame.render_widget(path_widget, layout[0]);
 
-    let type_text: String;
-    match unit.content_type {
-        EElementType::File => type_text = "File".to_string(),
-        EElementType::Folder => type_text = "Folder".to_string(),
-        EElementType::Anything => type_text = "ERROR".to_string(),
+    let mut patterns_text = Vec::new();
+    if !unit.exclude.is_empty() {
+        patterns_text.push(format!("-{}", unit.exclude.join(",")));
+    }
+    if !unit.include.is_empty() {
+        patterns_text.push(format!("+{}", unit.include.join(",")));
+    }
+    let mut patterns_widget = Paragraph::new(patterns_text.join(" ")).dark_gray();
+    if selected {
+        patterns_widget = patterns_widget.black();
     }
+    frame.render_widget(patterns_widget, layout[1]);
+
+    let compression_text = unit
+        .compression
+        .map(|compression| compression.to_string())
+        .unwrap_or_default();
+    let mut compression_widget = Paragraph::new(compression_text).dark_gray();
+    if selected {
+        compression_widget = compression_widget.black();
+    }
+    frame.render_widget(compression_widget, layout[2]);
+
+    let type_text = match unit.content_type {
+        EElementType::File => "File".to_string(),
+        EElementType::Folder => "Folder".to_string(),
+        EElementType::Anything => "ERROR".to_string(),
+    };
 
     let mut type_text_widget = Paragraph::new(type_text).bold().blue();
     if selected {
         type_text_widget = type_text_widget.bg(Color::Blue).black();
     }
-    frame.render_widget(type_text_widget, layout[1]);
+    frame.render_widget(type_text_widget, layout[3]);
+}
+
+fn edit_textarea(field: EEditedField, text: String) -> TextArea<'static> {
+    let (title, placeholder) = match field {
+        EEditedField::Exclude => (
+            "Exclude patterns, comma separated: ",
+            "target, node_modules, *.log",
+        ),
+        EEditedField::Include => (
+            "Include patterns, comma separated: ",
+            "target, node_modules, *.log",
+        ),
+        EEditedField::Retention => (
+            "Snapshots kept by prune, comma separated: ",
+            "last=7, daily=7, weekly=4, monthly=12, size=50GiB",
+        ),
+        EEditedField::Compression => (
+            "Compression of this element, empty for the destination's: ",
+            "none, gzip, zstd:3, lz4",
+        ),
+    };
+
+    let mut textarea = TextArea::new(vec![text]);
+    textarea.set_block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::White))
+            .title_bottom("CANCEL(ESC) SAVE(ENTER)")
+            .title_alignment(Alignment::Center)
+            .title(title),
+    );
+    textarea.set_style(Style::default().fg(Color::Yellow));
+    textarea.set_placeholder_style(Style::default());
+    textarea.set_placeholder_text(placeholder);
+    textarea.move_cursor(tui_textarea::CursorMove::End);
+    textarea
+}
+
+fn enter_patterns(frame: &mut Frame, text_area: &mut TextArea<'_>) -> (bool, bool) {
+    // close: bool, save: bool
+    let area = Rect {
+        width: 80,
+        height: 3,
+        x: (frame.size().width / 2).saturating_sub(40),
+        y: (frame.size().height / 2).saturating_sub(2),
+    };
+
+    frame.render_widget(text_area.widget(), area);
+
+    if event::poll(std::time::Duration::from_millis(16)).unwrap() {
+        if let event::Event::Key(key) = event::read().unwrap() {
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Esc {
+                return (true, false);
+            }
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Enter {
+                return (true, true);
+            }
+
+            text_area.input(key);
+        }
+    }
+
+    (false, false)
+}
+
+fn ui_error(frame: &mut Frame, text1: String, text2: String) {
+    let width: u16 = 120;
+    let height: u16 = 4;
+    let area = Rect {
+        width,
+        height,
+        x: (frame.size().width / 2) - width / 2,
+        y: (frame.size().height / 2) - height / 2,
+    };
+
+    frame.render_widget(
+        Block::new()
+            .title(" ERROR! ")
+            .borders(Borders::all())
+            .border_style(Style::default().red()),
+        area,
+    );
+
+    let layout = Layout::new(
+        Direction::Vertical,
+        [Constraint::Length(1), Constraint::Length(1)],
+    )
+    .margin(1)
+    .split(area);
+
+    frame.render_widget(Paragraph::new(text1).gray(), layout[0]);
+    frame.render_widget(
+        Paragraph::new(text2).white().alignment(Alignment::Right),
+        layout[1],
+    );
 }
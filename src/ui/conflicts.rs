@@ -0,0 +1,196 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{Error, Stdout},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Local};
+use crossterm::event::{self, *};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style, Stylize},
+    widgets::{Block, Borders, Paragraph},
+    *,
+};
+
+use crate::backup_service::SRestoreConflict;
+use crate::progress::format_size;
+
+use super::recovery::EConflictPolicy;
+
+/// Lets the user pick a policy for each file in the way of a restored one. Files without
+/// one of their own follow `default`. Returns whether the restore should go ahead.
+pub fn start(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    conflicts: &[SRestoreConflict],
+    default: EConflictPolicy,
+    policies: &mut HashMap<PathBuf, EConflictPolicy>,
+) -> Result<bool, Error> {
+    let mut current: usize = 0;
+
+    loop {
+        terminal
+            .borrow_mut()
+            .draw(|f| ui(f, conflicts, default, policies, current))?;
+
+        if let event::Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let policy = match key.code {
+                KeyCode::Char('r')
+                | KeyCode::Char('R')
+                | KeyCode::Char('к')
+                | KeyCode::Char('К') => Some(EConflictPolicy::Replace),
+                KeyCode::Char('s')
+                | KeyCode::Char('S')
+                | KeyCode::Char('ы')
+                | KeyCode::Char('Ы') => Some(EConflictPolicy::Skip),
+                KeyCode::Char('n')
+                | KeyCode::Char('N')
+                | KeyCode::Char('т')
+                | KeyCode::Char('Т') => Some(EConflictPolicy::KeepNewer),
+                KeyCode::Char('a')
+                | KeyCode::Char('A')
+                | KeyCode::Char('ф')
+                | KeyCode::Char('Ф') => Some(EConflictPolicy::Rename),
+                _ => None,
+            };
+            if let Some(policy) = policy {
+                if let Some(conflict) = conflicts.get(current) {
+                    policies.insert(conflict.path.clone(), policy);
+                }
+                if current + 1 < conflicts.len() {
+                    current += 1;
+                }
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q')
+                | KeyCode::Char('Q')
+                | KeyCode::Char('й')
+                | KeyCode::Char('Й')
+                | KeyCode::Esc => return Ok(false),
+                KeyCode::Up if current > 0 => current -= 1,
+                KeyCode::Down if current + 1 < conflicts.len() => current += 1,
+                KeyCode::Enter => return Ok(true),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn ui(
+    frame: &mut Frame,
+    conflicts: &[SRestoreConflict],
+    default: EConflictPolicy,
+    policies: &HashMap<PathBuf, EConflictPolicy>,
+    current: usize,
+) {
+    // Layouts ==========================
+    let layout = Layout::new(
+        Direction::Vertical,
+        [
+            Constraint::Length(1), // 0 Header
+            Constraint::Length(1), // 1 Columns
+            Constraint::Fill(1),   // 2 Conflicts
+            Constraint::Length(1), // 3 Action menu
+        ],
+    )
+    .split(frame.size());
+
+    // Render ==========================
+    // Header
+    frame.render_widget(
+        Block::new()
+            .title("BackupNF")
+            .borders(Borders::TOP)
+            .border_style(Style::default().green())
+            .title_alignment(Alignment::Center),
+        layout[0],
+    );
+
+    let columns = ["File", "In the backup", "Existing", "Action"];
+    let columns_layout = row_layout(&layout[1]);
+    for (i, column) in columns.iter().enumerate() {
+        frame.render_widget(Paragraph::new(*column).white(), columns_layout[i]);
+    }
+
+    let size = layout[2].height as usize;
+    let top = (current + 1).saturating_sub(size);
+    let rows = Layout::new(Direction::Vertical, vec![Constraint::Length(1); size]).split(layout[2]);
+    for (i, conflict) in conflicts.iter().skip(top).take(size).enumerate() {
+        let policy = policies.get(&conflict.path).copied().unwrap_or(default);
+        conflict_ui(frame, &rows[i], conflict, policy, top + i == current);
+    }
+
+    frame.render_widget(
+        Paragraph::new(
+            "REPLACE(R)  SKIP(S)  KEEP NEWER(N)  RENAME(A)  START RECOVERY(ENTER)  BACK(Q)",
+        )
+        .gray(),
+        layout[3],
+    );
+}
+
+fn row_layout(area: &Rect) -> std::rc::Rc<[Rect]> {
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Fill(1),
+            Constraint::Length(32),
+            Constraint::Length(32),
+            Constraint::Length(12),
+        ])
+        .split(*area)
+}
+
+fn conflict_ui(
+    frame: &mut Frame,
+    area: &Rect,
+    conflict: &SRestoreConflict,
+    policy: EConflictPolicy,
+    selected: bool,
+) {
+    let layout = row_layout(area);
+
+    if selected {
+        frame.render_widget(Block::new().borders(Borders::NONE).bg(Color::Gray), *area);
+    }
+
+    let backup_is_newer = conflict.modified > conflict.existing_modified;
+    let cells = [
+        (conflict.path.to_string_lossy().to_string(), false),
+        (file_text(conflict.size, conflict.modified), backup_is_newer),
+        (
+            file_text(conflict.existing_size, conflict.existing_modified),
+            conflict.existing_modified > conflict.modified,
+        ),
+        (policy.label().to_string(), false),
+    ];
+    for (i, (cell, newer)) in cells.into_iter().enumerate() {
+        let mut widget = Paragraph::new(cell).gray();
+        if newer {
+            widget = widget.green();
+        }
+        if selected {
+            widget = widget.black();
+        }
+        frame.render_widget(widget, layout[i]);
+    }
+}
+
+fn file_text(size: u64, modified: Option<SystemTime>) -> String {
+    let modified = modified
+        .map(|time| {
+            DateTime::<Local>::from(time)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|| "-".to_string());
+    format!("{}  {}", modified, format_size(size))
+}
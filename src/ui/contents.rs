@@ -0,0 +1,348 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap, HashSet},
+    io::{Error, Stdout},
+};
+
+use crossterm::event::{self, *};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style, Stylize},
+    widgets::{Block, Borders, Paragraph},
+    *,
+};
+
+use crate::backup_service::SBackupEntry;
+use crate::progress::format_size;
+
+/// The entries of a backup as a tree, with the ticked ones by path. A ticked folder
+/// brings everything below it.
+struct SContentsTree {
+    entries: Vec<SBackupEntry>,
+    /// Entry indexes by the path of their folder, `""` for the top
+    children: HashMap<String, Vec<usize>>,
+    expanded: HashSet<String>,
+    ticked: BTreeSet<String>,
+}
+
+enum ETick {
+    None,
+    Partly,
+    Full,
+}
+
+impl SContentsTree {
+    fn new(mut entries: Vec<SBackupEntry>, selection: &[String]) -> SContentsTree {
+        // Folders that only show up in the paths below them
+        let known: HashSet<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+        let mut missing = BTreeSet::new();
+        for entry in &entries {
+            for parent in ancestors(&entry.path) {
+                if !known.contains(parent) {
+                    missing.insert(parent.to_string());
+                }
+            }
+        }
+        entries.extend(missing.into_iter().map(|path| SBackupEntry {
+            path,
+            is_dir: true,
+            size: 0,
+        }));
+
+        let mut children: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let parent = ancestors(&entry.path).last().unwrap_or("");
+            children.entry(parent.to_string()).or_default().push(i);
+        }
+        for indexes in children.values_mut() {
+            indexes.sort_by(|a, b| {
+                let (a, b) = (&entries[*a], &entries[*b]);
+                b.is_dir.cmp(&a.is_dir).then_with(|| a.path.cmp(&b.path))
+            });
+        }
+
+        let ticked = entries
+            .iter()
+            .filter(|entry| selection.contains(&globset::escape(&entry.path)))
+            .map(|entry| entry.path.clone())
+            .collect();
+        SContentsTree {
+            entries,
+            children,
+            expanded: HashSet::new(),
+            ticked,
+        }
+    }
+
+    /// Indexes of the entries on screen with their depth, folders first
+    fn rows(&self) -> Vec<(usize, usize)> {
+        let mut rows = Vec::new();
+        self.push_rows("", 0, &mut rows);
+        rows
+    }
+
+    fn push_rows(&self, parent: &str, depth: usize, rows: &mut Vec<(usize, usize)>) {
+        for &i in self.children.get(parent).into_iter().flatten() {
+            rows.push((i, depth));
+            let path = &self.entries[i].path;
+            if self.expanded.contains(path) {
+                self.push_rows(path, depth + 1, rows);
+            }
+        }
+    }
+
+    fn tick(&self, path: &str) -> ETick {
+        if self.ticked.contains(path) || ancestors(path).any(|parent| self.ticked.contains(parent))
+        {
+            return ETick::Full;
+        }
+        let prefix = format!("{}/", path);
+        if self.ticked.iter().any(|ticked| ticked.starts_with(&prefix)) {
+            return ETick::Partly;
+        }
+        ETick::None
+    }
+
+    /// Unticking something below a ticked folder unticks the folder and ticks the rest of it
+    fn toggle(&mut self, path: &str) {
+        if self.ticked.remove(path) {
+            return;
+        }
+        if let Some(folder) = ancestors(path).find(|parent| self.ticked.contains(*parent)) {
+            let folder = folder.to_string();
+            self.ticked.remove(&folder);
+            let mut current = folder;
+            while current != path {
+                let mut next = None;
+                for &i in self.children.get(&current).into_iter().flatten() {
+                    let child = &self.entries[i].path;
+                    if path == child || path.starts_with(&format!("{}/", child)) {
+                        next = Some(child.clone());
+                    } else {
+                        self.ticked.insert(child.clone());
+                    }
+                }
+                match next {
+                    Some(next) => current = next,
+                    None => break,
+                }
+            }
+            return;
+        }
+        let prefix = format!("{}/", path);
+        self.ticked.retain(|ticked| !ticked.starts_with(&prefix));
+        self.ticked.insert(path.to_string());
+    }
+
+    fn selection(&self) -> Vec<String> {
+        self.ticked
+            .iter()
+            .map(|path| globset::escape(path))
+            .collect()
+    }
+}
+
+/// Folders above `path`, the outermost first
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(end, _)| &path[..end])
+}
+
+/// Lets the user tick the files and folders of a backup to restore. Nothing ticked restores
+/// everything. `selection` is left as it was when the user goes back without saving.
+pub fn start(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    backup_folder: &str,
+    entries: Vec<SBackupEntry>,
+    selection: &mut Vec<String>,
+) -> Result<(), Error> {
+    let mut tree = SContentsTree::new(entries, selection);
+    let mut current: usize = 0;
+
+    loop {
+        let rows = tree.rows();
+        terminal
+            .borrow_mut()
+            .draw(|f| ui(f, backup_folder, &tree, &rows, current))?;
+
+        if let event::Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let path = rows
+                .get(current)
+                .map(|(i, _)| tree.entries[*i].path.clone());
+            match key.code {
+                KeyCode::Char('q')
+                | KeyCode::Char('Q')
+                | KeyCode::Char('й')
+                | KeyCode::Char('Й')
+                | KeyCode::Esc => return Ok(()),
+                KeyCode::Enter => {
+                    *selection = tree.selection();
+                    return Ok(());
+                }
+                KeyCode::Up if current > 0 => current -= 1,
+                KeyCode::Down if current + 1 < rows.len() => current += 1,
+                KeyCode::Right => {
+                    if let Some(path) = path {
+                        tree.expanded.insert(path);
+                    }
+                }
+                KeyCode::Left => {
+                    let Some(path) = path else {
+                        continue;
+                    };
+                    if !tree.expanded.remove(&path) {
+                        // Jump to the folder the entry is in
+                        if let Some(parent) = ancestors(&path).last() {
+                            if let Some(row) = rows
+                                .iter()
+                                .position(|(i, _)| tree.entries[*i].path == parent)
+                            {
+                                current = row;
+                            }
+                        }
+                    }
+                }
+                KeyCode::Char(' ') => {
+                    if let Some(path) = path {
+                        tree.toggle(&path);
+                        if current + 1 < rows.len() {
+                            current += 1;
+                        }
+                    }
+                }
+                KeyCode::Char('a')
+                | KeyCode::Char('A')
+                | KeyCode::Char('ф')
+                | KeyCode::Char('Ф') => {
+                    if tree.ticked.is_empty() {
+                        let top: Vec<String> = tree
+                            .children
+                            .get("")
+                            .into_iter()
+                            .flatten()
+                            .map(|i| tree.entries[*i].path.clone())
+                            .collect();
+                        tree.ticked.extend(top);
+                    } else {
+                        tree.ticked.clear();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn ui(
+    frame: &mut Frame,
+    backup_folder: &str,
+    tree: &SContentsTree,
+    rows: &[(usize, usize)],
+    current: usize,
+) {
+    // Layouts ==========================
+    let layout = Layout::new(
+        Direction::Vertical,
+        [
+            Constraint::Length(1), // 0 Header
+            Constraint::Length(1), // 1 Backup folder
+            Constraint::Fill(1),   // 2 Entries
+            Constraint::Length(1), // 3 Action menu
+        ],
+    )
+    .split(frame.size());
+
+    // Render ==========================
+    // Header
+    frame.render_widget(
+        Block::new()
+            .title("BackupNF")
+            .borders(Borders::TOP)
+            .border_style(Style::default().green())
+            .title_alignment(Alignment::Center),
+        layout[0],
+    );
+
+    let summary = match tree.ticked.len() {
+        0 => "nothing ticked, all files are restored".to_string(),
+        count => format!("{} ticked", count),
+    };
+    frame.render_widget(
+        Paragraph::new(format!("{}  ({})", backup_folder, summary)).white(),
+        layout[1],
+    );
+
+    let size = layout[2].height as usize;
+    let top = (current + 1).saturating_sub(size);
+    let areas =
+        Layout::new(Direction::Vertical, vec![Constraint::Length(1); size]).split(layout[2]);
+    for (i, (index, depth)) in rows.iter().skip(top).take(size).enumerate() {
+        entry_ui(frame, &areas[i], tree, *index, *depth, top + i == current);
+    }
+
+    frame.render_widget(
+        Paragraph::new("TICK(SPACE)  OPEN(→)  CLOSE(←)  ALL/NONE(A)  SAVE(ENTER)  BACK(Q)").gray(),
+        layout[3],
+    );
+}
+
+fn entry_ui(
+    frame: &mut Frame,
+    area: &Rect,
+    tree: &SContentsTree,
+    index: usize,
+    depth: usize,
+    selected: bool,
+) {
+    let layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Fill(1), Constraint::Length(12)])
+        .split(*area);
+
+    if selected {
+        frame.render_widget(Block::new().borders(Borders::NONE).bg(Color::Gray), *area);
+    }
+
+    let entry = &tree.entries[index];
+    let tick = tree.tick(&entry.path);
+    let check = match tick {
+        ETick::None => "[ ]",
+        ETick::Partly => "[~]",
+        ETick::Full => "[x]",
+    };
+    let marker = match (entry.is_dir, tree.expanded.contains(&entry.path)) {
+        (false, _) => " ",
+        (true, false) => "▸",
+        (true, true) => "▾",
+    };
+    let name = entry.path.rsplit('/').next().unwrap_or(&entry.path);
+    let suffix = if entry.is_dir { "/" } else { "" };
+    let text = format!(
+        "{}{} {} {}{}",
+        "  ".repeat(depth),
+        check,
+        marker,
+        name,
+        suffix
+    );
+
+    let mut name_widget = Paragraph::new(text).gray();
+    if !matches!(tick, ETick::None) {
+        name_widget = name_widget.green();
+    }
+    let size = if entry.is_dir {
+        String::new()
+    } else {
+        format_size(entry.size)
+    };
+    let mut size_widget = Paragraph::new(size).gray().alignment(Alignment::Right);
+    if selected {
+        name_widget = name_widget.black();
+        size_widget = size_widget.black();
+    }
+    frame.render_widget(name_widget, layout[0]);
+    frame.render_widget(size_widget, layout[1]);
+}
@@ -18,7 +18,7 @@ pub fn start(
 
     let mut working = true;
 
-    Ok(while working {
+    while working {
         terminal.borrow_mut().draw(|f| {
             f.render_widget(&file_explorer.widget(), f.size());
         })?;
@@ -48,5 +48,7 @@ pub fn start(
         }
 
         file_explorer.handle(&event)?;
-    })
+    }
+
+    Ok(())
 }
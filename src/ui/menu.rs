@@ -9,6 +9,7 @@ use std::{
 pub enum CurrentlyBtn {
     Backup,
     Restore,
+    Verify,
 }
 
 pub struct Menu {
@@ -25,7 +26,16 @@ impl Menu {
     fn next_btn(&mut self) {
         match self.currently_btn {
             CurrentlyBtn::Backup => self.currently_btn = CurrentlyBtn::Restore,
+            CurrentlyBtn::Restore => self.currently_btn = CurrentlyBtn::Verify,
+            CurrentlyBtn::Verify => self.currently_btn = CurrentlyBtn::Backup,
+        };
+    }
+
+    fn previous_btn(&mut self) {
+        match self.currently_btn {
+            CurrentlyBtn::Backup => self.currently_btn = CurrentlyBtn::Verify,
             CurrentlyBtn::Restore => self.currently_btn = CurrentlyBtn::Backup,
+            CurrentlyBtn::Verify => self.currently_btn = CurrentlyBtn::Restore,
         };
     }
 }
@@ -37,11 +47,13 @@ pub fn start(
     let mut working = true;
     let mut menu = Menu::new();
 
-    Ok(while working {
+    while working {
         terminal.borrow_mut().draw(|f| ui(f, &menu))?;
         let (_close, _result) = handle_evnets(&mut menu, &mut select_fn).unwrap();
         working = !_close;
-    })
+    }
+
+    Ok(())
 }
 
 fn ui(frame: &mut Frame, menu: &Menu) {
@@ -56,12 +68,18 @@ fn ui(frame: &mut Frame, menu: &Menu) {
         .borders(Borders::NONE)
         .style(Style::default())
         .title_alignment(Alignment::Center);
+    let mut verify_btn = Block::default()
+        .title("VERIFY")
+        .borders(Borders::NONE)
+        .style(Style::default())
+        .title_alignment(Alignment::Center);
 
     let active_style = Style::default().black().bg(Color::Gray).bold();
 
     match menu.currently_btn {
         CurrentlyBtn::Backup => backup_btn = backup_btn.style(active_style),
         CurrentlyBtn::Restore => restore_btn = restore_btn.style(active_style),
+        CurrentlyBtn::Verify => verify_btn = verify_btn.style(active_style),
     };
 
     // Layouts ==========================
@@ -72,8 +90,9 @@ fn ui(frame: &mut Frame, menu: &Menu) {
             Constraint::Fill(1),   // 1 Spacer
             Constraint::Length(1), // 2 Btn Backup
             Constraint::Length(1), // 3 Btn Restore
-            Constraint::Fill(1),   // 4 Spacer
-            Constraint::Length(1), // 5 Action menu
+            Constraint::Length(1), // 4 Btn Verify
+            Constraint::Fill(1),   // 5 Spacer
+            Constraint::Length(1), // 6 Action menu
         ],
     )
     .split(frame.size());
@@ -94,6 +113,14 @@ fn ui(frame: &mut Frame, menu: &Menu) {
             Constraint::Fill(1),
         ])
         .split(layout[3]);
+    let verify_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Fill(1),
+            Constraint::Length(30),
+            Constraint::Fill(1),
+        ])
+        .split(layout[4]);
 
     // Render ==========================
     // Header
@@ -119,15 +146,20 @@ fn ui(frame: &mut Frame, menu: &Menu) {
     frame.render_widget(restore_btn, restore_layout[1]);
     frame.render_widget(Block::default().borders(Borders::NONE), restore_layout[2]);
 
+    // Btn Verify
+    frame.render_widget(Block::default().borders(Borders::NONE), verify_layout[0]);
+    frame.render_widget(verify_btn, verify_layout[1]);
+    frame.render_widget(Block::default().borders(Borders::NONE), verify_layout[2]);
+
     //Spacer
-    frame.render_widget(Block::default().borders(Borders::NONE), layout[4]);
+    frame.render_widget(Block::default().borders(Borders::NONE), layout[5]);
 
     // Action menu
     frame.render_widget(
         Paragraph::new("SELECT(ENTER) QUIT(Q)")
             .gray()
             .alignment(Alignment::Center),
-        layout[5],
+        layout[6],
     );
 }
 
@@ -144,10 +176,14 @@ fn handle_evnets(
             return Ok((true, Ok(())));
         }
 
-        if key.kind == KeyEventKind::Press && key.code == KeyCode::Up || key.code == KeyCode::Down {
+        if key.kind == KeyEventKind::Press && key.code == KeyCode::Down {
             menu.next_btn();
         }
 
+        if key.kind == KeyEventKind::Press && key.code == KeyCode::Up {
+            menu.previous_btn();
+        }
+
         if key.kind == KeyEventKind::Press && key.code == KeyCode::Enter {
             select_fn(menu.currently_btn.clone());
         }
@@ -1,5 +1,13 @@
 pub mod backup;
 pub mod backup_config;
+pub mod conflicts;
+pub mod contents;
 pub mod file_picker;
 pub mod menu;
+pub mod passphrase;
+pub mod plan;
+pub mod progress;
+pub mod prune;
 pub mod recovery;
+pub mod snapshots;
+pub mod verify;
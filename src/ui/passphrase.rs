@@ -0,0 +1,124 @@
+use std::{
+    cell::RefCell,
+    io::{Error, Stdout},
+    path::{Path, PathBuf},
+};
+
+use crossterm::event::{self, *};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Rect},
+    style::{Color, Style},
+    widgets::{Block, Borders},
+    *,
+};
+
+use tui_textarea::TextArea;
+
+use crate::config::EElementType;
+use crate::encryption::{self, EKeyDerivation, EKeySource, SEncryptionHeader};
+
+use super::file_picker;
+
+pub enum EKeyPrompt {
+    NotEncrypted,
+    Key(EKeySource),
+    Cancelled,
+}
+
+/// Asks for a passphrase over what `background` draws, `None` when cancelled
+pub fn ask(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    title: &str,
+    mut background: impl FnMut(&mut Frame),
+) -> Result<Option<String>, Error> {
+    let mut textarea = TextArea::default();
+    textarea.set_block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::White))
+            .title_bottom("CANCEL(ESC) OK(ENTER)")
+            .title_alignment(Alignment::Center)
+            .title(title.to_string()),
+    );
+    textarea.set_style(Style::default().fg(Color::Yellow));
+    textarea.set_mask_char('*');
+
+    loop {
+        let mut answer = None;
+        terminal.borrow_mut().draw(|f| {
+            background(f);
+            let (_end, _save) = enter_passphrase(f, &mut textarea);
+            if _end {
+                answer = Some(_save);
+            }
+        })?;
+        match answer {
+            Some(true) => return Ok(Some(textarea.lines()[0].clone())),
+            Some(false) => return Ok(None),
+            None => {}
+        }
+    }
+}
+
+/// Picks a key file, `None` when nothing was picked
+pub fn pick_key_file(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+) -> Result<Option<PathBuf>, Error> {
+    let mut key_file = None;
+    let callback = |path: String, _element_type: EElementType| key_file = Some(PathBuf::from(path));
+    file_picker::start(terminal, callback, EElementType::File)?;
+    Ok(key_file)
+}
+
+/// Asks for what unlocks `backup_folder`, a passphrase or a key file depending on how it
+/// was encrypted
+pub fn ask_key(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    backup_folder: &Path,
+    background: impl FnMut(&mut Frame),
+) -> Result<EKeyPrompt, Error> {
+    let header = encryption::header_folder(backup_folder)
+        .and_then(|folder| SEncryptionHeader::load(folder).ok().flatten());
+    let Some(header) = header else {
+        return Ok(EKeyPrompt::NotEncrypted);
+    };
+
+    let key = match header.derivation {
+        EKeyDerivation::Argon2id => ask(
+            terminal,
+            "The backup is encrypted, enter passphrase:",
+            background,
+        )?
+        .map(EKeySource::Passphrase),
+        EKeyDerivation::KeyFile => pick_key_file(terminal)?.map(EKeySource::KeyFile),
+    };
+    Ok(key.map_or(EKeyPrompt::Cancelled, EKeyPrompt::Key))
+}
+
+fn enter_passphrase(frame: &mut Frame, text_area: &mut TextArea<'_>) -> (bool, bool) {
+    // close: bool, save: bool
+    let area = Rect {
+        width: 50,
+        height: 3,
+        x: (frame.size().width / 2) - 25,
+        y: (frame.size().height / 2) - 2,
+    };
+
+    frame.render_widget(text_area.widget(), area);
+
+    if event::poll(std::time::Duration::from_millis(16)).unwrap() {
+        if let event::Event::Key(key) = event::read().unwrap() {
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Esc {
+                return (true, false);
+            }
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Enter {
+                return (true, true);
+            }
+
+            text_area.input(key);
+        }
+    }
+
+    (false, false)
+}
@@ -0,0 +1,155 @@
+use std::{
+    cell::RefCell,
+    io::{Error, Stdout},
+};
+
+use crossterm::event::{self, *};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style, Stylize},
+    widgets::{Block, Borders, Paragraph},
+    *,
+};
+
+use crate::plan::{EPlanAction, SPlan, SPlanEntry};
+use crate::progress::format_size;
+
+/// Shows what a dry run found, one file per row, until the user goes back
+pub fn start(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    plan: &SPlan,
+) -> Result<(), Error> {
+    let mut current: usize = 0;
+
+    loop {
+        let mut page = 1;
+        terminal
+            .borrow_mut()
+            .draw(|f| page = ui(f, plan, current))?;
+
+        if let event::Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let last = plan.entries.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Char('q')
+                | KeyCode::Char('Q')
+                | KeyCode::Char('й')
+                | KeyCode::Char('Й')
+                | KeyCode::Esc => return Ok(()),
+                KeyCode::Up => current = current.saturating_sub(1),
+                KeyCode::Down => current = (current + 1).min(last),
+                KeyCode::PageUp => current = current.saturating_sub(page),
+                KeyCode::PageDown => current = (current + page).min(last),
+                KeyCode::Home => current = 0,
+                KeyCode::End => current = last,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Returns how many rows fit on the screen
+fn ui(frame: &mut Frame, plan: &SPlan, current: usize) -> usize {
+    // Layouts ==========================
+    let layout = Layout::new(
+        Direction::Vertical,
+        [
+            Constraint::Length(1), // 0 Header
+            Constraint::Length(1), // 1 Title
+            Constraint::Fill(1),   // 2 Entries
+            Constraint::Length(1), // 3 Totals
+            Constraint::Length(1), // 4 Action menu
+        ],
+    )
+    .split(frame.size());
+
+    // Render ==========================
+    // Header
+    frame.render_widget(
+        Block::new()
+            .title("BackupNF")
+            .borders(Borders::TOP)
+            .border_style(Style::default().green())
+            .title_alignment(Alignment::Center),
+        layout[0],
+    );
+
+    frame.render_widget(
+        Paragraph::new(format!("DRY RUN: {}", plan.title)).white(),
+        layout[1],
+    );
+
+    let size = layout[2].height as usize;
+    if plan.entries.is_empty() {
+        frame.render_widget(Paragraph::new("Nothing to do").gray(), layout[2]);
+    }
+    let top = (current + 1).saturating_sub(size);
+    let areas =
+        Layout::new(Direction::Vertical, vec![Constraint::Length(1); size]).split(layout[2]);
+    for (i, entry) in plan.entries.iter().skip(top).take(size).enumerate() {
+        entry_ui(frame, &areas[i], entry, top + i == current);
+    }
+
+    let totals: Vec<String> = plan
+        .totals()
+        .iter()
+        .map(|(action, files, bytes)| {
+            format!(
+                "{}: {} files, {}",
+                action.label(),
+                files,
+                format_size(*bytes)
+            )
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(totals.join("   ")).white(), layout[3]);
+
+    frame.render_widget(
+        Paragraph::new("SCROLL(↑/↓/PGUP/PGDN)  BACK(Q)").gray(),
+        layout[4],
+    );
+    size.max(1)
+}
+
+fn entry_ui(frame: &mut Frame, area: &Rect, entry: &SPlanEntry, selected: bool) {
+    let layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Fill(1),
+        ])
+        .split(*area);
+
+    if selected {
+        frame.render_widget(Block::new().borders(Borders::NONE).bg(Color::Gray), *area);
+    }
+
+    let color = match entry.action {
+        EPlanAction::Copy => Color::Green,
+        EPlanAction::Overwrite => Color::Yellow,
+        EPlanAction::Skip => Color::DarkGray,
+        EPlanAction::Delete => Color::Red,
+    };
+    let mut text = entry.path.display().to_string();
+    if !entry.note.is_empty() {
+        text.push_str(&format!("  ({})", entry.note));
+    }
+
+    let mut action_widget = Paragraph::new(entry.action.label()).fg(color);
+    let mut size_widget = Paragraph::new(format_size(entry.size))
+        .gray()
+        .alignment(Alignment::Right);
+    let mut path_widget = Paragraph::new(format!("  {}", text)).gray();
+    if selected {
+        action_widget = action_widget.black();
+        size_widget = size_widget.black();
+        path_widget = path_widget.black();
+    }
+    frame.render_widget(action_widget, layout[0]);
+    frame.render_widget(size_widget, layout[1]);
+    frame.render_widget(path_widget, layout[2]);
+}
@@ -0,0 +1,222 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{Error, Stdout},
+    time::Duration,
+};
+
+use crossterm::event::{self, *};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout},
+    style::{Color, Style, Stylize},
+    widgets::{Block, Borders, Gauge, Paragraph},
+    *,
+};
+
+use crate::error::BackupError;
+use crate::job::{EJobMessage, SJob};
+use crate::progress::{format_duration, format_size, EProgressEvent, SProgress};
+
+const MAX_LOG_LINES: usize = 1000;
+
+pub struct SProgressView {
+    title: String,
+    progress: Option<SProgress>,
+    log: VecDeque<String>,
+    finished: bool,
+    cancelling: bool,
+}
+
+impl SProgressView {
+    pub fn new(title: &str) -> SProgressView {
+        SProgressView {
+            title: title.to_string(),
+            progress: None,
+            log: VecDeque::new(),
+            finished: false,
+            cancelling: false,
+        }
+    }
+
+    pub fn update(&mut self, event: EProgressEvent, progress: &SProgress) {
+        if event == EProgressEvent::FileDone {
+            if self.log.len() >= MAX_LOG_LINES {
+                self.log.pop_front();
+            }
+            self.log
+                .push_back(progress.current_path.to_string_lossy().to_string());
+        }
+        if event == EProgressEvent::Finished {
+            self.finished = true;
+        }
+        self.progress = Some(progress.clone());
+    }
+}
+
+/// Draws the job's progress until it is done, the cancel key asks it to stop
+/// before the next file. On success the final state stays on screen until closed,
+/// the job's result is returned for the panel's error modal.
+pub fn run_job<T: Send + 'static>(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    view: &mut SProgressView,
+    job: SJob<T>,
+) -> Result<Result<T, BackupError>, Error> {
+    loop {
+        while let Some(message) = job.try_recv() {
+            match message {
+                EJobMessage::Progress(event, progress) => view.update(event, &progress),
+                EJobMessage::Done(result) => {
+                    if result.is_ok() {
+                        wait_close(terminal, view)?;
+                    }
+                    return Ok(result);
+                }
+            }
+        }
+
+        terminal.borrow_mut().draw(|f| ui(f, view))?;
+
+        if event::poll(Duration::from_millis(16))? {
+            if let event::Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press
+                    && (key.code == KeyCode::Char('c')
+                        || key.code == KeyCode::Char('C')
+                        || key.code == KeyCode::Char('с')
+                        || key.code == KeyCode::Char('С')
+                        || key.code == KeyCode::Esc)
+                {
+                    job.cancel();
+                    view.cancelling = job.is_cancelling();
+                }
+            }
+        }
+    }
+}
+
+/// Shows the final state until the user closes the screen
+pub fn wait_close(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    view: &SProgressView,
+) -> Result<(), Error> {
+    loop {
+        terminal.borrow_mut().draw(|f| ui(f, view))?;
+        if let event::Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press
+                && (key.code == KeyCode::Esc || key.code == KeyCode::Enter)
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+pub fn ui(frame: &mut Frame, view: &SProgressView) {
+    // Layouts ==========================
+    let layout = Layout::new(
+        Direction::Vertical,
+        [
+            Constraint::Length(1), // 0 Header
+            Constraint::Length(3), // 1 Gauge
+            Constraint::Length(1), // 2 Files and size
+            Constraint::Length(1), // 3 Speed and ETA
+            Constraint::Length(1), // 4 Current file
+            Constraint::Fill(1),   // 5 Log
+            Constraint::Length(1), // 6 Action menu
+        ],
+    )
+    .split(frame.size());
+
+    // Render ==========================
+    // Header
+    frame.render_widget(
+        Block::new()
+            .title("BackupNF")
+            .borders(Borders::TOP)
+            .border_style(Style::default().green())
+            .title_alignment(Alignment::Center),
+        layout[0],
+    );
+
+    let progress = match &view.progress {
+        Some(progress) => progress,
+        None => return,
+    };
+
+    frame.render_widget(
+        Gauge::default()
+            .block(
+                Block::default()
+                    .title(format!(" {} ", view.title))
+                    .borders(Borders::ALL),
+            )
+            .gauge_style(Style::default().fg(Color::Green).bg(Color::Black))
+            .ratio(progress.ratio()),
+        layout[1],
+    );
+
+    frame.render_widget(
+        Paragraph::new(format!(
+            "Files: {}/{}  Size: {}/{}  New: {}  Reused: {} in {} files",
+            progress.files_done,
+            progress.files_total,
+            format_size(progress.bytes_done),
+            format_size(progress.bytes_total),
+            format_size(progress.bytes_done - progress.bytes_reused),
+            format_size(progress.bytes_reused),
+            progress.files_reused
+        ))
+        .white(),
+        layout[2],
+    );
+
+    let eta = match progress.eta() {
+        Some(eta) if !view.finished => format_duration(eta),
+        _ => "--:--:--".to_string(),
+    };
+    frame.render_widget(
+        Paragraph::new(format!(
+            "Speed: {}/s  Elapsed: {}  ETA: {}",
+            format_size(progress.throughput() as u64),
+            format_duration(progress.elapsed()),
+            eta
+        ))
+        .white(),
+        layout[3],
+    );
+
+    frame.render_widget(
+        Paragraph::new(progress.current_path.to_string_lossy().to_string()).gray(),
+        layout[4],
+    );
+
+    let log_height = layout[5].height.saturating_sub(2) as usize;
+    let log_lines: Vec<text::Line> = view
+        .log
+        .iter()
+        .skip(view.log.len().saturating_sub(log_height))
+        .map(|line| text::Line::from(line.clone()))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(log_lines).gray().block(
+            Block::default()
+                .title("Processed files")
+                .borders(Borders::ALL),
+        ),
+        layout[5],
+    );
+
+    let action_text = if view.finished {
+        "DONE  CLOSE(ESC)"
+    } else if view.cancelling {
+        "CANCELLING AFTER THE CURRENT FILE..."
+    } else {
+        "CANCEL(C)"
+    };
+    frame.render_widget(
+        Paragraph::new(action_text)
+            .gray()
+            .alignment(Alignment::Center),
+        layout[6],
+    );
+}
@@ -0,0 +1,276 @@
+use std::{
+    cell::RefCell,
+    io::{Error, Stdout},
+    path::Path,
+};
+
+use crossterm::event::{self, *};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style, Stylize},
+    widgets::{Block, Borders, Paragraph},
+    *,
+};
+
+use crate::config::SBackupConfig;
+use crate::encryption::EKeySource;
+use crate::progress::format_size;
+use crate::retention::{self, SPruneEntry, SPrunePlan};
+
+struct SPruneUI {
+    plan: Option<SPrunePlan>,
+    current: usize,
+    confirm: bool,
+    error: Option<String>,
+    message: Option<String>,
+}
+
+/// Previews what the retention rules of `config` prune in `destination`,
+/// the snapshots are only deleted after the user confirms
+pub fn start(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    config: &SBackupConfig,
+    destination: &str,
+    key_source: Option<&EKeySource>,
+) -> Result<(), Error> {
+    let mut prune = SPruneUI {
+        plan: None,
+        current: 0,
+        confirm: false,
+        error: None,
+        message: None,
+    };
+    match retention::plan(Path::new(destination), config, key_source) {
+        Ok(plan) => prune.plan = Some(plan),
+        Err(e) => prune.error = Some(e.to_string()),
+    }
+
+    loop {
+        terminal.borrow_mut().draw(|f| ui(f, &prune, config))?;
+
+        let event::Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if prune.error.is_some() || prune.message.is_some() {
+            if key.code == KeyCode::Esc {
+                prune.error = None;
+                prune.message = None;
+            }
+            continue;
+        }
+
+        if prune.confirm {
+            if key.code == KeyCode::Char('y')
+                || key.code == KeyCode::Char('Y')
+                || key.code == KeyCode::Char('н')
+                || key.code == KeyCode::Char('Н')
+            {
+                prune.confirm = false;
+                if let Some(plan) = &prune.plan {
+                    match retention::apply(plan) {
+                        Ok(removed) => {
+                            prune.message = Some(format!(
+                                "Deleted {} snapshots, {}",
+                                removed,
+                                format_size(plan.pruned_size())
+                            ))
+                        }
+                        Err(e) => prune.error = Some(e.to_string()),
+                    }
+                }
+                // The catalog changed, show what is left
+                match retention::plan(Path::new(destination), config, key_source) {
+                    Ok(plan) => prune.plan = Some(plan),
+                    Err(e) => prune.error = Some(e.to_string()),
+                }
+                prune.current = 0;
+            } else if key.code == KeyCode::Esc {
+                prune.confirm = false;
+            }
+            continue;
+        }
+
+        let entries = prune.plan.as_ref().map_or(0, |plan| plan.entries.len());
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Char('й') | KeyCode::Char('Й') => {
+                return Ok(())
+            }
+            KeyCode::Up if prune.current > 0 => prune.current -= 1,
+            KeyCode::Down if prune.current + 1 < entries => prune.current += 1,
+            KeyCode::Char('d') | KeyCode::Char('D') | KeyCode::Char('в') | KeyCode::Char('В')
+                if prune
+                    .plan
+                    .as_ref()
+                    .is_some_and(|plan| plan.pruned().next().is_some()) =>
+            {
+                prune.confirm = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn ui(frame: &mut Frame, prune: &SPruneUI, config: &SBackupConfig) {
+    // Layouts ==========================
+    let layout = Layout::new(
+        Direction::Vertical,
+        [
+            Constraint::Length(1), // 0 Header
+            Constraint::Length(1), // 1 Rules
+            Constraint::Length(1), // 2 Summary
+            Constraint::Fill(1),   // 3 Snapshots
+            Constraint::Length(1), // 4 Action menu
+        ],
+    )
+    .split(frame.size());
+
+    // Render ==========================
+    // Header
+    frame.render_widget(
+        Block::new()
+            .title("BackupNF")
+            .borders(Borders::TOP)
+            .border_style(Style::default().green())
+            .title_alignment(Alignment::Center),
+        layout[0],
+    );
+
+    let rules = if config.retention.is_empty() {
+        "none, every snapshot is kept".to_string()
+    } else {
+        config.retention.to_string()
+    };
+    frame.render_widget(
+        Paragraph::new(format!(
+            "Profile: {}  Retention: {}",
+            config.profile_name(),
+            rules
+        ))
+        .white(),
+        layout[1],
+    );
+
+    if let Some(plan) = &prune.plan {
+        frame.render_widget(
+            Paragraph::new(format!(
+                "{} snapshots, {} would be deleted, {}",
+                plan.entries.len(),
+                plan.pruned().count(),
+                format_size(plan.pruned_size())
+            ))
+            .gray(),
+            layout[2],
+        );
+
+        let size = layout[3].height.saturating_sub(2) as usize;
+        let top = (prune.current + 1).saturating_sub(size);
+        let rows = Layout::new(Direction::Vertical, vec![Constraint::Length(1); size])
+            .split(layout[3].inner(&layout::Margin::new(1, 1)));
+        frame.render_widget(Block::default().borders(Borders::ALL), layout[3]);
+        for (i, entry) in plan.entries.iter().skip(top).take(size).enumerate() {
+            entry_ui(frame, &rows[i], entry, top + i == prune.current);
+        }
+    }
+
+    frame.render_widget(
+        Paragraph::new("DELETE SNAPSHOTS(D)  BACK(Q)").gray(),
+        layout[4],
+    );
+
+    if let Some(message) = &prune.error {
+        ui_modal(frame, " ERROR! ", Color::Red, message.clone(), "Close(ESC)");
+    } else if let Some(message) = &prune.message {
+        ui_modal(
+            frame,
+            " PRUNE ",
+            Color::Green,
+            message.clone(),
+            "Close(ESC)",
+        );
+    } else if prune.confirm {
+        let text = prune.plan.as_ref().map_or(String::new(), |plan| {
+            format!(
+                "Delete {} snapshots, {}? This can not be undone.",
+                plan.pruned().count(),
+                format_size(plan.pruned_size())
+            )
+        });
+        ui_modal(frame, " PRUNE ", Color::Yellow, text, "Yes(Y) No(ESC)");
+    }
+}
+
+fn entry_ui(frame: &mut Frame, area: &Rect, entry: &SPruneEntry, selected: bool) {
+    let layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Length(8),
+            Constraint::Fill(1),
+            Constraint::Length(20),
+            Constraint::Length(12),
+            Constraint::Fill(1),
+        ])
+        .split(*area);
+
+    if selected {
+        frame.render_widget(Block::new().borders(Borders::NONE).bg(Color::Gray), *area);
+    }
+
+    let (action, color) = if entry.keep {
+        ("keep", Color::Green)
+    } else {
+        ("delete", Color::Red)
+    };
+    frame.render_widget(Paragraph::new(action).fg(color).bold(), layout[0]);
+
+    let cells = [
+        entry.snapshot.name.clone(),
+        entry.snapshot.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        format_size(entry.snapshot.size),
+        entry.reason.clone(),
+    ];
+    for (i, cell) in cells.into_iter().enumerate() {
+        let mut widget = Paragraph::new(cell).gray();
+        if selected {
+            widget = widget.black();
+        }
+        frame.render_widget(widget, layout[i + 1]);
+    }
+}
+
+fn ui_modal(frame: &mut Frame, title: &str, color: Color, text1: String, text2: &str) {
+    let width: u16 = 120;
+    let height: u16 = 4;
+    let area = Rect {
+        width,
+        height,
+        x: (frame.size().width / 2).saturating_sub(width / 2),
+        y: (frame.size().height / 2).saturating_sub(height / 2),
+    };
+
+    frame.render_widget(widgets::Clear, area);
+    frame.render_widget(
+        Block::new()
+            .title(title)
+            .borders(Borders::all())
+            .border_style(Style::default().fg(color)),
+        area,
+    );
+
+    let layout = Layout::new(
+        Direction::Vertical,
+        [Constraint::Length(1), Constraint::Length(1)],
+    )
+    .margin(1)
+    .split(area);
+
+    frame.render_widget(Paragraph::new(text1).gray(), layout[0]);
+    frame.render_widget(
+        Paragraph::new(text2).white().alignment(Alignment::Right),
+        layout[1],
+    );
+}
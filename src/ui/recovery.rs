@@ -1,6 +1,8 @@
 use std::{
     cell::RefCell,
+    collections::HashMap,
     io::{self, Error, Stdout},
+    path::{Path, PathBuf},
 };
 
 use crossterm::event::{self, *};
@@ -12,45 +14,131 @@ use ratatui::{
     *,
 };
 
+use crate::backup_service::{self, SRestoreConflict, SRestoreReport};
 use crate::config::*;
+use crate::encryption::EKeySource;
+use crate::job::SJob;
 
+use super::conflicts;
+use super::contents;
 use super::file_picker;
+use super::passphrase::{self, EKeyPrompt};
+use super::plan;
+use super::progress::{self, SProgressView};
+use super::snapshots;
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum EFileAction {
     Copied,
     Moved,
 }
 
+/// How the original paths are placed under a restore root
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum ERestoreLayout {
+    /// `/home/user/docs` is restored to `<root>/home/user/docs`
+    #[default]
+    FullPath,
+    /// `/home/user/docs` is restored to `<root>/docs`
+    Flat,
+}
+
+/// What happens to a file that is in the way of a restored one
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum EConflictPolicy {
+    #[default]
+    Replace,
+    Skip,
+    /// Replaced only when the copy in the backup is newer
+    KeepNewer,
+    /// Kept, the restored file goes next to it under another name
+    Rename,
+}
+
+impl EConflictPolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            EConflictPolicy::Replace => "Replace",
+            EConflictPolicy::Skip => "Skip",
+            EConflictPolicy::KeepNewer => "Keep newer",
+            EConflictPolicy::Rename => "Rename",
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct SRecoveryPanel {
     pub file_action: EFileAction,
     pub backup_folder: String,
-    show_error: bool,
+    /// Asked for when the backup is encrypted
+    pub key: Option<EKeySource>,
+    /// Elements are restored under it instead of to their original paths
+    pub restore_root: Option<PathBuf>,
+    pub restore_layout: ERestoreLayout,
+    /// For files in the way that have no policy in `file_policies`
+    pub conflict_policy: EConflictPolicy,
+    pub file_policies: HashMap<PathBuf, EConflictPolicy>,
+    /// Patterns of paths inside the backup to restore, everything when empty
+    pub selection: Vec<String>,
+    /// Files restored at once from a backup folder, 0 for one per CPU
+    pub threads: usize,
+    /// Bytes per second read and written
+    pub bandwidth_limit: Option<u64>,
+    /// Read and write only while no other program uses the disk, Linux only
+    pub idle_io: bool,
+    /// Added to the CPU nice level while the restore runs
+    pub nice: i32,
+    /// Found before the restore starts, the user picks what happens to them
+    conflicts: Vec<SRestoreConflict>,
+    /// Of the last restore, shown when it could not set every attribute
+    report: Option<SRestoreReport>,
+    error: Option<String>,
 }
 
 impl SRecoveryPanel {
-    fn new() -> SRecoveryPanel {
+    pub fn new() -> SRecoveryPanel {
         SRecoveryPanel {
             file_action: EFileAction::Copied,
             backup_folder: "".to_string(),
-            show_error: false,
+            key: None,
+            restore_root: None,
+            restore_layout: ERestoreLayout::FullPath,
+            conflict_policy: EConflictPolicy::Replace,
+            file_policies: HashMap::new(),
+            selection: Vec::new(),
+            threads: 0,
+            bandwidth_limit: None,
+            idle_io: false,
+            nice: 0,
+            conflicts: Vec::new(),
+            report: None,
+            error: None,
         }
     }
+
+    pub fn conflict_policy_for(&self, path: &Path) -> EConflictPolicy {
+        self.file_policies
+            .get(path)
+            .copied()
+            .unwrap_or(self.conflict_policy)
+    }
 }
 
 pub fn start(
     terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
-    mut start_recovery: impl FnMut(&SRecoveryPanel),
+    mut start_recovery: impl FnMut(&SRecoveryPanel) -> SJob<SRestoreReport>,
 ) -> Result<(), Error> {
     let mut recovery = SRecoveryPanel::new();
     let mut working = true;
 
-    Ok(while working {
+    while working {
         terminal.borrow_mut().draw(|f| ui(f, &recovery))?;
         let (_close, _result) =
             handle_events(&mut start_recovery, &mut recovery, terminal).unwrap();
         working = !_close;
-    })
+    }
+
+    Ok(())
 }
 
 fn ui(frame: &mut Frame, recovery: &SRecoveryPanel) {
@@ -61,8 +149,12 @@ fn ui(frame: &mut Frame, recovery: &SRecoveryPanel) {
             Constraint::Length(1), // 0 Header
             Constraint::Length(1), // 1 Folder name
             Constraint::Length(1), // 2 Backup folder
-            Constraint::Fill(1),   // 3 Spacer
-            Constraint::Length(1), // 4 Action menu
+            Constraint::Length(1), // 3 Snapshot list
+            Constraint::Length(1), // 4 Restore root
+            Constraint::Length(1), // 5 Restore layout
+            Constraint::Length(1), // 6 Selection
+            Constraint::Fill(1),   // 7 Spacer
+            Constraint::Length(1), // 8 Action menu
         ],
     )
     .split(frame.size());
@@ -81,6 +173,25 @@ fn ui(frame: &mut Frame, recovery: &SRecoveryPanel) {
         .constraints([Constraint::Length(32), Constraint::Fill(1)])
         .split(layout[2]);
 
+    let restore_root_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(32), Constraint::Fill(1)])
+        .split(layout[4]);
+
+    let restore_layout_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Length(32),
+            Constraint::Length(14),
+            Constraint::Length(10),
+        ])
+        .split(layout[5]);
+
+    let selection_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(32), Constraint::Fill(1)])
+        .split(layout[6]);
+
     // Render ==========================
     // Header
     frame.render_widget(
@@ -118,25 +229,192 @@ fn ui(frame: &mut Frame, recovery: &SRecoveryPanel) {
         backup_folder_layout[1],
     );
 
-    frame.render_widget(Block::default().borders(Borders::NONE), layout[3]);
+    frame.render_widget(
+        Paragraph::new("   or pick from destination catalog(L)").white(),
+        layout[3],
+    );
+
+    frame.render_widget(
+        Paragraph::new("3. Restore to(T): ").white(),
+        restore_root_layout[0],
+    );
+
+    let restore_root_text = match &recovery.restore_root {
+        Some(root) => format!("{}  original location(O)", root.display()),
+        None => "Original location".to_string(),
+    };
+    frame.render_widget(
+        Paragraph::new(restore_root_text).gray(),
+        restore_root_layout[1],
+    );
+
+    frame.render_widget(
+        Paragraph::new("   Paths under it: ").white(),
+        restore_layout_layout[0],
+    );
+
+    let mut full_path_btn = Paragraph::new("Full path(P)").gray();
+    let mut flat_btn = Paragraph::new("Flat(P)").gray();
+
+    if recovery.restore_root.is_some() {
+        match recovery.restore_layout {
+            ERestoreLayout::FullPath => full_path_btn = full_path_btn.green(),
+            ERestoreLayout::Flat => flat_btn = flat_btn.green(),
+        };
+    }
+
+    frame.render_widget(full_path_btn, restore_layout_layout[1]);
+    frame.render_widget(flat_btn, restore_layout_layout[2]);
+
+    frame.render_widget(
+        Paragraph::new("4. Choose files(E): ").white(),
+        selection_layout[0],
+    );
+
+    let selection_text = match recovery.selection.len() {
+        0 => "All files".to_string(),
+        1 => "1 ticked".to_string(),
+        count => format!("{} ticked", count),
+    };
+    frame.render_widget(Paragraph::new(selection_text).gray(), selection_layout[1]);
+
+    match &recovery.report {
+        // A move that left files in the backup matters more than attributes
+        Some(report) if !report.left_behind.is_empty() => {
+            let files: Vec<text::Line> = report
+                .left_behind
+                .iter()
+                .map(|file| {
+                    text::Line::from(vec![
+                        text::Span::from(format!("{}  ", file.path.display())).yellow(),
+                        text::Span::from(file.reason.clone()).gray(),
+                    ])
+                })
+                .collect();
+            let title = format!(
+                "Restored, but the backup was kept: {} files stayed in it",
+                report.left_behind.len()
+            );
+            frame.render_widget(
+                Paragraph::new(files).block(Block::default().title(title).borders(Borders::ALL)),
+                layout[7],
+            );
+        }
+        Some(report) if !report.metadata_failures.is_empty() => {
+            let failures: Vec<text::Line> = report
+                .metadata_failures
+                .iter()
+                .map(|failure| {
+                    text::Line::from(vec![
+                        text::Span::from(format!("{}  ", failure.attribute)).yellow(),
+                        text::Span::from(format!("{} ({})", failure.path.display(), failure.error))
+                            .gray(),
+                    ])
+                })
+                .collect();
+            let title = format!(
+                "Restored, but {} attributes could not be set",
+                report.metadata_failures.len()
+            );
+            frame.render_widget(
+                Paragraph::new(failures).block(Block::default().title(title).borders(Borders::ALL)),
+                layout[7],
+            );
+        }
+        _ => frame.render_widget(Block::default().borders(Borders::NONE), layout[7]),
+    }
 
     frame.render_widget(
-        Paragraph::new("START RECOVERY(S)  QUIT(Q)").gray(),
-        layout[4],
+        Paragraph::new("START RECOVERY(S)  DRY RUN(D)  QUIT(Q)").gray(),
+        layout[8],
     );
 
-    if recovery.show_error {
-        ui_error(frame);
+    if let Some(message) = &recovery.error {
+        ui_error(frame, message.clone(), "Close(ESC)".to_string());
+    } else if !recovery.conflicts.is_empty() {
+        ui_error(
+            frame,
+            format!(
+                "There are {} matches between your files and the backup files!",
+                recovery.conflicts.len()
+            ),
+            "Replace files(R) Skip files(S) Keep newer(N) Rename(A) Choose per file(F) Cancel(C)"
+                .to_string(),
+        );
     }
 }
 
 fn handle_events(
-    mut start_recovery: impl FnMut(&SRecoveryPanel),
+    mut start_recovery: impl FnMut(&SRecoveryPanel) -> SJob<SRestoreReport>,
     recovery: &mut SRecoveryPanel,
     terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
 ) -> io::Result<(bool, io::Result<()>)> {
     if event::poll(std::time::Duration::from_millis(16))? {
         if let event::Event::Key(key) = event::read()? {
+            if recovery.error.is_some() {
+                if key.kind == KeyEventKind::Press && key.code == KeyCode::Esc {
+                    recovery.error = None;
+                }
+                return Ok((false, Ok(())));
+            }
+
+            if !recovery.conflicts.is_empty() {
+                if key.kind != KeyEventKind::Press {
+                    return Ok((false, Ok(())));
+                }
+                let policy = match key.code {
+                    KeyCode::Char('r')
+                    | KeyCode::Char('R')
+                    | KeyCode::Char('к')
+                    | KeyCode::Char('К') => Some(EConflictPolicy::Replace),
+                    KeyCode::Char('s')
+                    | KeyCode::Char('S')
+                    | KeyCode::Char('ы')
+                    | KeyCode::Char('Ы') => Some(EConflictPolicy::Skip),
+                    KeyCode::Char('n')
+                    | KeyCode::Char('N')
+                    | KeyCode::Char('т')
+                    | KeyCode::Char('Т') => Some(EConflictPolicy::KeepNewer),
+                    KeyCode::Char('a')
+                    | KeyCode::Char('A')
+                    | KeyCode::Char('ф')
+                    | KeyCode::Char('Ф') => Some(EConflictPolicy::Rename),
+                    _ => None,
+                };
+                if let Some(policy) = policy {
+                    recovery.conflict_policy = policy;
+                    recovery.conflicts.clear();
+                    run_recovery(&mut start_recovery, recovery, terminal)?;
+                    return Ok((false, Ok(())));
+                }
+                match key.code {
+                    KeyCode::Char('f')
+                    | KeyCode::Char('F')
+                    | KeyCode::Char('а')
+                    | KeyCode::Char('А') => {
+                        let conflicts = std::mem::take(&mut recovery.conflicts);
+                        let default = recovery.conflict_policy;
+                        if conflicts::start(
+                            terminal,
+                            &conflicts,
+                            default,
+                            &mut recovery.file_policies,
+                        )? {
+                            run_recovery(&mut start_recovery, recovery, terminal)?;
+                        } else {
+                            recovery.conflicts = conflicts;
+                        }
+                    }
+                    KeyCode::Char('c')
+                    | KeyCode::Char('C')
+                    | KeyCode::Char('с')
+                    | KeyCode::Char('С')
+                    | KeyCode::Esc => recovery.conflicts.clear(),
+                    _ => {}
+                }
+                return Ok((false, Ok(())));
+            }
+
             if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('q')
                 || key.code == KeyCode::Char('Q')
                 || key.code == KeyCode::Char('й')
@@ -150,7 +428,44 @@ fn handle_events(
                 || key.code == KeyCode::Char('ы')
                 || key.code == KeyCode::Char('Ы')
             {
-                start_recovery(&recovery)
+                let backup_folder = recovery.backup_folder.clone();
+                recovery.key =
+                    match passphrase::ask_key(terminal, Path::new(&backup_folder), |f| {
+                        ui(f, recovery)
+                    })? {
+                        EKeyPrompt::NotEncrypted => None,
+                        EKeyPrompt::Key(key) => Some(key),
+                        EKeyPrompt::Cancelled => return Ok((false, Ok(()))),
+                    };
+                // Files in the way are shown first, the user picks what happens to them
+                recovery.file_policies.clear();
+                match backup_service::find_conflicts(recovery) {
+                    Ok(conflicts) if conflicts.is_empty() => {
+                        run_recovery(&mut start_recovery, recovery, terminal)?
+                    }
+                    Ok(conflicts) => recovery.conflicts = conflicts,
+                    Err(e) => recovery.error = Some(e.to_string()),
+                }
+            }
+
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('D')
+                || key.code == KeyCode::Char('d')
+                || key.code == KeyCode::Char('в')
+                || key.code == KeyCode::Char('В')
+            {
+                let backup_folder = recovery.backup_folder.clone();
+                recovery.key =
+                    match passphrase::ask_key(terminal, Path::new(&backup_folder), |f| {
+                        ui(f, recovery)
+                    })? {
+                        EKeyPrompt::NotEncrypted => None,
+                        EKeyPrompt::Key(key) => Some(key),
+                        EKeyPrompt::Cancelled => return Ok((false, Ok(()))),
+                    };
+                match backup_service::recovery_plan(recovery) {
+                    Ok(recovery_plan) => plan::start(terminal, &recovery_plan)?,
+                    Err(e) => recovery.error = Some(e.to_string()),
+                }
             }
 
             if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('c')
@@ -174,11 +489,82 @@ fn handle_events(
                 || key.code == KeyCode::Char('F')
                 || key.code == KeyCode::Char('f')
             {
-                let callback =
-                    |path: String, _element_type: EElementType| recovery.backup_folder = path;
+                // Ticked paths belong to the previous backup
+                let callback = |path: String, _element_type: EElementType| {
+                    recovery.backup_folder = path;
+                    recovery.selection.clear();
+                };
+                // A backup is a folder or an archive file
+                let _ = file_picker::start(terminal, callback, EElementType::Anything);
+            }
+
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('Е')
+                || key.code == KeyCode::Char('е')
+                || key.code == KeyCode::Char('T')
+                || key.code == KeyCode::Char('t')
+            {
+                let callback = |path: String, _element_type: EElementType| {
+                    recovery.restore_root = Some(PathBuf::from(path))
+                };
                 let _ = file_picker::start(terminal, callback, EElementType::Folder);
             }
 
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('У')
+                || key.code == KeyCode::Char('у')
+                || key.code == KeyCode::Char('E')
+                || key.code == KeyCode::Char('e')
+            {
+                let backup_folder = recovery.backup_folder.clone();
+                recovery.key =
+                    match passphrase::ask_key(terminal, Path::new(&backup_folder), |f| {
+                        ui(f, recovery)
+                    })? {
+                        EKeyPrompt::NotEncrypted => None,
+                        EKeyPrompt::Key(key) => Some(key),
+                        EKeyPrompt::Cancelled => return Ok((false, Ok(()))),
+                    };
+                match backup_service::backup_contents(recovery) {
+                    Ok(entries) => {
+                        contents::start(terminal, &backup_folder, entries, &mut recovery.selection)?
+                    }
+                    Err(e) => recovery.error = Some(e.to_string()),
+                }
+            }
+
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('Щ')
+                || key.code == KeyCode::Char('щ')
+                || key.code == KeyCode::Char('O')
+                || key.code == KeyCode::Char('o')
+            {
+                recovery.restore_root = None;
+            }
+
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('З')
+                || key.code == KeyCode::Char('з')
+                || key.code == KeyCode::Char('P')
+                || key.code == KeyCode::Char('p')
+            {
+                recovery.restore_layout = match recovery.restore_layout {
+                    ERestoreLayout::FullPath => ERestoreLayout::Flat,
+                    ERestoreLayout::Flat => ERestoreLayout::FullPath,
+                };
+            }
+
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('Д')
+                || key.code == KeyCode::Char('д')
+                || key.code == KeyCode::Char('L')
+                || key.code == KeyCode::Char('l')
+            {
+                match snapshots::pick_backup_folder(terminal)? {
+                    Ok(Some(folder)) => {
+                        recovery.backup_folder = folder;
+                        recovery.selection.clear();
+                    }
+                    Ok(None) => {}
+                    Err(e) => recovery.error = Some(e.to_string()),
+                }
+            }
+
             if key.kind == KeyEventKind::Press && key.code == KeyCode::Enter {
                 //select_fn(menu.currently_btn.clone());
                 return Ok((true, Ok(())));
@@ -188,12 +574,29 @@ fn handle_events(
     Ok((false, Ok(())))
 }
 
-fn ui_error(frame: &mut Frame) {
+fn run_recovery(
+    mut start_recovery: impl FnMut(&SRecoveryPanel) -> SJob<SRestoreReport>,
+    recovery: &mut SRecoveryPanel,
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+) -> io::Result<()> {
+    let mut view = SProgressView::new("Recovery");
+    let job = start_recovery(recovery);
+    match progress::run_job(terminal, &mut view, job)? {
+        Ok(report) => recovery.report = Some(report),
+        Err(e) => {
+            recovery.report = None;
+            recovery.error = Some(e.to_string());
+        }
+    }
+    Ok(())
+}
+
+fn ui_error(frame: &mut Frame, text1: String, text2: String) {
     let width: u16 = 120;
     let height: u16 = 4;
     let area = Rect {
-        width: width,
-        height: height,
+        width,
+        height,
         x: (frame.size().width / 2) - width / 2,
         y: (frame.size().height / 2) - height / 2,
     };
@@ -213,14 +616,9 @@ fn ui_error(frame: &mut Frame) {
     .margin(1)
     .split(area);
 
+    frame.render_widget(Paragraph::new(text1).gray(), layout[0]);
     frame.render_widget(
-        Paragraph::new("There are matches between your files and the backup files!").gray(),
-        layout[0],
-    );
-    frame.render_widget(
-        Paragraph::new("Replace files(R) Skip files(S) Cancel(C)")
-            .white()
-            .alignment(Alignment::Right),
+        Paragraph::new(text2).white().alignment(Alignment::Right),
         layout[1],
     );
 }
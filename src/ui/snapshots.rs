@@ -0,0 +1,174 @@
+use std::{
+    cell::RefCell,
+    io::{Error, Stdout},
+    path::Path,
+};
+
+use crossterm::event::{self, *};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style, Stylize},
+    widgets::{Block, Borders, Paragraph},
+    *,
+};
+
+use crate::catalog::{ESnapshotStatus, SCatalog, SSnapshotInfo};
+use crate::config::EElementType;
+use crate::error::BackupError;
+use crate::progress::format_size;
+
+use super::file_picker;
+
+/// Asks for a destination folder, then for one of the snapshots in its catalog.
+/// Returns the chosen snapshot's folder, `None` when the user backed out.
+pub fn pick_backup_folder(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+) -> Result<Result<Option<String>, BackupError>, Error> {
+    let mut destination = None;
+    let callback = |path: String, _element_type: EElementType| destination = Some(path);
+    file_picker::start(terminal, callback, EElementType::Folder)?;
+    let Some(destination) = destination else {
+        return Ok(Ok(None));
+    };
+
+    let catalog = match SCatalog::load(Path::new(&destination)) {
+        Ok(catalog) => catalog,
+        Err(e) => return Ok(Err(e)),
+    };
+    let mut folder = None;
+    let callback = |snapshot: &SSnapshotInfo| {
+        folder = Some(
+            Path::new(&destination)
+                .join(&snapshot.name)
+                .to_string_lossy()
+                .to_string(),
+        )
+    };
+    start(terminal, &catalog.snapshots, callback)?;
+    Ok(Ok(folder))
+}
+
+/// Lists the snapshots of a catalog, newest first, and passes the chosen one to `fn_select`
+pub fn start(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    snapshots: &[SSnapshotInfo],
+    mut fn_select: impl FnMut(&SSnapshotInfo),
+) -> Result<(), Error> {
+    let snapshots: Vec<&SSnapshotInfo> = snapshots.iter().rev().collect();
+    let mut current: usize = 0;
+
+    loop {
+        terminal.borrow_mut().draw(|f| ui(f, &snapshots, current))?;
+
+        if let event::Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q')
+                | KeyCode::Char('Q')
+                | KeyCode::Char('й')
+                | KeyCode::Char('Й')
+                | KeyCode::Esc => return Ok(()),
+                KeyCode::Up if current > 0 => current -= 1,
+                KeyCode::Down if current + 1 < snapshots.len() => current += 1,
+                KeyCode::Enter => {
+                    if let Some(snapshot) = snapshots.get(current) {
+                        fn_select(snapshot);
+                    }
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn ui(frame: &mut Frame, snapshots: &[&SSnapshotInfo], current: usize) {
+    // Layouts ==========================
+    let layout = Layout::new(
+        Direction::Vertical,
+        [
+            Constraint::Length(1), // 0 Header
+            Constraint::Length(1), // 1 Columns
+            Constraint::Fill(1),   // 2 Snapshots
+            Constraint::Length(1), // 3 Action menu
+        ],
+    )
+    .split(frame.size());
+
+    // Render ==========================
+    // Header
+    frame.render_widget(
+        Block::new()
+            .title("BackupNF")
+            .borders(Borders::TOP)
+            .border_style(Style::default().green())
+            .title_alignment(Alignment::Center),
+        layout[0],
+    );
+
+    let columns = ["Name", "Time", "Files", "Size", "Status"];
+    let columns_layout = row_layout(&layout[1]);
+    for (i, column) in columns.iter().enumerate() {
+        frame.render_widget(Paragraph::new(*column).white(), columns_layout[i]);
+    }
+
+    if snapshots.is_empty() {
+        frame.render_widget(
+            Paragraph::new("No snapshots in this destination").gray(),
+            layout[2],
+        );
+    }
+
+    let size = layout[2].height as usize;
+    let top = (current + 1).saturating_sub(size);
+    let rows = Layout::new(Direction::Vertical, vec![Constraint::Length(1); size]).split(layout[2]);
+    for (i, snapshot) in snapshots.iter().skip(top).take(size).enumerate() {
+        snapshot_ui(frame, &rows[i], snapshot, top + i == current);
+    }
+
+    frame.render_widget(Paragraph::new("SELECT(ENTER)  BACK(Q)").gray(), layout[3]);
+}
+
+fn row_layout(area: &Rect) -> std::rc::Rc<[Rect]> {
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Fill(1),
+            Constraint::Length(20),
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Length(12),
+        ])
+        .split(*area)
+}
+
+fn snapshot_ui(frame: &mut Frame, area: &Rect, snapshot: &SSnapshotInfo, selected: bool) {
+    let layout = row_layout(area);
+
+    if selected {
+        frame.render_widget(Block::new().borders(Borders::NONE).bg(Color::Gray), *area);
+    }
+
+    let (status_text, status_color) = match snapshot.status {
+        ESnapshotStatus::Complete => ("Complete", Color::Green),
+        ESnapshotStatus::Incomplete => ("Incomplete", Color::Red),
+    };
+
+    let cells = [
+        snapshot.name.clone(),
+        snapshot.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        snapshot.files.to_string(),
+        format_size(snapshot.size),
+    ];
+    for (i, cell) in cells.into_iter().enumerate() {
+        let mut widget = Paragraph::new(cell).gray();
+        if selected {
+            widget = widget.black();
+        }
+        frame.render_widget(widget, layout[i]);
+    }
+    frame.render_widget(Paragraph::new(status_text).fg(status_color), layout[4]);
+}
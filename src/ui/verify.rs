@@ -0,0 +1,234 @@
+use std::{
+    cell::RefCell,
+    io::{Error, Stdout},
+    path::Path,
+};
+
+use crossterm::event::{self, *};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style, Stylize},
+    widgets::{Block, Borders, Paragraph},
+    *,
+};
+
+use crate::backup_service::SVerifyReport;
+use crate::config::EElementType;
+use crate::encryption::EKeySource;
+use crate::job::SJob;
+
+use super::file_picker;
+use super::passphrase::{self, EKeyPrompt};
+use super::progress::{self, SProgressView};
+use super::snapshots;
+
+struct SVerifyPanel {
+    backup_folder: String,
+    report: Option<SVerifyReport>,
+    top: usize,
+    error: Option<String>,
+}
+
+pub fn start(
+    terminal: &RefCell<Terminal<CrosstermBackend<Stdout>>>,
+    mut start_verify: impl FnMut(&str, Option<EKeySource>) -> SJob<SVerifyReport>,
+) -> Result<(), Error> {
+    let mut verify = SVerifyPanel {
+        backup_folder: String::new(),
+        report: None,
+        top: 0,
+        error: None,
+    };
+
+    loop {
+        terminal.borrow_mut().draw(|f| ui(f, &verify))?;
+
+        let event::Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if verify.error.is_some() {
+            if key.code == KeyCode::Esc {
+                verify.error = None;
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Char('й') | KeyCode::Char('Й') => {
+                return Ok(())
+            }
+            KeyCode::Char('f') | KeyCode::Char('F') | KeyCode::Char('а') | KeyCode::Char('А') => {
+                let callback = |path: String, _element_type: EElementType| {
+                    verify.backup_folder = path;
+                    verify.report = None;
+                };
+                file_picker::start(terminal, callback, EElementType::Anything)?;
+            }
+            KeyCode::Char('l') | KeyCode::Char('L') | KeyCode::Char('д') | KeyCode::Char('Д') => {
+                match snapshots::pick_backup_folder(terminal)? {
+                    Ok(Some(folder)) => {
+                        verify.backup_folder = folder;
+                        verify.report = None;
+                    }
+                    Ok(None) => {}
+                    Err(e) => verify.error = Some(e.to_string()),
+                }
+            }
+            KeyCode::Char('s') | KeyCode::Char('S') | KeyCode::Char('ы') | KeyCode::Char('Ы') => {
+                if verify.backup_folder.is_empty() {
+                    verify.error = Some("Не указана папка бэкапа".to_string());
+                    continue;
+                }
+                let backup_folder = Path::new(&verify.backup_folder);
+                let key = match passphrase::ask_key(terminal, backup_folder, |f| ui(f, &verify))? {
+                    EKeyPrompt::NotEncrypted => None,
+                    EKeyPrompt::Key(key) => Some(key),
+                    EKeyPrompt::Cancelled => continue,
+                };
+                let mut view = SProgressView::new("Verify");
+                let job = start_verify(&verify.backup_folder, key);
+                match progress::run_job(terminal, &mut view, job)? {
+                    Ok(report) => verify.report = Some(report),
+                    Err(e) => verify.error = Some(e.to_string()),
+                }
+                verify.top = 0;
+            }
+            KeyCode::Up if verify.top > 0 => verify.top -= 1,
+            KeyCode::Down
+                if verify.report.as_ref().is_some_and(|report| {
+                    verify.top + 1
+                        < report.missing.len() + report.corrupted.len() + report.extra.len()
+                }) =>
+            {
+                verify.top += 1
+            }
+            _ => {}
+        }
+    }
+}
+
+fn ui(frame: &mut Frame, verify: &SVerifyPanel) {
+    // Layouts ==========================
+    let layout = Layout::new(
+        Direction::Vertical,
+        [
+            Constraint::Length(1), // 0 Header
+            Constraint::Length(1), // 1 Backup folder
+            Constraint::Length(1), // 2 Snapshot list
+            Constraint::Length(1), // 3 Summary
+            Constraint::Fill(1),   // 4 Problems
+            Constraint::Length(1), // 5 Action menu
+        ],
+    )
+    .split(frame.size());
+
+    let backup_folder_layout = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(32), Constraint::Fill(1)])
+        .split(layout[1]);
+
+    // Render ==========================
+    // Header
+    frame.render_widget(
+        Block::new()
+            .title("BackupNF")
+            .borders(Borders::TOP)
+            .border_style(Style::default().green())
+            .title_alignment(Alignment::Center),
+        layout[0],
+    );
+
+    frame.render_widget(
+        Paragraph::new("Select backup folder(F): ").white(),
+        backup_folder_layout[0],
+    );
+    frame.render_widget(
+        Paragraph::new(verify.backup_folder.clone()).gray(),
+        backup_folder_layout[1],
+    );
+    frame.render_widget(
+        Paragraph::new("   or pick from destination catalog(L)").white(),
+        layout[2],
+    );
+
+    if let Some(report) = &verify.report {
+        let summary = format!(
+            "Checked {} files: {} missing, {} corrupted, {} extra",
+            report.checked,
+            report.missing.len(),
+            report.corrupted.len(),
+            report.extra.len()
+        );
+        let summary = if report.is_ok() {
+            Paragraph::new(summary).green()
+        } else {
+            Paragraph::new(summary).red()
+        };
+        frame.render_widget(summary, layout[3]);
+
+        let problems: Vec<text::Line> = [
+            ("missing   ", Color::Red, &report.missing),
+            ("corrupted ", Color::Red, &report.corrupted),
+            ("extra     ", Color::Yellow, &report.extra),
+        ]
+        .into_iter()
+        .flat_map(|(kind, color, paths)| {
+            paths.iter().map(move |path| {
+                text::Line::from(vec![
+                    text::Span::from(kind).fg(color),
+                    text::Span::from(path.clone()).gray(),
+                ])
+            })
+        })
+        .skip(verify.top)
+        .collect();
+        frame.render_widget(
+            Paragraph::new(problems)
+                .block(Block::default().title("Problems").borders(Borders::ALL)),
+            layout[4],
+        );
+    }
+
+    frame.render_widget(Paragraph::new("START VERIFY(S)  QUIT(Q)").gray(), layout[5]);
+
+    if let Some(message) = &verify.error {
+        ui_error(frame, message.clone(), "Close(ESC)".to_string());
+    }
+}
+
+fn ui_error(frame: &mut Frame, text1: String, text2: String) {
+    let width: u16 = 120;
+    let height: u16 = 4;
+    let area = Rect {
+        width,
+        height,
+        x: (frame.size().width / 2).saturating_sub(width / 2),
+        y: (frame.size().height / 2).saturating_sub(height / 2),
+    };
+
+    frame.render_widget(
+        Block::new()
+            .title(" ERROR! ")
+            .borders(Borders::all())
+            .border_style(Style::default().red()),
+        area,
+    );
+
+    let layout = Layout::new(
+        Direction::Vertical,
+        [Constraint::Length(1), Constraint::Length(1)],
+    )
+    .margin(1)
+    .split(area);
+
+    frame.render_widget(Paragraph::new(text1).gray(), layout[0]);
+    frame.render_widget(
+        Paragraph::new(text2).white().alignment(Alignment::Right),
+        layout[1],
+    );
+}